@@ -0,0 +1,62 @@
+//! Finding ways for a stack to succeed without any module having checked a credential, like `pam_permit.so` or `pam_succeed_if.so` marked `sufficient` before `pam_unix.so`.
+
+use crate::paths::{module_name, Path, MAX_PATHS};
+use crate::{Facility, ReturnCode, Rule};
+use serde::Serialize;
+
+/// If the rule's module checks a credential, one with a [factor](crate::modules::ModuleInfo::factor) or in `extra`, which are compared without any directory or `.so`.
+pub fn checks_credential(rule: &Rule, extra: &[String]) -> bool {
+    crate::mfa::factor(rule, &[]).is_some()
+        || extra
+            .iter()
+            .any(|extra| module_name(extra) == module_name(&rule.module))
+}
+
+/// A way through the stack that succeeds without a credential being checked.
+#[derive(Clone, Debug, Serialize, Eq, PartialEq)]
+pub struct Bypass {
+    /// The counterexample, what each module that was run returned
+    pub path: Path,
+    /// Credential modules that ran but didn't succeed, by the index of their rule
+    pub failed_checks: Vec<usize>,
+}
+
+#[derive(Clone, Debug, Serialize, Eq, PartialEq)]
+pub struct BypassReport {
+    pub facility: Facility,
+    pub rules: Vec<Rule>,
+    pub bypasses: Vec<Bypass>,
+    /// Every way through the stack was looked at, if not there may be more bypasses
+    pub complete: bool,
+}
+
+/// Every way through the stack that succeeds without one of the credential modules succeeding. `extra` names more modules that count as checking a credential.
+pub fn find_bypasses(facility: &Facility, rules: &[Rule], extra: &[String]) -> BypassReport {
+    let paths = crate::paths::paths(facility, rules, MAX_PATHS);
+    let bypasses = paths
+        .paths
+        .into_iter()
+        .filter(|path| path.trace.result == ReturnCode::Success)
+        .filter(|path| {
+            !path
+                .outcomes
+                .keys()
+                .any(|index| checks_credential(&rules[*index], extra) && path.succeeded(*index))
+        })
+        .map(|path| Bypass {
+            failed_checks: path
+                .outcomes
+                .keys()
+                .copied()
+                .filter(|index| checks_credential(&rules[*index], extra))
+                .collect(),
+            path,
+        })
+        .collect();
+    BypassReport {
+        facility: facility.clone(),
+        rules: rules.to_vec(),
+        bypasses,
+        complete: paths.complete,
+    }
+}
@@ -0,0 +1,144 @@
+//! Every service in a pam.d directory, loaded at once.
+
+use crate::include::load_service_from;
+use crate::{Facility, LoadError, ParseError, Rule, RuleSet, RuleSets};
+use enum_iterator::all;
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+/// Where the service files live, relative to the root of the system.
+pub const PAM_D: &str = "etc/pam.d";
+/// Where distributions that use a vendor directory ship their defaults, used when a file isn't in [PAM_D].
+pub const VENDOR_PAM_D: &str = "usr/lib/pam.d";
+/// The service libpam uses when there isn't a file for the one that was asked for.
+pub const OTHER_SERVICE: &str = "other";
+
+/// A service, with all of its includes and substacks resolved.
+#[derive(Clone, Debug)]
+pub struct Service {
+    pub name: String,
+    pub path: PathBuf,
+    /// Every rule the service runs, in order, each one knowing the file and line it came from
+    pub rules: Vec<Rule>,
+}
+
+impl Service {
+    /// The rules run for `facility`, in order.
+    pub fn stack(&self, facility: &Facility) -> Vec<Rule> {
+        self.rules
+            .iter()
+            .filter(|rule| &rule.facility == facility)
+            .cloned()
+            .collect()
+    }
+
+    /// A [RuleSet] for each facility the service has rules for.
+    pub fn rulesets(&self) -> RuleSets {
+        all::<Facility>()
+            .filter_map(|facility| {
+                let stack = self.stack(&facility);
+                match stack.is_empty() {
+                    true => None,
+                    false => Some((facility.clone(), RuleSet::new(&facility, stack))),
+                }
+            })
+            .collect()
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct ServiceCatalog {
+    /// The directories service files are read from, in order of preference
+    pub dirs: Vec<PathBuf>,
+    pub services: BTreeMap<String, Service>,
+    /// Services that couldn't be loaded at all, like when an include is missing or loops
+    pub failures: BTreeMap<String, LoadError>,
+    pub diagnostics: Vec<ParseError>,
+}
+
+impl ServiceCatalog {
+    /// Load the services from the running system's `/etc/pam.d`.
+    pub fn load_system(results: &[Rule]) -> Result<Self, LoadError> {
+        Self::load_sysroot(Path::new("/"), results)
+    }
+
+    /// Load the services from a system mounted or extracted at `sysroot`, from [PAM_D] with [VENDOR_PAM_D] filling in for anything that's not there.
+    ///
+    /// Absolute include paths are looked up inside the sysroot, not on the host.
+    pub fn load_sysroot(sysroot: &Path, results: &[Rule]) -> Result<Self, LoadError> {
+        let dirs: Vec<PathBuf> = [PAM_D, VENDOR_PAM_D]
+            .into_iter()
+            .map(|dir| sysroot.join(dir))
+            .filter(|dir| dir.is_dir())
+            .collect();
+        if dirs.is_empty() {
+            return Err(LoadError::NoPamDir {
+                sysroot: sysroot.to_path_buf(),
+            });
+        }
+        Self::load(Some(sysroot), dirs, results)
+    }
+
+    /// Load the services from a single pam.d directory.
+    pub fn load_dir(dir: &Path, results: &[Rule]) -> Result<Self, LoadError> {
+        Self::load(None, vec![dir.to_path_buf()], results)
+    }
+
+    fn load(
+        sysroot: Option<&Path>,
+        dirs: Vec<PathBuf>,
+        results: &[Rule],
+    ) -> Result<Self, LoadError> {
+        let mut catalog = Self::default();
+        let search: Vec<&Path> = dirs.iter().map(|dir| dir.as_path()).collect();
+
+        let mut names = BTreeMap::new();
+        // earlier directories take precedence
+        for dir in search.iter().rev() {
+            for entry in std::fs::read_dir(dir).map_err(LoadError::io(dir))? {
+                let entry = entry.map_err(LoadError::io(dir))?;
+                if !entry.path().is_file() {
+                    continue;
+                }
+                if let Some(name) = entry.file_name().to_str() {
+                    names.insert(name.to_string(), entry.path());
+                }
+            }
+        }
+
+        for (name, path) in names {
+            match load_service_from(sysroot, &search, &name, results) {
+                Ok((rules, diagnostics)) => {
+                    catalog.diagnostics.extend(diagnostics);
+                    catalog
+                        .services
+                        .insert(name.clone(), Service { name, path, rules });
+                }
+                Err(err) => {
+                    catalog.failures.insert(name, err);
+                }
+            }
+        }
+        catalog.dirs = dirs;
+        Ok(catalog)
+    }
+
+    /// The service libpam would use for `name`, falling back to [OTHER_SERVICE] if there's no file for it. A service that failed to load doesn't fall back, libpam refuses to run it.
+    pub fn service(&self, name: &str) -> Option<&Service> {
+        // pam_start lowercases the service name before looking for the file
+        let name = name.to_lowercase();
+        if self.failures.contains_key(&name) {
+            return None;
+        }
+        self.services
+            .get(&name)
+            .or_else(|| self.services.get(OTHER_SERVICE))
+    }
+
+    /// The flattened stack for `facility` in the service libpam would use for `name`.
+    pub fn stack(&self, name: &str, facility: &Facility) -> Vec<Rule> {
+        self.service(name)
+            .map(|service| service.stack(facility))
+            .unwrap_or_default()
+    }
+}
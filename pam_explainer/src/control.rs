@@ -0,0 +1,266 @@
+//! The Linux-PAM `[value1=action1 value2=action2 ...]` control syntax, see pam.conf(5)
+
+use enum_iterator::Sequence;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fmt::Display;
+use std::str::FromStr;
+
+/// The return codes a PAM module can hand back, in the same order (and with the same numeric values) as `_pam_types.h`.
+#[derive(
+    Clone, Copy, Debug, Deserialize, Serialize, Eq, PartialEq, Hash, PartialOrd, Ord, Sequence,
+)]
+#[serde(rename_all = "snake_case")]
+pub enum ReturnCode {
+    Success,
+    OpenErr,
+    SymbolErr,
+    ServiceErr,
+    SystemErr,
+    BufErr,
+    PermDenied,
+    AuthErr,
+    CredInsufficient,
+    AuthinfoUnavail,
+    UserUnknown,
+    Maxtries,
+    NewAuthtokReqd,
+    AcctExpired,
+    SessionErr,
+    CredUnavail,
+    CredExpired,
+    CredErr,
+    NoModuleData,
+    ConvErr,
+    AuthtokErr,
+    AuthtokRecoverErr,
+    AuthtokLockBusy,
+    AuthtokDisableAging,
+    TryAgain,
+    Ignore,
+    Abort,
+    AuthtokExpired,
+    ModuleUnknown,
+    BadItem,
+    ConvAgain,
+    Incomplete,
+}
+
+impl ReturnCode {
+    /// The name used for this code on the left hand side of a `value=action` pair
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ReturnCode::Success => "success",
+            ReturnCode::OpenErr => "open_err",
+            ReturnCode::SymbolErr => "symbol_err",
+            ReturnCode::ServiceErr => "service_err",
+            ReturnCode::SystemErr => "system_err",
+            ReturnCode::BufErr => "buf_err",
+            ReturnCode::PermDenied => "perm_denied",
+            ReturnCode::AuthErr => "auth_err",
+            ReturnCode::CredInsufficient => "cred_insufficient",
+            ReturnCode::AuthinfoUnavail => "authinfo_unavail",
+            ReturnCode::UserUnknown => "user_unknown",
+            ReturnCode::Maxtries => "maxtries",
+            ReturnCode::NewAuthtokReqd => "new_authtok_reqd",
+            ReturnCode::AcctExpired => "acct_expired",
+            ReturnCode::SessionErr => "session_err",
+            ReturnCode::CredUnavail => "cred_unavail",
+            ReturnCode::CredExpired => "cred_expired",
+            ReturnCode::CredErr => "cred_err",
+            ReturnCode::NoModuleData => "no_module_data",
+            ReturnCode::ConvErr => "conv_err",
+            ReturnCode::AuthtokErr => "authtok_err",
+            ReturnCode::AuthtokRecoverErr => "authtok_recover_err",
+            ReturnCode::AuthtokLockBusy => "authtok_lock_busy",
+            ReturnCode::AuthtokDisableAging => "authtok_disable_aging",
+            ReturnCode::TryAgain => "try_again",
+            ReturnCode::Ignore => "ignore",
+            ReturnCode::Abort => "abort",
+            ReturnCode::AuthtokExpired => "authtok_expired",
+            ReturnCode::ModuleUnknown => "module_unknown",
+            ReturnCode::BadItem => "bad_item",
+            ReturnCode::ConvAgain => "conv_again",
+            ReturnCode::Incomplete => "incomplete",
+        }
+    }
+}
+
+impl Display for ReturnCode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
+
+impl FromStr for ReturnCode {
+    type Err = ControlError;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        enum_iterator::all::<ReturnCode>()
+            .find(|code| code.as_str() == value)
+            .ok_or_else(|| ControlError::UnknownValue(value.to_string()))
+    }
+}
+
+/// Why a bracketed control couldn't be parsed
+#[derive(Clone, Debug, Deserialize, Serialize, Eq, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum ControlError {
+    NotBracketed,
+    Empty,
+    MissingEquals(String),
+    UnknownValue(String),
+    UnknownAction(String),
+}
+
+impl Display for ControlError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ControlError::NotBracketed => write!(f, "control isn't wrapped in [ ]"),
+            ControlError::Empty => write!(f, "no value=action pairs in control"),
+            ControlError::MissingEquals(pair) => {
+                write!(f, "'{}' should be in the form value=action", pair)
+            }
+            ControlError::UnknownValue(value) => write!(f, "unknown return value '{}'", value),
+            ControlError::UnknownAction(action) => write!(f, "unknown action '{}'", action),
+        }
+    }
+}
+
+impl std::error::Error for ControlError {}
+
+/// What the stack does with a given module return value
+#[derive(Clone, Copy, Debug, Deserialize, Serialize, Eq, PartialEq, Hash)]
+#[serde(rename_all = "lowercase")]
+pub enum Action {
+    /// The return status won't contribute to the final result of the stack.
+    Ignore,
+    /// The stack will fail, and this return status is used as the failure reason if it's the first failure.
+    Bad,
+    /// Equivalent to bad, but terminates the stack immediately.
+    Die,
+    /// The return code contributes to the final result, unless an earlier module has already failed.
+    Ok,
+    /// Equivalent to ok, but terminates the stack immediately.
+    Done,
+    /// Clear all memory of the state of the stack and start again with the next module.
+    Reset,
+    /// Jumps over the next N modules in the stack. pam.conf(5) calls this equivalent to ok, but on a first pass libpam doesn't record the result.
+    Jump(u32),
+}
+
+impl Action {
+    /// A short explanation of what happens to the stack when this action is taken.
+    pub fn explain(&self) -> String {
+        match self {
+            Action::Ignore => "the result doesn't change the outcome of the stack.".to_string(),
+            Action::Bad => "the stack will fail, but the remaining rules still run.".to_string(),
+            Action::Die => "instant failure of this facility!".to_string(),
+            Action::Ok => "the result counts towards the outcome unless something already failed.".to_string(),
+            Action::Done => "the result counts towards the outcome and, unless something already failed, the stack stops here.".to_string(),
+            Action::Reset => "the state of the stack is cleared and evaluation starts again from the next rule.".to_string(),
+            Action::Jump(count) => format!(
+                "the next {} rule(s) are skipped, without recording a result.",
+                count
+            ),
+        }
+    }
+}
+
+impl Display for Action {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Action::Ignore => write!(f, "ignore"),
+            Action::Bad => write!(f, "bad"),
+            Action::Die => write!(f, "die"),
+            Action::Ok => write!(f, "ok"),
+            Action::Done => write!(f, "done"),
+            Action::Reset => write!(f, "reset"),
+            Action::Jump(count) => write!(f, "{}", count),
+        }
+    }
+}
+
+impl FromStr for Action {
+    type Err = ControlError;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "ignore" => Ok(Action::Ignore),
+            "bad" => Ok(Action::Bad),
+            "die" => Ok(Action::Die),
+            "ok" => Ok(Action::Ok),
+            "done" => Ok(Action::Done),
+            "reset" => Ok(Action::Reset),
+            _ => match value.parse::<u32>() {
+                // libpam uses zero as the numeric value of ignore, so a zero-length jump is just that
+                Ok(0) => Ok(Action::Ignore),
+                Ok(count) => Ok(Action::Jump(count)),
+                Err(_) => Err(ControlError::UnknownAction(value.to_string())),
+            },
+        }
+    }
+}
+
+/// A parsed `[value1=action1 value2=action2 ...]` control field.
+#[derive(Clone, Debug, Default, Deserialize, Serialize, Eq, PartialEq)]
+pub struct ControlActions {
+    pub values: BTreeMap<ReturnCode, Action>,
+    /// The action for any value not explicitly listed, set with `default=action`
+    pub default: Option<Action>,
+}
+
+impl ControlActions {
+    /// The action taken when the module returns `code`. Unlisted values with no `default` are treated as `bad`, as libpam does.
+    pub fn action_for(&self, code: ReturnCode) -> Action {
+        self.values
+            .get(&code)
+            .copied()
+            .or(self.default)
+            .unwrap_or(Action::Bad)
+    }
+}
+
+impl Display for ControlActions {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let mut parts: Vec<String> = self
+            .values
+            .iter()
+            .map(|(code, action)| format!("{}={}", code, action))
+            .collect();
+        if let Some(default) = self.default {
+            parts.push(format!("default={}", default));
+        }
+        write!(f, "[{}]", parts.join(" "))
+    }
+}
+
+impl FromStr for ControlActions {
+    type Err = ControlError;
+
+    /// Parses the bracketed form, including the brackets
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let inner = value
+            .trim()
+            .strip_prefix('[')
+            .and_then(|v| v.strip_suffix(']'))
+            .ok_or(ControlError::NotBracketed)?;
+
+        let mut actions = ControlActions::default();
+        for pair in inner.split_whitespace() {
+            let (key, action) = pair
+                .split_once('=')
+                .ok_or_else(|| ControlError::MissingEquals(pair.to_string()))?;
+            let action = Action::from_str(action)?;
+            if key == "default" {
+                actions.default = Some(action);
+            } else {
+                actions.values.insert(ReturnCode::from_str(key)?, action);
+            }
+        }
+        if actions.values.is_empty() && actions.default.is_none() {
+            return Err(ControlError::Empty);
+        }
+        Ok(actions)
+    }
+}
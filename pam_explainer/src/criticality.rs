@@ -0,0 +1,144 @@
+//! Which modules a facility depends on, like whether local logins still work if `pam_sss.so` breaks.
+//!
+//! Worked out from the stack's [Diagram](crate::symbolic::Diagram), so it doesn't need every combination of module results to be run.
+
+use crate::symbolic::Node;
+use crate::{Facility, ReturnCode, Rule, RuleSet};
+use serde::Serialize;
+use std::collections::{BTreeSet, HashMap};
+
+/// How many sufficient sets to find before giving up, they can multiply with each substack or alternative.
+pub const MAX_SUFFICIENT: usize = 256;
+
+#[derive(Clone, Debug, Serialize, Eq, PartialEq)]
+pub struct Criticality {
+    pub facility: Facility,
+    pub rules: Vec<Rule>,
+    /// If there's any way for the facility to succeed
+    pub can_succeed: bool,
+    /// Rules whose module has to succeed for the facility to, if one breaks and fails the way modules in the facility usually do, nothing gets through
+    pub must_succeed: Vec<usize>,
+    /// Rules whose module can return anything without changing the result, though it may change what else is run
+    pub never_matters: Vec<usize>,
+    /// The smallest sets of rules where their modules succeeding is enough for the facility to, whatever the others return
+    pub sufficient: Vec<Vec<usize>>,
+    /// Every sufficient set was found, if not there are more than [MAX_SUFFICIENT]
+    pub complete: bool,
+}
+
+/// Keeps only the sets that don't contain another one, so the `limit` smallest are kept.
+fn minimal(mut sets: Vec<BTreeSet<usize>>, limit: usize) -> (Vec<BTreeSet<usize>>, bool) {
+    sets.sort_by_key(|set| set.len());
+    let mut kept: Vec<BTreeSet<usize>> = Vec::new();
+    for set in sets {
+        if !kept.iter().any(|smaller| smaller.is_subset(&set)) {
+            kept.push(set);
+        }
+    }
+    let complete = kept.len() <= limit;
+    kept.truncate(limit);
+    (kept, complete)
+}
+
+impl RuleSet {
+    /// Which modules the facility depends on, for every combination of what they return. The results already set on the rules are ignored.
+    pub fn criticality(&self) -> Criticality {
+        let mut rules = self.rules.clone();
+        rules.iter_mut().for_each(|rule| rule.final_result = None);
+        let diagram = RuleSet::new(&self.facility, rules.clone())
+            .diagram()
+            .results_only();
+        let reachable = diagram.reachable();
+
+        let decided: BTreeSet<usize> = reachable
+            .iter()
+            .filter_map(|node| match &diagram.nodes[*node] {
+                Node::Decision { rule, .. } => Some(*rule),
+                Node::Result(_) => None,
+            })
+            .collect();
+        let never_matters = diagram
+            .domains
+            .keys()
+            .copied()
+            .filter(|rule| !decided.contains(rule))
+            .collect();
+
+        let required = diagram.required(ReturnCode::Success);
+        // a module breaking fails it the way the facility's modules usually fail, so it has to
+        // succeed if nothing gets through when it does, even if returning something else wouldn't matter
+        let must_succeed = diagram
+            .domains
+            .iter()
+            .filter(|_| required.is_some())
+            .filter_map(|(rule, codes)| {
+                let failure = rules[*rule].facility.failure_code();
+                let actions = rules[*rule].control.actions()?;
+                let code = codes
+                    .iter()
+                    .find(|code| actions.action_for(**code) == actions.action_for(failure))?;
+                (!diagram.reaches_with(ReturnCode::Success, *rule, *code)).then_some(*rule)
+            })
+            .collect();
+
+        // for each node, the smallest sets of modules that get to success from there when
+        // they succeed, whatever the rest return, worked out from the results up
+        let mut complete = true;
+        let mut sufficient: HashMap<usize, Vec<BTreeSet<usize>>> = HashMap::new();
+        for node in reachable {
+            let sets = match &diagram.nodes[node] {
+                Node::Result(ending) => match ending.result == ReturnCode::Success {
+                    true => vec![BTreeSet::new()],
+                    false => Vec::new(),
+                },
+                Node::Decision { rule, branches } => {
+                    // the module succeeds and that's enough from there
+                    let mut sets: Vec<BTreeSet<usize>> = branches
+                        .iter()
+                        .filter(|(code, _)| *code == ReturnCode::Success)
+                        .flat_map(|(_, next)| sufficient[next].iter().cloned())
+                        .map(|mut set| {
+                            set.insert(*rule);
+                            set
+                        })
+                        .collect();
+                    // or whatever it returns, the rest are enough
+                    let mut combined = vec![BTreeSet::new()];
+                    for (_, next) in branches.iter() {
+                        combined = combined
+                            .iter()
+                            .flat_map(|set| {
+                                sufficient[next]
+                                    .iter()
+                                    .map(move |other| set.union(other).copied().collect())
+                            })
+                            .collect();
+                        let found_all;
+                        (combined, found_all) = minimal(combined, MAX_SUFFICIENT);
+                        complete &= found_all;
+                    }
+                    sets.extend(combined);
+                    let (sets, found_all) = minimal(sets, MAX_SUFFICIENT);
+                    complete &= found_all;
+                    sets
+                }
+            };
+            sufficient.insert(node, sets);
+        }
+
+        Criticality {
+            facility: self.facility.clone(),
+            can_succeed: required.is_some(),
+            must_succeed,
+            never_matters,
+            sufficient: sufficient
+                .remove(&diagram.root)
+                .unwrap_or_default()
+                .into_iter()
+                .map(|set| set.into_iter().collect())
+                .collect(),
+            complete,
+            rules,
+        }
+    }
+}
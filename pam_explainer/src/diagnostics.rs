@@ -0,0 +1,314 @@
+//! Problems found while parsing PAM configuration, with enough location information to point at them.
+
+use crate::control::ControlError;
+use crate::modules::{module_info, ArgumentError, ArgumentKind};
+use crate::ReturnCode;
+use serde::{Deserialize, Serialize};
+use std::fmt::Display;
+use std::ops::Range;
+
+const FACILITIES: [&str; 4] = ["auth", "account", "password", "session"];
+const CONTROLS: [&str; 6] = [
+    "required",
+    "requisite",
+    "sufficient",
+    "optional",
+    "include",
+    "substack",
+];
+const ACTIONS: [&str; 6] = ["ignore", "bad", "die", "ok", "done", "reset"];
+
+/// Where in the configuration a problem is.
+#[derive(Clone, Debug, Default, Deserialize, Serialize, Eq, PartialEq)]
+pub struct Span {
+    /// The file the line came from, if there was one
+    pub file: Option<String>,
+    /// 1-based line number, zero if it's not known
+    pub line: usize,
+    /// 0-based byte offsets of the problem within the line
+    pub columns: Range<usize>,
+}
+
+impl Display for Span {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if let Some(file) = &self.file {
+            write!(f, "{}:", file)?;
+        }
+        write!(f, "{}:{}", self.line, self.columns.start + 1)
+    }
+}
+
+/// Something wrong with a line of PAM configuration.
+#[derive(Clone, Debug, Deserialize, Serialize, Eq, PartialEq)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum ParseError {
+    EmptyLine {
+        span: Span,
+    },
+    /// A pam.conf line with a service name and nothing else
+    MissingFacility {
+        span: Span,
+    },
+    MissingControl {
+        span: Span,
+    },
+    MissingModule {
+        span: Span,
+    },
+    /// A `[` without the matching `]`
+    UnterminatedBracket {
+        span: Span,
+    },
+    /// The rule is kept, as a [crate::Facility::Invalid]
+    UnknownFacility {
+        span: Span,
+        value: String,
+    },
+    /// The rule is kept, as a [crate::Control::Invalid]
+    UnknownControl {
+        span: Span,
+        value: String,
+    },
+    /// The rule is kept, as a [crate::Control::Invalid]
+    InvalidControl {
+        span: Span,
+        value: String,
+        reason: ControlError,
+    },
+    MissingIncludeTarget {
+        span: Span,
+    },
+    /// An `@include` in a config that wasn't loaded from a directory, so there's nothing to include
+    UnresolvedInclude {
+        span: Span,
+        target: String,
+    },
+    /// An argument a [well-known module](crate::modules) won't do what's expected with, the rule is kept as it is
+    InvalidArgument {
+        span: Span,
+        module: String,
+        argument: String,
+        reason: Box<ArgumentError>,
+    },
+}
+
+impl ParseError {
+    pub fn span(&self) -> &Span {
+        match self {
+            ParseError::EmptyLine { span }
+            | ParseError::MissingFacility { span }
+            | ParseError::MissingControl { span }
+            | ParseError::MissingModule { span }
+            | ParseError::UnterminatedBracket { span }
+            | ParseError::UnknownFacility { span, .. }
+            | ParseError::UnknownControl { span, .. }
+            | ParseError::InvalidControl { span, .. }
+            | ParseError::MissingIncludeTarget { span }
+            | ParseError::UnresolvedInclude { span, .. }
+            | ParseError::InvalidArgument { span, .. } => span,
+        }
+    }
+
+    fn span_mut(&mut self) -> &mut Span {
+        match self {
+            ParseError::EmptyLine { span }
+            | ParseError::MissingFacility { span }
+            | ParseError::MissingControl { span }
+            | ParseError::MissingModule { span }
+            | ParseError::UnterminatedBracket { span }
+            | ParseError::UnknownFacility { span, .. }
+            | ParseError::UnknownControl { span, .. }
+            | ParseError::InvalidControl { span, .. }
+            | ParseError::MissingIncludeTarget { span }
+            | ParseError::UnresolvedInclude { span, .. }
+            | ParseError::InvalidArgument { span, .. } => span,
+        }
+    }
+
+    /// Set which file and line the error came from, [crate::Rule::parse] only knows about the columns.
+    pub fn at(mut self, file: Option<&str>, line: usize) -> Self {
+        let span = self.span_mut();
+        span.file = file.map(|file| file.to_string());
+        span.line = line;
+        self
+    }
+
+    /// Like [ParseError::at], for errors from a rule that may have been continued over several lines with `\\`. `text` is the whole rule and `line` the line it started on, the span is moved to the line the problem is actually on.
+    pub fn within(mut self, text: &str, file: Option<&str>, line: usize) -> Self {
+        let span = self.span_mut();
+        let start = span.columns.start.min(text.len());
+        let line_start = text[..start].rfind('\n').map_or(0, |index| index + 1);
+        let line_end = text[start..]
+            .find(['\r', '\n'])
+            .map_or(text.len(), |index| start + index);
+        span.columns = start - line_start..span.columns.end.min(line_end).max(start) - line_start;
+        let line = line + text[..start].matches('\n').count();
+        self.at(file, line)
+    }
+
+    pub fn message(&self) -> String {
+        match self {
+            ParseError::EmptyLine { .. } => "Empty line".to_string(),
+            ParseError::MissingFacility { .. } => {
+                "Not enough parts to the rule, failed to get facility".to_string()
+            }
+            ParseError::MissingControl { .. } => {
+                "Not enough parts to the rule, failed to get control".to_string()
+            }
+            ParseError::MissingModule { .. } => {
+                "Not enough parts to the rule, failed to get module".to_string()
+            }
+            ParseError::UnterminatedBracket { .. } => "'[' is never closed".to_string(),
+            ParseError::UnknownFacility { value, .. } => format!("Unknown facility '{}'", value),
+            ParseError::UnknownControl { value, .. } => format!("Unknown control '{}'", value),
+            ParseError::InvalidControl { value, reason, .. } => {
+                format!("Invalid control '{}': {}", value, reason)
+            }
+            ParseError::MissingIncludeTarget { .. } => {
+                "@include doesn't say which file to include".to_string()
+            }
+            ParseError::UnresolvedInclude { target, .. } => {
+                format!("Can't resolve '@include {}' without a directory", target)
+            }
+            ParseError::InvalidArgument {
+                module,
+                argument,
+                reason,
+                ..
+            } => format!("Argument '{}' to {}: {}", argument, module, reason),
+        }
+    }
+
+    /// A hint at how to fix the problem, if there's something useful to say.
+    pub fn suggestion(&self) -> Option<String> {
+        match self {
+            ParseError::EmptyLine { .. } => None,
+            ParseError::MissingControl { .. } | ParseError::MissingModule { .. } => Some(
+                "rules need a facility, control and module, eg 'auth required pam_unix.so'"
+                    .to_string(),
+            ),
+            ParseError::MissingFacility { .. } => Some(
+                "pam.conf rules need a service, facility, control and module, eg 'login auth required pam_unix.so'"
+                    .to_string(),
+            ),
+            ParseError::UnterminatedBracket { .. } => Some("add the closing ']'".to_string()),
+            ParseError::UnknownFacility { value, .. } => Some(
+                match closest(value.trim_start_matches('-'), FACILITIES.into_iter()) {
+                    Some(facility) => format!("did you mean '{}'?", facility),
+                    None => "the facility should be one of auth, account, password or session"
+                        .to_string(),
+                },
+            ),
+            ParseError::UnknownControl { value, .. } => Some(match closest(value, CONTROLS.into_iter()) {
+                Some(control) => format!("did you mean '{}'?", control),
+                None => "the control should be required, requisite, sufficient, optional, include, substack or [value=action ...]".to_string(),
+            }),
+            ParseError::InvalidControl { reason, .. } => match reason {
+                ControlError::NotBracketed | ControlError::Empty => Some(
+                    "use value=action pairs inside the brackets, eg [success=ok default=bad]"
+                        .to_string(),
+                ),
+                ControlError::MissingEquals(pair) => {
+                    Some(format!("'{}' needs an action, eg {}=ok", pair, pair))
+                }
+                ControlError::UnknownValue(value) => closest(
+                    value,
+                    enum_iterator::all::<ReturnCode>()
+                        .map(|code| code.as_str())
+                        .chain(["default"]),
+                )
+                .map(|value| format!("did you mean '{}'?", value)),
+                ControlError::UnknownAction(action) => Some(match closest(action, ACTIONS.into_iter()) {
+                    Some(action) => format!("did you mean '{}'?", action),
+                    None => format!(
+                        "the action should be one of {} or a number of rules to jump over",
+                        ACTIONS.join(", ")
+                    ),
+                }),
+            },
+            ParseError::MissingIncludeTarget { .. } => {
+                Some("name the file to include, eg '@include common-auth'".to_string())
+            }
+            ParseError::UnresolvedInclude { .. } => {
+                Some("load the service from its pam.d directory so includes can be followed".to_string())
+            }
+            ParseError::InvalidArgument {
+                module,
+                argument,
+                reason,
+                ..
+            } => {
+                let name = argument.split_once('=').map_or(argument.as_str(), |(name, _)| name);
+                match reason.as_ref() {
+                    ArgumentError::Unknown => closest(
+                        name,
+                        module_info(module)
+                            .into_iter()
+                            .flat_map(|info| info.arguments.iter())
+                            .map(|known| known.name.as_str()),
+                    )
+                    .map(|name| format!("did you mean '{}'?", name)),
+                    ArgumentError::MissingValue(kind) => Some(format!(
+                        "give it {}, eg {}=...",
+                        match kind {
+                            ArgumentKind::Integer => "a number",
+                            ArgumentKind::Path => "a path",
+                            ArgumentKind::Flag | ArgumentKind::String => "a value",
+                        },
+                        name
+                    )),
+                    ArgumentError::NotAnInteger(_) => {
+                        let words = module_info(module)
+                            .and_then(|info| info.argument(name))
+                            .map(|known| known.values.clone())
+                            .unwrap_or_default();
+                        Some(match words.is_empty() {
+                            true => "use a whole number".to_string(),
+                            false => format!("use a whole number or {}", words.join(", ")),
+                        })
+                    }
+                    ArgumentError::UnexpectedValue => Some(format!("use '{}' on its own", name)),
+                    ArgumentError::Conflicts(_) => Some("remove one of them".to_string()),
+                    ArgumentError::Deprecated(_) => None,
+                }
+            }
+        }
+    }
+}
+
+impl Display for ParseError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}: {}", self.span(), self.message())?;
+        if let Some(suggestion) = self.suggestion() {
+            write!(f, " ({})", suggestion)?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+/// The candidate within a couple of typos of `value`, if there's one.
+fn closest<'a>(value: &str, candidates: impl Iterator<Item = &'a str>) -> Option<&'a str> {
+    let value = value.to_lowercase();
+    candidates
+        .map(|candidate| (edit_distance(&value, candidate), candidate))
+        .filter(|(distance, candidate)| *distance <= 2.min(candidate.len() / 3 + 1))
+        .min_by_key(|(distance, _)| *distance)
+        .map(|(_, candidate)| candidate)
+}
+
+/// Levenshtein distance between two strings
+fn edit_distance(left: &str, right: &str) -> usize {
+    let right: Vec<char> = right.chars().collect();
+    let mut previous: Vec<usize> = (0..=right.len()).collect();
+    for (i, left_char) in left.chars().enumerate() {
+        let mut current = vec![i + 1];
+        for (j, right_char) in right.iter().enumerate() {
+            let substitution = previous[j] + usize::from(left_char != *right_char);
+            current.push(substitution.min(previous[j + 1] + 1).min(current[j] + 1));
+        }
+        previous = current;
+    }
+    previous[right.len()]
+}
@@ -0,0 +1,81 @@
+//! What changed in how a stack behaves, rather than in how it's written.
+//!
+//! Rules are matched up by the module they run, see [ModuleId], so moving a rule or changing its control shows up as the combinations of module results that now end differently, and rewording one that doesn't change anything doesn't show up at all.
+
+use crate::paths::MAX_PATHS;
+use crate::symbolic::{differences, Ending, ModuleId};
+use crate::truth_table::collapse;
+use crate::{Facility, ReturnCode, Rule, RuleSet};
+use serde::Serialize;
+
+/// A set of module results the old and new stacks end differently for.
+#[derive(Clone, Debug, Serialize, Eq, PartialEq)]
+pub struct Change {
+    /// What each of [StackDiff::modules] returns, `None` if it doesn't matter
+    pub outcomes: Vec<Option<ReturnCode>>,
+    pub old: Ending,
+    pub new: Ending,
+}
+
+impl Change {
+    /// If the facility hands back something different, rather than only running a different number of rules to get there.
+    pub fn result_changed(&self) -> bool {
+        self.old.result != self.new.result
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Eq, PartialEq)]
+pub struct StackDiff {
+    pub facility: Facility,
+    /// The modules in either stack, the old stack's then any that are new
+    pub modules: Vec<ModuleId>,
+    pub changes: Vec<Change>,
+    /// Every change was found, if not there are more than [MAX_PATHS]
+    pub complete: bool,
+}
+
+/// How the `new` stack behaves differently to the `old` one, for every combination of what the modules return. The results already set on the rules are ignored.
+pub fn diff_stacks(facility: &Facility, old: &[Rule], new: &[Rule]) -> StackDiff {
+    let clear = |rules: &[Rule]| {
+        let mut rules = rules.to_vec();
+        rules.iter_mut().for_each(|rule| rule.final_result = None);
+        RuleSet::new(facility, rules)
+    };
+    let found = differences(&clear(old), &clear(new), true, MAX_PATHS);
+    let rows = found
+        .differences
+        .into_iter()
+        .map(|difference| {
+            let outcomes = found
+                .modules
+                .iter()
+                .map(|(module, _)| {
+                    difference
+                        .outcomes
+                        .iter()
+                        .find(|(id, _)| id == module)
+                        .map(|(_, code)| *code)
+                })
+                .collect();
+            (outcomes, (difference.left, difference.right))
+        })
+        .collect();
+    let classes: Vec<Vec<ReturnCode>> = found
+        .modules
+        .iter()
+        .map(|(_, codes)| codes.clone())
+        .collect();
+    StackDiff {
+        facility: facility.clone(),
+        modules: found
+            .modules
+            .into_iter()
+            .map(|(module, _)| module)
+            .collect(),
+        changes: collapse(rows, &classes)
+            .into_iter()
+            .map(|(outcomes, (old, new))| Change { outcomes, old, new })
+            .collect(),
+        complete: found.complete,
+    }
+}
@@ -0,0 +1,84 @@
+//! Things that stop configuration or results being loaded at all, as opposed to the [crate::ParseError]s found in the lines of a config.
+
+use std::fmt::Display;
+use std::path::PathBuf;
+
+#[derive(Debug)]
+pub enum LoadError {
+    /// Reading a file or stream failed
+    Io {
+        path: Option<PathBuf>,
+        source: std::io::Error,
+    },
+    /// A results or scenario file wasn't valid
+    Results {
+        path: Option<PathBuf>,
+        source: serde_json::Error,
+    },
+    /// The files that were being included when it looped back on itself
+    IncludeLoop {
+        chain: Vec<String>,
+    },
+    /// Includes and substacks nested deeper than libpam allows
+    IncludeTooDeep {
+        chain: Vec<String>,
+    },
+    IncludeNotFound {
+        target: String,
+    },
+    /// There's no pam.d directory in the sysroot
+    NoPamDir {
+        sysroot: PathBuf,
+    },
+}
+
+impl LoadError {
+    pub(crate) fn io(path: impl Into<PathBuf>) -> impl FnOnce(std::io::Error) -> Self {
+        let path = path.into();
+        move |source| LoadError::Io {
+            path: Some(path),
+            source,
+        }
+    }
+}
+
+impl Display for LoadError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            LoadError::Io { path, source } => match path {
+                Some(path) => write!(f, "Failed to read {}: {}", path.display(), source),
+                None => write!(f, "Failed to read input: {}", source),
+            },
+            LoadError::Results { path, source } => match path {
+                Some(path) => write!(f, "Failed to parse results {}: {}", path.display(), source),
+                None => write!(f, "Failed to parse results: {}", source),
+            },
+            LoadError::IncludeLoop { chain } => write!(f, "Include loop: {}", chain.join(" -> ")),
+            LoadError::IncludeTooDeep { chain } => {
+                write!(f, "Includes nested too deeply: {}", chain.join(" -> "))
+            }
+            LoadError::IncludeNotFound { target } => {
+                write!(f, "Couldn't find '{}' to include", target)
+            }
+            LoadError::NoPamDir { sysroot } => {
+                write!(f, "No pam.d directory under {}", sysroot.display())
+            }
+        }
+    }
+}
+
+impl std::error::Error for LoadError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            LoadError::Io { source, .. } => Some(source),
+            LoadError::Results { source, .. } => Some(source),
+            _ => None,
+        }
+    }
+}
+
+impl From<std::io::Error> for LoadError {
+    fn from(source: std::io::Error) -> Self {
+        LoadError::Io { path: None, source }
+    }
+}
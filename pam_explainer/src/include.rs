@@ -0,0 +1,194 @@
+//! Resolving `include`, `substack` and Debian's `@include` directives against a directory of service files.
+
+use crate::syntax::{Allowances, EntryKind, SyntaxFile, TokenKind};
+use crate::{parse_line, Control, Facility, LoadError, ParseError, Rule, Span};
+use log::info;
+use std::path::Path;
+
+/// How deeply includes and substacks can be nested, libpam's `PAM_SUBSTACK_MAX_LEVEL`.
+pub const MAX_INCLUDE_DEPTH: usize = 16;
+
+/// Load the service file `service` from `dir`, with all of its includes resolved.
+pub fn load_service(
+    dir: &Path,
+    service: &str,
+    results: &[Rule],
+) -> Result<(Vec<Rule>, Vec<ParseError>), LoadError> {
+    load_service_from(None, &[dir], service, results)
+}
+
+/// Like [load_service], looking through `dirs` in order for the service file and anything it includes. Absolute include paths are taken relative to `sysroot` if there is one.
+pub(crate) fn load_service_from(
+    sysroot: Option<&Path>,
+    dirs: &[&Path],
+    service: &str,
+    results: &[Rule],
+) -> Result<(Vec<Rule>, Vec<ParseError>), LoadError> {
+    let mut resolver = Resolver::new(dirs, results);
+    resolver.sysroot = sysroot;
+    resolver.expand_file(service, None, 0)?;
+    Ok(resolver.finish())
+}
+
+/// Parse a service file, pulling in anything it includes from `dir`. `file` is used to label the diagnostics.
+///
+/// Included rules are inlined in place of the directive, substacks keep their `substack` rule as a header and the rules from the substack follow it with a deeper [Rule::stack_level].
+pub fn resolve_includes(
+    text: &str,
+    file: Option<&str>,
+    dir: &Path,
+    results: &[Rule],
+) -> Result<(Vec<Rule>, Vec<ParseError>), LoadError> {
+    let dirs = [dir];
+    let mut resolver = Resolver::new(&dirs, results);
+    resolver.expand(file, &SyntaxFile::parse(text), None, 0)?;
+    Ok(resolver.finish())
+}
+
+struct Resolver<'a> {
+    dirs: &'a [&'a Path],
+    sysroot: Option<&'a Path>,
+    results: &'a [Rule],
+    /// The files currently being expanded, to catch include loops
+    chain: Vec<String>,
+    rules: Vec<Rule>,
+    diagnostics: Vec<ParseError>,
+}
+
+impl<'a> Resolver<'a> {
+    fn new(dirs: &'a [&'a Path], results: &'a [Rule]) -> Self {
+        Self {
+            dirs,
+            sysroot: None,
+            results,
+            chain: Vec::new(),
+            rules: Vec::new(),
+            diagnostics: Vec::new(),
+        }
+    }
+
+    /// Number the flattened rules in the order they'll be run.
+    fn finish(mut self) -> (Vec<Rule>, Vec<ParseError>) {
+        for (order, rule) in self.rules.iter_mut().enumerate() {
+            rule.rule_order = Some(order as u32);
+        }
+        (self.rules, self.diagnostics)
+    }
+
+    fn expand(
+        &mut self,
+        file: Option<&str>,
+        syntax: &SyntaxFile,
+        facility: Option<&Facility>,
+        level: usize,
+    ) -> Result<(), LoadError> {
+        let mut allowances = Allowances::default();
+        for entry in syntax.entries.iter() {
+            let allow = allowances.next(entry);
+            if entry.kind == EntryKind::Include {
+                // Debian's @include pulls in the whole file, every facility included
+                match entry.field(TokenKind::IncludeTarget) {
+                    Some(target) => self.expand_file(&target.text, facility, level)?,
+                    None => {
+                        let text = entry.text();
+                        let start = text.len() - text.trim_start().len();
+                        self.diagnostics.push(
+                            ParseError::MissingIncludeTarget {
+                                span: Span {
+                                    columns: start..text.trim_end().len(),
+                                    ..Default::default()
+                                },
+                            }
+                            .at(file, entry.line),
+                        )
+                    }
+                }
+                continue;
+            }
+
+            let Some(mut rule) = parse_line(
+                &entry.text(),
+                file,
+                entry.line,
+                0,
+                self.results,
+                allow,
+                &mut self.diagnostics,
+            ) else {
+                continue;
+            };
+            if facility.is_some_and(|facility| facility != &rule.facility) {
+                continue;
+            }
+            rule.stack_level = level;
+
+            match rule.control {
+                Control::Include => {
+                    let target = rule.module.clone();
+                    self.expand_file(&target, Some(&rule.facility), level)?;
+                }
+                Control::Substack => {
+                    let target = rule.module.clone();
+                    let facility = rule.facility.clone();
+                    self.rules.push(rule);
+                    self.expand_file(&target, Some(&facility), level + 1)?;
+                }
+                _ => self.rules.push(rule),
+            }
+        }
+        Ok(())
+    }
+
+    /// The include chain so far, ending with `target`.
+    fn chain_to(&self, target: &str) -> Vec<String> {
+        let mut chain = self.chain.clone();
+        chain.push(target.to_string());
+        chain
+    }
+
+    fn expand_file(
+        &mut self,
+        target: &str,
+        facility: Option<&Facility>,
+        level: usize,
+    ) -> Result<(), LoadError> {
+        if self.chain.iter().any(|name| name == target) {
+            return Err(LoadError::IncludeLoop {
+                chain: self.chain_to(target),
+            });
+        }
+        if self.chain.len() > MAX_INCLUDE_DEPTH || level > MAX_INCLUDE_DEPTH {
+            return Err(LoadError::IncludeTooDeep {
+                chain: self.chain_to(target),
+            });
+        }
+
+        // the first directory that has it wins, the same as libpam with /etc/pam.d and the vendor directory
+        let absolute = Path::new(target)
+            .strip_prefix("/")
+            .ok()
+            .map(|target| match self.sysroot {
+                Some(sysroot) => sysroot.join(target),
+                None => Path::new("/").join(target),
+            });
+        let path = match absolute {
+            Some(path) => Some(path),
+            None => self
+                .dirs
+                .iter()
+                .map(|dir| dir.join(target))
+                .find(|path| path.is_file()),
+        }
+        .filter(|path| path.is_file())
+        .ok_or_else(|| LoadError::IncludeNotFound {
+            target: target.to_string(),
+        })?;
+        info!("Loading file: {}", path.display());
+        let syntax =
+            SyntaxFile::parse(&std::fs::read_to_string(&path).map_err(LoadError::io(&path))?);
+        self.chain.push(target.to_string());
+        let res = self.expand(Some(&path.display().to_string()), &syntax, facility, level);
+        self.chain.pop();
+        res
+    }
+}
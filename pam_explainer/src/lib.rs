@@ -1,13 +1,40 @@
 //! based on <https://www.linux.com/news/understanding-pam/> which is probably wrong in places
 
-// use dialoguer::Confirm;
 use enum_iterator::Sequence;
 use log::{debug, error, info, warn};
 use serde::{Deserialize, Serialize, Serializer};
-use std::collections::HashMap;
-use std::env;
+use std::collections::{BTreeMap, HashMap};
 use std::fmt::Display;
-use std::io::Error;
+use std::io::Read;
+use std::ops::Range;
+use std::path::Path;
+use std::str::FromStr;
+
+pub mod bypass;
+pub mod catalog;
+pub mod control;
+pub mod criticality;
+pub mod diagnostics;
+pub mod diff;
+pub mod error;
+pub mod include;
+pub mod lint;
+pub mod mfa;
+pub mod modules;
+pub mod outage;
+pub mod outcome;
+pub mod pamconf;
+pub mod paths;
+pub mod symbolic;
+pub mod syntax;
+pub mod trace;
+pub mod truth_table;
+
+pub use control::{Action, ControlActions, ReturnCode};
+pub use diagnostics::{ParseError, Span};
+pub use error::LoadError;
+pub use outcome::{NoOutcome, OutcomeProvider, Scenario};
+pub use trace::{Outcome, SkipReason, StackState, Step, Trace};
 
 #[derive(Clone, Debug, Deserialize, Serialize, Eq, PartialEq, Hash)]
 #[serde(rename_all = "lowercase")]
@@ -61,15 +88,16 @@ impl Sequence for Facility {
     }
 
     fn previous(&self) -> Option<Self> {
-        todo!()
+        let val: usize = self.clone().into();
+        val.checked_sub(1).map(Self::from)
     }
 
     fn first() -> Option<Self> {
-        todo!()
+        Some(0.into())
     }
 
     fn last() -> Option<Self> {
-        todo!()
+        Some((Self::CARDINALITY - 1).into())
     }
 }
 
@@ -85,6 +113,19 @@ impl Ord for Facility {
     }
 }
 
+impl Facility {
+    /// The code a module in this facility typically returns when it fails, used when all we know is that a rule didn't succeed.
+    pub fn failure_code(&self) -> ReturnCode {
+        match self {
+            Facility::Auth => ReturnCode::AuthErr,
+            Facility::Account => ReturnCode::PermDenied,
+            Facility::Password => ReturnCode::AuthtokErr,
+            Facility::Session => ReturnCode::SessionErr,
+            Facility::Invalid(_) => ReturnCode::PermDenied,
+        }
+    }
+}
+
 impl Display for Facility {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
@@ -112,7 +153,7 @@ impl From<&str> for Facility {
 #[derive(Clone, Debug, Deserialize, Serialize, Eq, PartialEq)]
 #[serde(rename_all = "lowercase")]
 #[allow(dead_code)]
-/// The Control defines how the success or failure of a given module will affect the overall success or failure of the operation. There are four possible values for this field: ‘required’, ‘requisite’, ‘sufficient’, and ‘optional’.
+/// The Control defines how the success or failure of a given module will affect the overall success or failure of the operation. There are four historical keywords for this field: ‘required’, ‘requisite’, ‘sufficient’, and ‘optional’, and Linux-PAM also allows a bracketed list of `value=action` pairs.
 pub enum Control {
     /// If a ‘required’ module returns a status that is not ‘success’, the operation will ultimately fail, but only after the modules below it are invoked. This serves the purpose of always acting the same way from the point of view of the user trying to utilize the service. The net effect is that it becomes harder for a potential attacker to determine which module caused the failure – the less information a malicious user has about your system, the better. Important to note is that even if all of the modules in the stack succeed, failure of one ‘required’ module means the operation will ultimately fail. Of course, if a required module succeeds, the operation can still fail if a ‘required’ module later in the stack fails.
     Required,
@@ -122,9 +163,44 @@ pub enum Control {
     Sufficient,
     /// An ‘optional’ module, according to the pam(8) manpage, will only cause an operation to fail if it’s the only module in the stack for that facility.
     Optional,
+    /// Include all lines of the same facility from the configuration file named in the module field.
+    Include,
+    /// Like include, but `done` and `die` only end the substack, jumps can't leave it, and a `reset` goes back to the state the substack started with. A jump in the parent stack skips the whole substack as one module.
+    Substack,
+    /// The `[value1=action1 value2=action2 ...]` form, which says exactly what to do for each module return value, eg `[success=1 default=ignore]`.
+    Complex(ControlActions),
     Invalid(String),
 }
 
+impl Control {
+    /// The actions libpam uses for this control, the keywords are shorthand for these tables from `_pam_parse_conf_file`.
+    ///
+    /// `include` and `substack` don't have any, and neither does a control libpam couldn't parse, which treats every result as `bad`.
+    pub fn actions(&self) -> Option<ControlActions> {
+        let keyword = |success: Action, ignore: Option<Action>, default: Action| {
+            let mut values = BTreeMap::from([
+                (ReturnCode::Success, success),
+                (ReturnCode::NewAuthtokReqd, success),
+            ]);
+            if let Some(ignore) = ignore {
+                values.insert(ReturnCode::Ignore, ignore);
+            }
+            ControlActions {
+                values,
+                default: Some(default),
+            }
+        };
+        match self {
+            Control::Required => Some(keyword(Action::Ok, Some(Action::Ignore), Action::Bad)),
+            Control::Requisite => Some(keyword(Action::Ok, Some(Action::Ignore), Action::Die)),
+            Control::Optional => Some(keyword(Action::Ok, None, Action::Ignore)),
+            Control::Sufficient => Some(keyword(Action::Done, None, Action::Ignore)),
+            Control::Complex(actions) => Some(actions.clone()),
+            Control::Include | Control::Substack | Control::Invalid(_) => None,
+        }
+    }
+}
+
 impl From<&str> for Control {
     fn from(value: &str) -> Self {
         match value {
@@ -132,6 +208,15 @@ impl From<&str> for Control {
             "requisite" => Self::Requisite,
             "sufficient" => Self::Sufficient,
             "optional" => Self::Optional,
+            "include" => Self::Include,
+            "substack" => Self::Substack,
+            _ if value.starts_with('[') => match ControlActions::from_str(value) {
+                Ok(actions) => Self::Complex(actions),
+                Err(err) => {
+                    debug!("Failed to parse control {}: {}", value, err);
+                    Self::Invalid(value.to_string())
+                }
+            },
             _ => Self::Invalid(value.to_string()),
         }
     }
@@ -144,6 +229,9 @@ impl Display for Control {
             Control::Requisite => write!(f, "requisite"),
             Control::Sufficient => write!(f, "sufficient"),
             Control::Optional => write!(f, "optional"),
+            Control::Include => write!(f, "include"),
+            Control::Substack => write!(f, "substack"),
+            Control::Complex(actions) => write!(f, "{}", actions),
             Control::Invalid(value) => write!(f, "invalid: {}", value),
         }
     }
@@ -153,7 +241,16 @@ fn serialize_rules<S>(input: &[String], serializer: S) -> Result<S::Ok, S::Error
 where
     S: Serializer,
 {
-    serializer.serialize_str(&input.join(" "))
+    serializer.serialize_str(&join_arguments(input))
+}
+
+/// The arguments as they'd be written in the config file
+pub fn join_arguments(arguments: &[String]) -> String {
+    arguments
+        .iter()
+        .map(|argument| quote_argument(argument))
+        .collect::<Vec<String>>()
+        .join(" ")
 }
 
 #[derive(Clone, Debug, Deserialize, Serialize, Eq)]
@@ -164,9 +261,21 @@ pub struct Rule {
     pub module: String,
     #[serde(default = "Vec::new", serialize_with = "serialize_rules")]
     pub arguments: Vec<String>,
-    pub final_result: Option<FinalResult>,
+    pub final_result: Option<ReturnCode>,
     pub rule_order: Option<u32>,
     pub rulehash: Option<String>,
+    /// How many substacks deep this rule is, zero for the top level stack
+    #[serde(default)]
+    pub stack_level: usize,
+    /// The facility was prefixed with a `-`, so if the module isn't installed the rule is silently skipped
+    #[serde(default)]
+    pub silent_if_missing: bool,
+    /// Where the rule was read from, when it came from a file
+    #[serde(default)]
+    pub source: Span,
+    /// The lints allowed for this rule by `# pam-explainer: allow(ID)` comments, see [lint]
+    #[serde(default)]
+    pub allow: Vec<String>,
 }
 
 impl PartialEq for Rule {
@@ -178,6 +287,10 @@ impl PartialEq for Rule {
             && self.final_result == other.final_result
             && self.rule_order == other.rule_order
             && self.rulehash == other.rulehash
+            && self.stack_level == other.stack_level
+            && self.silent_if_missing == other.silent_if_missing
+            && self.source == other.source
+            && self.allow == other.allow
     }
 }
 
@@ -185,97 +298,230 @@ impl Rule {
     // hash of the configuration, not including the final result
     pub fn hash(&self) -> String {
         let mut hash_string = String::new();
+        if self.silent_if_missing {
+            hash_string.push('-');
+        }
         hash_string.push_str(&self.facility.to_string());
         hash_string.push_str(&self.control.to_string());
         hash_string.push_str(&self.module.to_string());
-        hash_string.push_str(&self.arguments.join(" ").to_string());
+        hash_string.push_str(&join_arguments(&self.arguments));
 
         sha256::digest(hash_string)
     }
 
-    pub fn new(value: &str, rule_order: &u32, results: &[Rule]) -> Result<Self, Error> {
+    pub fn new(value: &str, rule_order: &u32, results: &[Rule]) -> Result<Self, ParseError> {
+        Self::parse(value, rule_order, results).map(|(rule, _)| rule)
+    }
+
+    /// Parse a line into a rule, along with any problems that didn't stop it being parsed (an unknown facility or control is kept as an `Invalid` variant).
+    ///
+    /// The spans in the errors only have the columns set, use [ParseError::at] to say where the line came from.
+    pub fn parse(
+        value: &str,
+        rule_order: &u32,
+        results: &[Rule],
+    ) -> Result<(Self, Vec<ParseError>), ParseError> {
+        let span = |start: usize, end: usize| Span {
+            columns: start..end,
+            ..Default::default()
+        };
         if value.trim().is_empty() {
-            return Err(Error::other("Empty line"));
-        }
-        // check it's long enough
-        if value.split_whitespace().collect::<Vec<&str>>().len() < 3 {
-            return Err(Error::other("Not enough parts to the rule"));
-        }
-        // go through and make the rule naow
-        let mut parts = value.split_whitespace();
-
-        let facility = parts
-            .next()
-            .ok_or_else(|| Error::other("Failed to get facility"))?;
-        let control = parts
-            .next()
-            .ok_or_else(|| Error::other("Failed to get control"))?;
-        let module = parts
-            .next()
-            .ok_or_else(|| Error::other("Failed to get module"))?;
-        let arguments = parts.collect::<Vec<&str>>();
+            return Err(ParseError::EmptyLine {
+                span: span(0, value.len()),
+            });
+        }
+        let fields: Vec<Range<usize>> = syntax::lex(value)
+            .into_iter()
+            .filter(|(lexeme, _)| *lexeme == syntax::Lexeme::Field)
+            .map(|(_, range)| range)
+            .collect();
+        // the facility, control and module can't run on to the end of the line
+        if let Some(field) = fields.iter().take(3).find(|field| {
+            value[field.start..].starts_with('[')
+                && syntax::bracket_end(&value[field.start..]).is_none()
+        }) {
+            return Err(ParseError::UnterminatedBracket {
+                span: span(field.start, field.end),
+            });
+        }
+        let missing_at = fields.last().map(|field| field.end).unwrap_or_default();
+        if fields.is_empty() {
+            return Err(ParseError::EmptyLine {
+                span: span(0, value.len()),
+            });
+        }
+        if fields.len() < 2 {
+            return Err(ParseError::MissingControl {
+                span: span(missing_at, missing_at),
+            });
+        }
+        if fields.len() < 3 {
+            return Err(ParseError::MissingModule {
+                span: span(missing_at, missing_at),
+            });
+        }
+        let facility = &value[fields[0].clone()];
+        let control = &value[fields[1].clone()];
+        let module = &value[fields[2].clone()];
+        let arguments: Vec<String> = fields[3..]
+            .iter()
+            .map(|field| unbracket(&value[field.clone()]))
+            .collect();
+
+        let mut warnings = Vec::new();
+        let facility_span = span(fields[0].start, fields[0].end);
+        let control_span = span(fields[1].start, fields[1].end);
+        let (facility, silent_if_missing) = match facility.strip_prefix('-') {
+            Some(facility) => (facility, true),
+            None => (facility, false),
+        };
 
         let mut rule = Rule {
             facility: Facility::from(facility),
             control: Control::from(control),
             module: module.to_string(),
-            arguments: arguments.iter().map(|s| s.to_string()).collect(),
+            arguments,
             final_result: None,
             rule_order: Some(rule_order.to_owned()),
             rulehash: None,
+            stack_level: 0,
+            silent_if_missing,
+            source: Span::default(),
+            allow: Vec::new(),
         };
+        if let Facility::Invalid(value) = &rule.facility {
+            warnings.push(ParseError::UnknownFacility {
+                span: facility_span,
+                value: value.to_owned(),
+            });
+        }
+        if let Control::Invalid(value) = &rule.control {
+            warnings.push(match ControlActions::from_str(value) {
+                Err(reason) if value.starts_with('[') => ParseError::InvalidControl {
+                    span: control_span,
+                    value: value.to_owned(),
+                    reason,
+                },
+                _ => ParseError::UnknownControl {
+                    span: control_span,
+                    value: value.to_owned(),
+                },
+            });
+        }
+        if let Some(info) = modules::module_info(&rule.module) {
+            for (index, reason) in info.check_arguments(&rule.arguments) {
+                let field = &fields[3 + index];
+                warnings.push(ParseError::InvalidArgument {
+                    span: span(field.start, field.end),
+                    module: rule.module.clone(),
+                    argument: rule.arguments[index].clone(),
+                    reason: Box::new(reason),
+                });
+            }
+        }
         rule.final_result = try_find_matching_rule_result(results, &rule);
         // can't hash it until it's made
         rule.rulehash = Some(rule.hash());
-        Ok(rule)
+        Ok((rule, warnings))
+    }
+
+    /// The module is missing (`module_unknown`) but the rule started with a `-`, so libpam skips it rather than failing.
+    pub fn is_skipped_missing_module(&self) -> bool {
+        self.silent_if_missing && self.final_result == Some(ReturnCode::ModuleUnknown)
     }
+
     pub fn to_shortstring(&self) -> String {
         format!(
             "{} {} {}",
             self.control,
             self.module,
-            self.arguments.join(" ")
+            join_arguments(&self.arguments)
         )
     }
 
+    /// The rule as it would be written in a service file, without the line it came from's comments or spacing.
+    pub fn to_config_line(&self) -> String {
+        let facility = match &self.facility {
+            Facility::Invalid(value) => value.clone(),
+            facility => facility.to_string(),
+        };
+        let control = match &self.control {
+            Control::Invalid(value) => value.clone(),
+            control => control.to_string(),
+        };
+        let mut line = format!(
+            "{}{} {} {}",
+            if self.silent_if_missing { "-" } else { "" },
+            facility,
+            control,
+            self.module
+        );
+        if !self.arguments.is_empty() {
+            line.push(' ');
+            line.push_str(&join_arguments(&self.arguments));
+        }
+        line
+    }
+
     // return the result of the combination of the
     pub fn result_string(&self) -> String {
-        match &self.final_result {
-            Some(ref final_result) => match &self.control {
-                Control::Required => match final_result {
-                    FinalResult::Success => "Required rule succeeded",
-                    FinalResult::Failure => {
-                        "Required rule failed - other rules will run but the event will fail."
-                    }
-                }
-                .to_string(),
-                Control::Requisite => match final_result {
-                    FinalResult::Success => "Requisite rule continues.",
-                    FinalResult::Failure => "Instant failure of this facility!",
-                }
-                .to_string(),
-                Control::Sufficient => match final_result {
-                    FinalResult::Success => {
-                        "This'll allow further 'sufficient' rules to be skipped."
-                    }
-                    FinalResult::Failure => "'sufficient' rule failed, but other rules will run.",
-                }
-                .to_string(),
-                Control::Optional => if self.rule_order != Some(0) || self.rule_order.is_none() {
-                    "Result is irrelevant as it's not the only rule"
-                } else {
-                    match final_result {
-                        FinalResult::Success => {
-                            "Optional rule succeeded, as it's the only rule the facility succeeds."
-                        }
-                        FinalResult::Failure => {
-                            "Optional rule failed, and thus the facility fails."
-                        }
-                    }
+        match self.control {
+            Control::Include => {
+                return format!(
+                    "Runs the {} rules from {} as if they were here.",
+                    self.facility, self.module
+                )
+            }
+            Control::Substack => {
+                return format!(
+                    "Runs the {} rules from {} as a substack, which counts as a single rule.",
+                    self.facility, self.module
+                )
+            }
+            _ => {}
+        }
+        if self.is_skipped_missing_module() {
+            return format!(
+                "{} isn't installed, but the leading '-' on the facility means the rule is silently skipped.",
+                self.module
+            );
+        }
+        match self.final_result {
+            Some(final_result) => match &self.control {
+                Control::Required => match self.control.actions().map(|a| a.action_for(final_result)) {
+                    Some(Action::Ok) => "Required rule succeeded.".to_string(),
+                    Some(Action::Ignore) => "Required rule asked to be ignored, so it doesn't change the result.".to_string(),
+                    _ => format!(
+                        "Required rule failed with {} - the rest of the stack still runs, but the facility will fail.",
+                        final_result
+                    ),
+                },
+                Control::Requisite => match self.control.actions().map(|a| a.action_for(final_result)) {
+                    Some(Action::Ok) => "Requisite rule succeeded, so the stack continues.".to_string(),
+                    Some(Action::Ignore) => "Requisite rule asked to be ignored, so it doesn't change the result.".to_string(),
+                    _ => format!("Instant failure of this facility with {}!", final_result),
+                },
+                Control::Sufficient => match self.control.actions().map(|a| a.action_for(final_result)) {
+                    Some(Action::Done) => "Sufficient rule succeeded, so unless an earlier rule already failed, the facility succeeds without running the rest of the stack.".to_string(),
+                    _ => format!(
+                        "'sufficient' rule returned {}, which is ignored.",
+                        final_result
+                    ),
+                },
+                Control::Optional => match self.control.actions().map(|a| a.action_for(final_result)) {
+                    Some(Action::Ok) => "Optional rule succeeded, which only decides the result if nothing else in the stack does.".to_string(),
+                    _ => format!(
+                        "Optional rule returned {}, which is ignored. If nothing else in the stack succeeds or fails, the facility fails.",
+                        final_result
+                    ),
+                },
+                Control::Include | Control::Substack => unreachable!("handled above"),
+                Control::Complex(actions) => {
+                    let action = actions.action_for(final_result);
+                    format!("Returned {}, so {}: {}", final_result, action, action.explain())
                 }
-                .to_string(),
                 Control::Invalid(invalid_value) => {
-                    format!("Invalid control configuration: {}", invalid_value)
+                    format!("Invalid control configuration: {}, so libpam treats every result as a failure.", invalid_value)
                 }
             },
             None => "Final result not set, can't determine state!".to_string(),
@@ -283,58 +529,77 @@ impl Rule {
     }
 }
 
-#[derive(Clone, Debug, Deserialize, Serialize, Eq, PartialEq)]
-pub enum FinalResult {
-    Success,
-    Failure,
-}
-
-impl From<FinalResult> for bool {
-    fn from(value: FinalResult) -> Self {
-        match value {
-            FinalResult::Success => true,
-            FinalResult::Failure => false,
+/// The value a module sees for an argument, a `[ ... ]` argument has its brackets removed and any `\]` inside turned into `]`.
+fn unbracket(field: &str) -> String {
+    match field.strip_prefix('[') {
+        Some(inner) => {
+            let inner = match syntax::bracket_end(field) {
+                Some(end) => &inner[..end - 2],
+                None => inner,
+            };
+            inner.replace("\\]", "]")
         }
+        None => field.to_string(),
     }
 }
 
-impl From<bool> for FinalResult {
-    fn from(value: bool) -> Self {
-        match value {
-            true => FinalResult::Success,
-            false => FinalResult::Failure,
-        }
+/// Write an argument back out the way it'd need to appear in a config file, wrapping it in `[ ... ]` if it has spaces in it.
+pub fn quote_argument(argument: &str) -> String {
+    if argument.is_empty()
+        || argument.starts_with(['[', '#'])
+        || argument.contains(char::is_whitespace)
+        || argument.ends_with('\\')
+    {
+        format!("[{}]", argument.replace(']', "\\]"))
+    } else {
+        argument.to_string()
     }
 }
 
+/// Which way the stack is leaning so far, libpam's `_PAM_UNDEF`, `_PAM_POSITIVE` and `_PAM_NEGATIVE`.
+#[derive(Clone, Copy, Debug, Deserialize, Serialize, Eq, PartialEq, Hash)]
+#[serde(rename_all = "lowercase")]
+pub enum Impression {
+    /// Nothing has contributed to the result yet, if the stack ends like this it fails.
+    Undefined,
+    Positive,
+    Negative,
+}
+
+/// What to do after a rule has been run.
+enum Flow {
+    Continue,
+    /// Skip over this many of the following rules, a substack counts as one
+    Jump(u32),
+    /// Go back to the state the current (sub)stack started with
+    Reset,
+    /// Skip the rest of the current (sub)stack
+    Stop,
+}
+
 #[derive(PartialEq, Eq, Clone, Debug)]
 pub struct RuleSet {
     pub facility: Facility,
     pub rules: Vec<Rule>,
-    pub finalresult: FinalResult,
-    pub had_sufficient: bool,
+    pub impression: Impression,
+    /// The frozen return code of the stack, only meaningful once the impression isn't [Impression::Undefined]
+    pub status: ReturnCode,
     pub rules_run: usize,
 }
 
 impl RuleSet {
-    pub fn get_rule_result(&self, rule: &Rule) -> bool {
-        match &rule.final_result {
-            Some(val) => val.to_owned().into(),
-            None => {
+    /// What the rule's module returned, from the rule if it's been set, otherwise from `provider`.
+    pub fn get_rule_result(&self, rule: &Rule, provider: &mut dyn OutcomeProvider) -> ReturnCode {
+        match rule.final_result {
+            Some(val) => val,
+            None => provider.outcome(rule).unwrap_or_else(|| {
                 info!(
-                    "Did this succeed: {} {}",
+                    "Don't know what {} {} returned, assuming it failed",
                     rule.facility,
                     rule.to_shortstring()
                 );
-                #[cfg(feature = "cli")]
-                match Confirm::new().interact() {
-                    Ok(true) => true,
-                    Ok(false) => false,
-                    Err(_) => false,
-                }
-                #[cfg(not(feature = "cli"))]
-                false
-            }
+                rule.facility.failure_code()
+            }),
         }
     }
 
@@ -342,188 +607,382 @@ impl RuleSet {
         RuleSet {
             facility: facility.clone(),
             rules,
-            finalresult: FinalResult::Success,
-            had_sufficient: false,
+            impression: Impression::Undefined,
+            status: ReturnCode::PermDenied,
             rules_run: 0,
         }
     }
-    pub fn run_rules(&mut self) -> FinalResult {
-        let rules_iter = self.rules.iter().enumerate();
-        // println!("Facility: {:?}", self.facility);
-        for (index, rule) in rules_iter {
-            match rule.control.clone() {
-                Control::Invalid(value) => {
-                    warn!("Invalid control: {}", value);
+
+    /// Update the state of the stack the way libpam's `_pam_dispatch_aux` does for a given action and module return code.
+    fn apply_action(&mut self, action: Action, code: ReturnCode) -> Flow {
+        match action {
+            Action::Reset => Flow::Reset,
+            Action::Ok | Action::Done => {
+                if self.impression == Impression::Undefined
+                    || (self.impression == Impression::Positive
+                        && self.status == ReturnCode::Success)
+                {
+                    self.impression = Impression::Positive;
+                    self.status = code;
                 }
-                Control::Required => {
-                    if let FinalResult::Failure = self.finalresult {
-                        info!(
-                            "Don't have to process \"{}\" because we already failed, and this won't change the state.",
-                            rule.to_shortstring(),
-                        );
-                        continue;
+                if action == Action::Done && self.impression != Impression::Negative {
+                    Flow::Stop
+                } else {
+                    Flow::Continue
+                }
+            }
+            Action::Bad | Action::Die => {
+                if self.impression != Impression::Negative {
+                    self.impression = Impression::Negative;
+                    // neither success nor ignore is allowed to be the reason for failure, libpam uses PAM_MUST_FAIL_CODE instead
+                    self.status = match code {
+                        ReturnCode::Success | ReturnCode::Ignore => ReturnCode::PermDenied,
+                        code => code,
                     };
-                    let rule_result = self.get_rule_result(rule);
-                    if !rule_result {
-                        self.finalresult = FinalResult::Failure;
-                        warn!(
-                            "Rule #{} was required, so {:?} will fail!",
-                            rule.rule_order
-                                .map(|i| i.to_string())
-                                .unwrap_or("?".to_string()),
-                            self.facility
-                        );
-                    }
-                    self.rules_run += 1;
                 }
-                Control::Requisite => {
-                    self.rules_run += 1;
-                    if !self.get_rule_result(rule) {
-                        warn!(
-                            "Rule #{} was requisite, so {:?} will fail regardless!",
-                            rule.rule_order
-                                .map(|i| i.to_string())
-                                .unwrap_or("?".to_string()),
-                            self.facility
-                        );
-                        return FinalResult::Failure;
-                    }
+                if action == Action::Die {
+                    Flow::Stop
+                } else {
+                    Flow::Continue
+                }
+            }
+            Action::Ignore => Flow::Continue,
+            // on a first pass through the stack a jump doesn't record the result, which is why
+            // Debian's common-auth primes the stack with pam_permit after jumping over pam_deny
+            Action::Jump(count) => Flow::Jump(count),
+        }
+    }
+
+    fn state(&self) -> StackState {
+        StackState {
+            impression: self.impression,
+            status: self.status,
+        }
+    }
+
+    /// Runs the stack, returning what happened to each rule and the code the facility would hand back to the application.
+    ///
+    /// Rules without a [Rule::final_result] are treated as failing, use [RuleSet::run_rules_with] to decide what they return.
+    pub fn run_rules(&mut self) -> Trace {
+        self.run_rules_with(&mut NoOutcome)
+    }
+
+    /// Runs the stack, asking `provider` what each module returned when the rule doesn't already say.
+    pub fn run_rules_with(&mut self, provider: &mut dyn OutcomeProvider) -> Trace {
+        let mut steps = Vec::new();
+        let mut index = 0;
+        let mut prev_level = 0;
+        // the state of the stack as each level of substack was entered
+        let mut substates = vec![(Impression::Undefined, ReturnCode::PermDenied)];
+        while index < self.rules.len() {
+            let rule = self.rules[index].clone();
+            let level = rule.stack_level;
+            self.enter(level, prev_level, &mut substates);
+            prev_level = level;
+
+            if rule.is_skipped_missing_module() {
+                debug!(
+                    "Skipping \"{}\" because {} isn't installed and the rule allows that.",
+                    rule.to_shortstring(),
+                    rule.module
+                );
+                steps.push(Step {
+                    index,
+                    outcome: Outcome::Skipped {
+                        reason: SkipReason::MissingModule,
+                    },
+                    state: self.state(),
+                });
+                index += 1;
+                continue;
+            }
+
+            let before = self.state();
+            let (code, action) = match rule.control.actions() {
+                Some(actions) => {
+                    let code = self.get_rule_result(&rule, provider);
+                    (code, actions.action_for(code))
                 }
-                Control::Sufficient => {
-                    if self.had_sufficient {
-                        info!(
-                            "Don't have to process {} {} {} because we already had a 'sufficient' rule",
-                            rule.control,
-                            rule.module,
-                            rule.arguments.join(" "),
-                        );
+                None => match &rule.control {
+                    Control::Include | Control::Substack => {
+                        let outcome = match rule.control {
+                            Control::Include => {
+                                warn!("Include of {} wasn't resolved, skipping it", rule.module);
+                                Outcome::UnresolvedInclude
+                            }
+                            _ => Outcome::Substack,
+                        };
+                        steps.push(Step {
+                            index,
+                            outcome,
+                            state: before,
+                        });
+                        index += 1;
                         continue;
-                    } else if self.get_rule_result(rule) {
-                        self.had_sufficient = true;
                     }
+                    // libpam still runs the module, but every result is bad
+                    _ => (self.get_rule_result(&rule, provider), Action::Bad),
+                },
+            };
+            self.rules_run += 1;
+            debug!(
+                "Rule #{} ({}) returned {} so {}",
+                index,
+                rule.to_shortstring(),
+                code,
+                action
+            );
+            let flow = self.apply_action(action, code);
+
+            let (skipped, bad_jump) = self.follow(index, level, action, flow, &substates);
+
+            let state = self.state();
+            steps.push(Step {
+                index,
+                outcome: Outcome::Invoked {
+                    code,
+                    action,
+                    changed: state != before,
+                    bad_jump,
+                },
+                state,
+            });
+            index += 1 + skipped.len();
+            steps.extend(skipped.into_iter().map(|(skip, reason)| Step {
+                index: skip,
+                outcome: Outcome::Skipped { reason },
+                state,
+            }));
+        }
+        Trace {
+            steps,
+            result: self.final_result(),
+        }
+    }
+
+    /// Notes the state of the stack when a rule at a deeper `level` of substack is reached, so a `reset` in it can go back to it.
+    fn enter(
+        &self,
+        level: usize,
+        prev_level: usize,
+        substates: &mut Vec<(Impression, ReturnCode)>,
+    ) {
+        if level > prev_level {
+            substates.resize(level + 1, (self.impression, self.status));
+            substates[level] = (self.impression, self.status);
+        }
+    }
 
-                    self.rules_run += 1;
+    /// Where the stack goes after the rule at `index` took `action`, returning the rules that are skipped because of it and if it jumped past the end of the (sub)stack, which fails it.
+    fn follow(
+        &mut self,
+        index: usize,
+        level: usize,
+        action: Action,
+        flow: Flow,
+        substates: &[(Impression, ReturnCode)],
+    ) -> (Vec<(usize, SkipReason)>, bool) {
+        let mut skipped = Vec::new();
+        let mut bad_jump = false;
+        match flow {
+            Flow::Continue => {}
+            Flow::Reset => (self.impression, self.status) = substates[level],
+            Flow::Jump(count) => {
+                let mut remaining = count;
+                let mut end = index;
+                while remaining > 0
+                    && self
+                        .rules
+                        .get(end + 1)
+                        .is_some_and(|next| next.stack_level >= level)
+                {
+                    end = self.end_of_entry(end + 1);
+                    remaining -= 1;
                 }
-                Control::Optional => {
-                    if !self.get_rule_result(rule) {
-                        self.rules_run += 1;
-                        // first in the facility, doesn't have to be the first *rule*
-                        if index == 0 {
-                            return FinalResult::Failure;
-                        } else {
-                            println!("Optional rule {} failed, but wasn't the first rule, so we'll continue", rule.rule_order
-                            .map(|i| i.to_string())
-                            .unwrap_or("?".to_string()),);
-                        }
-                    }
+                skipped.extend((index + 1..=end).map(|by| (by, SkipReason::Jumped { by: index })));
+                if remaining > 0 {
+                    bad_jump = true;
+                    self.impression = Impression::Negative;
+                    self.status = ReturnCode::PermDenied;
+                }
+            }
+            Flow::Stop => {
+                let reason = match action {
+                    Action::Die => SkipReason::Died { by: index },
+                    _ => SkipReason::Done { by: index },
+                };
+                // done and die only end the substack they're in
+                let mut end = index;
+                while self
+                    .rules
+                    .get(end + 1)
+                    .is_some_and(|next| next.stack_level >= level)
+                {
+                    end += 1;
                 }
+                skipped.extend((index + 1..=end).map(|skip| (skip, reason)));
             }
         }
-        self.finalresult.to_owned()
+        (skipped, bad_jump)
     }
-}
 
-pub fn loadresults() -> Vec<Rule> {
-    let filename = match env::args().nth(2) {
-        Some(filename) => filename,
-        None => {
-            debug!("No results file given, please tell me which file to read if you want them!");
-            return vec![];
+    /// The index of the last rule belonging to the entry at `index`, which is past the end of its children if it's a substack.
+    fn end_of_entry(&self, index: usize) -> usize {
+        let level = self.rules[index].stack_level;
+        let mut end = index;
+        while self
+            .rules
+            .get(end + 1)
+            .is_some_and(|next| next.stack_level > level)
+        {
+            end += 1;
         }
-    };
+        end
+    }
 
-    let input_string = match std::fs::read_to_string(&filename) {
-        Ok(val) => val,
-        Err(err) => {
-            error!("Failed to read {}: {:?}", filename, err);
-            return vec![];
+    /// The result of the stack given its current state, a success code only counts if something actually succeeded.
+    pub fn final_result(&self) -> ReturnCode {
+        if self.status == ReturnCode::Success && self.impression != Impression::Positive {
+            ReturnCode::PermDenied
+        } else {
+            self.status
         }
-    };
-    serde_json::from_str(&input_string).expect("Failed to load results")
+    }
 }
 
-pub fn load_file() -> Result<Vec<String>, std::io::Error> {
-    // load filename specified on the command line as argv[1] as a stirng
-    let filename = match env::args().nth(1) {
-        Some(filename) => filename,
-        None => {
-            error!("No filename given, please tell me which file to read!");
-            return Err(Error::other(
-                "No filename given, please tell me which file to read!",
-            ));
-        }
-    };
-    info!("Loading file: {}", filename);
-
-    // read the file into a string
-    let input_string = std::fs::read_to_string(&filename).map_err(|err| {
-        error!("Failed to read {}: {:?}", filename, err);
-        err
-    })?;
-
-    Ok(input_string
-        .lines()
-        .filter_map(|line| {
-            let line = line.trim();
-            if line.is_empty() {
-                // ignore blank lines
-                None
-            } else if line.starts_with('#') {
-                // comments
-                info!("Skipping comment: {}", line);
-                None
-            } else {
-                Some(line.to_string())
-            }
-        })
-        .collect::<Vec<String>>())
+/// Parse a config, returning the rules and the problems found with its lines. `file` is used to label the diagnostics.
+///
+/// Includes aren't followed, use [load_config] or [include::resolve_includes] for that.
+pub fn parse_config(
+    text: &str,
+    file: Option<&str>,
+    results: &[Rule],
+) -> (Vec<Rule>, Vec<ParseError>) {
+    let (rules, diagnostics) = syntax::SyntaxFile::parse(text).rules(file, results);
+    rules.iter().for_each(|r| debug!("{:?}", r));
+    (rules, diagnostics)
+}
+
+/// Read a config from `reader`, like [parse_config].
+pub fn read_config(
+    mut reader: impl Read,
+    file: Option<&str>,
+    results: &[Rule],
+) -> Result<(Vec<Rule>, Vec<ParseError>), LoadError> {
+    let mut text = String::new();
+    reader.read_to_string(&mut text)?;
+    Ok(parse_config(&text, file, results))
+}
+
+/// Load a service file, following its includes relative to the directory it's in.
+pub fn load_config(
+    path: &Path,
+    results: &[Rule],
+) -> Result<(Vec<Rule>, Vec<ParseError>), LoadError> {
+    info!("Loading file: {}", path.display());
+    let text = std::fs::read_to_string(path).map_err(LoadError::io(path))?;
+    let dir = path.parent().unwrap_or(Path::new(""));
+    include::resolve_includes(&text, path.to_str(), dir, results)
 }
 
-pub fn try_find_matching_rule_result(rules: &[Rule], rule: &Rule) -> Option<FinalResult> {
+pub fn try_find_matching_rule_result(rules: &[Rule], rule: &Rule) -> Option<ReturnCode> {
     rules.iter().find_map(|r| {
         if r.facility == rule.facility
             && r.control == rule.control
             && r.module == rule.module
             && r.arguments == rule.arguments
         {
-            r.final_result.clone().map(|r| r.to_owned())
+            r.final_result
         } else {
             None
         }
     })
 }
 
-pub fn rules_from_vec_string(value: Vec<String>) -> Vec<Rule> {
-    let mut rule_order: u32 = 0;
-    let results_vec = loadresults();
-    let rules: Vec<Rule> = value
+/// Parse every line of a config, returning the rules and the problems found with the lines. `file` is used to label the diagnostics.
+pub fn parse_lines(
+    value: Vec<String>,
+    file: Option<&str>,
+    results: &[Rule],
+) -> (Vec<Rule>, Vec<ParseError>) {
+    parse_config(&value.join("\n"), file, results)
+}
+
+/// Parse a single line of a config, skipping blanks and comments and adding any problems to `diagnostics`. `allow` is the lints allowed for the rule, which can include its [lint::INVALID_ARGUMENT]s.
+pub(crate) fn parse_line(
+    line: &str,
+    file: Option<&str>,
+    line_number: usize,
+    rule_order: u32,
+    results: &[Rule],
+    allow: Vec<String>,
+    diagnostics: &mut Vec<ParseError>,
+) -> Option<Rule> {
+    debug!("handling line: '{}'", line);
+    let trimmed = line.trim();
+    if trimmed.is_empty() || trimmed.starts_with('#') {
+        return None;
+    }
+    let fields: Vec<Range<usize>> = syntax::lex(line)
         .into_iter()
-        .filter_map(|line| {
-            debug!("handling line: '{}'", line);
-            if line.trim().is_empty() {
-                return None;
-            }
-            if line.trim().starts_with('#') {
-                return None;
+        .filter(|(lexeme, _)| *lexeme == syntax::Lexeme::Field)
+        .map(|(_, range)| range)
+        .collect();
+    if fields
+        .first()
+        .is_some_and(|field| &line[field.clone()] == "@include")
+    {
+        let span = Span {
+            columns: fields[0].start..fields[fields.len() - 1].end,
+            ..Default::default()
+        };
+        diagnostics.push(
+            match fields.get(1) {
+                None => ParseError::MissingIncludeTarget { span },
+                Some(target) => ParseError::UnresolvedInclude {
+                    span,
+                    target: line[target.clone()].to_string(),
+                },
             }
+            .within(line, file, line_number),
+        );
+        return None;
+    }
 
-            if let Ok(rule) = Rule::new(&line, &rule_order, &results_vec) {
-                rule_order += 1;
-                Some(rule)
-            } else {
-                None
-            }
-        })
-        .collect();
-    rules.iter().for_each(|r| debug!("{:?}", r));
-    rules
+    match Rule::parse(line, &rule_order, results) {
+        Ok((mut rule, warnings)) => {
+            let start = line.len() - line.trim_start().len();
+            rule.source = Span {
+                file: file.map(|file| file.to_string()),
+                line: line_number,
+                columns: start..line.trim_end().len(),
+            };
+            let allow_arguments = allow.iter().any(|id| id == lint::INVALID_ARGUMENT);
+            diagnostics.extend(
+                warnings
+                    .into_iter()
+                    .filter(|w| {
+                        !(allow_arguments && matches!(w, ParseError::InvalidArgument { .. }))
+                    })
+                    .map(|w| w.within(line, file, line_number)),
+            );
+            rule.allow = allow;
+            Some(rule)
+        }
+        Err(err) => {
+            diagnostics.push(err.within(line, file, line_number));
+            None
+        }
+    }
 }
 
 pub type RuleSets = HashMap<Facility, RuleSet>;
 
-pub fn rulesets_from_string(value: String, default_result: FinalResult) -> RuleSets {
-    let rule_vcec_string: Vec<String> = value.lines().map(|l| l.to_string()).collect();
-    let rules = rules_from_vec_string(rule_vcec_string);
+pub fn rulesets_from_string(
+    value: String,
+    default_result: ReturnCode,
+) -> (RuleSets, Vec<ParseError>) {
+    let (rules, diagnostics) = parse_config(&value, None, &[]);
 
     let mut rulesets: RuleSets = HashMap::new();
 
@@ -532,13 +991,13 @@ pub fn rulesets_from_string(value: String, default_result: FinalResult) -> RuleS
             rulesets.insert(rule.facility.clone(), RuleSet::new(&rule.facility, vec![]));
         }
         let mut rule = rule;
-        rule.final_result = Some(default_result.clone());
+        rule.final_result = Some(default_result);
         if let Some(rs) = rulesets.get_mut(&rule.facility) {
-            rs.finalresult = default_result.clone();
+            rs.rules.push(rule);
         } else {
             error!("Failed to get ruleset for facility {:?}", rule.facility);
         }
     });
 
-    rulesets
+    (rulesets, diagnostics)
 }
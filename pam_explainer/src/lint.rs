@@ -0,0 +1,503 @@
+//! Checks for configurations that parse fine but probably don't do what was meant.
+//!
+//! Each check has a stable ID that can be allowed for a rule with a comment on the line before it, or at the end of the rule itself:
+//!
+//! ```text
+//! # pam-explainer: allow(optional-only)
+//! session optional pam_systemd.so
+//! auth sufficient pam_permit.so # pam-explainer: allow(unreachable-rule, no-success-needed)
+//! ```
+
+use crate::diagnostics::Span;
+use crate::modules::{module_info, ArgumentError};
+use crate::paths::{fixed_outcome, Paths, MAX_PATHS};
+use crate::{Action, Control, ControlActions, Facility, Outcome, ParseError, Rule, SkipReason};
+use serde::Serialize;
+use std::fmt::Display;
+use std::str::FromStr;
+
+#[derive(Clone, Copy, Debug, Serialize, Eq, PartialEq, PartialOrd, Ord)]
+#[serde(rename_all = "lowercase")]
+pub enum Severity {
+    /// Worth knowing, but not necessarily wrong
+    Info,
+    Warning,
+    /// libpam won't do what the config says, or it's insecure
+    Error,
+}
+
+impl Display for Severity {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Severity::Info => write!(f, "info"),
+            Severity::Warning => write!(f, "warning"),
+            Severity::Error => write!(f, "error"),
+        }
+    }
+}
+
+/// Something a check found.
+#[derive(Clone, Debug, Serialize, Eq, PartialEq)]
+pub struct Lint {
+    /// The [Check::id] of the check that found it
+    pub id: &'static str,
+    pub severity: Severity,
+    pub message: String,
+    pub span: Span,
+}
+
+impl Display for Lint {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{}: {}[{}]: {}",
+            self.span, self.severity, self.id, self.message
+        )
+    }
+}
+
+/// A facility's stack within a service, and every way it can run.
+#[derive(Clone, Debug)]
+pub struct Stack {
+    pub facility: Facility,
+    pub rules: Vec<Rule>,
+    pub paths: Paths,
+}
+
+/// What the checks look at, one service at a time.
+#[derive(Clone, Debug)]
+pub struct Context {
+    pub service: String,
+    /// Every rule in the service, including ones with an invalid facility
+    pub rules: Vec<Rule>,
+    pub stacks: Vec<Stack>,
+}
+
+impl Context {
+    pub fn new(service: &str, rules: &[Rule]) -> Self {
+        let stacks = [
+            Facility::Auth,
+            Facility::Account,
+            Facility::Password,
+            Facility::Session,
+        ]
+        .into_iter()
+        .filter_map(|facility| {
+            let stack: Vec<Rule> = rules
+                .iter()
+                .filter(|rule| rule.facility == facility)
+                .cloned()
+                .collect();
+            match stack.is_empty() {
+                true => None,
+                false => Some(Stack {
+                    paths: crate::paths::paths(&facility, &stack, MAX_PATHS),
+                    facility,
+                    rules: stack,
+                }),
+            }
+        })
+        .collect();
+        Self {
+            service: service.to_string(),
+            rules: rules.to_vec(),
+            stacks,
+        }
+    }
+}
+
+/// A check, which finds rules with a particular kind of problem.
+pub struct Check {
+    /// Stable, used to allow the check with a `# pam-explainer: allow(ID)` comment
+    pub id: &'static str,
+    pub severity: Severity,
+    /// What the check looks for
+    pub summary: &'static str,
+    /// Each rule with the problem, and what's wrong with it
+    pub run: fn(&Context) -> Vec<(&Rule, String)>,
+}
+
+/// Every check there is, in the order they're run.
+pub const CHECKS: &[Check] = &[
+    Check {
+        id: "invalid-facility",
+        severity: Severity::Error,
+        summary: "The facility isn't one libpam knows, so the rule is ignored",
+        run: invalid_facility,
+    },
+    Check {
+        id: "invalid-control",
+        severity: Severity::Error,
+        summary: "libpam can't parse the control, so it treats every result as a failure",
+        run: invalid_control,
+    },
+    Check {
+        id: "unsupported-facility",
+        severity: Severity::Warning,
+        summary: "A known module is used in a facility it doesn't implement",
+        run: unsupported_facility,
+    },
+    Check {
+        id: "jump-past-end",
+        severity: Severity::Error,
+        summary: "A jump goes past the end of the (sub)stack, which fails it",
+        run: jump_past_end,
+    },
+    Check {
+        id: "unreachable-rule",
+        severity: Severity::Warning,
+        summary: "No combination of module results ever runs the rule",
+        run: unreachable_rule,
+    },
+    Check {
+        id: "optional-only",
+        severity: Severity::Warning,
+        summary: "Every rule in the facility is optional, so any one module succeeding is enough",
+        run: optional_only,
+    },
+    Check {
+        id: "no-success-needed",
+        severity: Severity::Warning,
+        summary: "auth or account can succeed without any module that can fail succeeding",
+        run: no_success_needed,
+    },
+    Check {
+        id: "requisite-after-required",
+        severity: Severity::Info,
+        summary: "A requisite rule can stop the stack after a required rule has already failed it",
+        run: requisite_after_required,
+    },
+];
+
+/// Problems found while parsing, which aren't attached to a rule so can't be allowed.
+pub const PARSE_ERROR: &str = "parse-error";
+
+/// Arguments a well-known module won't do what's expected with, found while parsing so they can point at the argument, but they can be allowed like a check.
+pub const INVALID_ARGUMENT: &str = "invalid-argument";
+
+/// The check with this ID.
+pub fn check(id: &str) -> Option<&'static Check> {
+    CHECKS.iter().find(|check| check.id == id)
+}
+
+/// Run every check over a service, leaving out anything the rules allow.
+pub fn lint_service(service: &str, rules: &[Rule]) -> Vec<Lint> {
+    let context = Context::new(service, rules);
+    let mut lints = Vec::new();
+    for check in CHECKS.iter() {
+        for (rule, message) in (check.run)(&context) {
+            if rule.allow.iter().any(|id| id == check.id) {
+                continue;
+            }
+            lints.push(Lint {
+                id: check.id,
+                severity: check.severity,
+                message,
+                span: rule.source.clone(),
+            });
+        }
+    }
+    lints
+}
+
+/// Turn the problems found while parsing into lints, other than the ones [CHECKS] already report from the rules they were kept as.
+pub fn lint_diagnostics(diagnostics: &[ParseError]) -> Vec<Lint> {
+    diagnostics
+        .iter()
+        .filter(|diagnostic| {
+            !matches!(
+                diagnostic,
+                ParseError::UnknownFacility { .. }
+                    | ParseError::UnknownControl { .. }
+                    | ParseError::InvalidControl { .. }
+            )
+        })
+        .map(|diagnostic| {
+            let (id, severity) = match diagnostic {
+                ParseError::InvalidArgument { reason, .. } => match reason.as_ref() {
+                    ArgumentError::Deprecated(_) => (INVALID_ARGUMENT, Severity::Info),
+                    _ => (INVALID_ARGUMENT, Severity::Warning),
+                },
+                _ => (PARSE_ERROR, Severity::Error),
+            };
+            Lint {
+                id,
+                severity,
+                message: describe(diagnostic.clone()),
+                span: diagnostic.span().clone(),
+            }
+        })
+        .collect()
+}
+
+/// The message and suggestion of a parse error.
+fn describe(error: ParseError) -> String {
+    match error.suggestion() {
+        Some(suggestion) => format!("{} ({})", error.message(), suggestion),
+        None => error.message(),
+    }
+}
+
+/// How other rules are referred to in messages, by where they are as the same rule can be in several services.
+fn describe_rule(rule: &Rule) -> String {
+    match rule.source.line {
+        0 => rule.to_shortstring(),
+        _ => format!("{} ({})", rule.module, rule.source),
+    }
+}
+
+fn invalid_facility(context: &Context) -> Vec<(&Rule, String)> {
+    context
+        .rules
+        .iter()
+        .filter_map(|rule| match &rule.facility {
+            Facility::Invalid(value) => Some((
+                rule,
+                describe(ParseError::UnknownFacility {
+                    span: rule.source.clone(),
+                    value: value.clone(),
+                }),
+            )),
+            _ => None,
+        })
+        .collect()
+}
+
+fn invalid_control(context: &Context) -> Vec<(&Rule, String)> {
+    context
+        .rules
+        .iter()
+        .filter_map(|rule| {
+            let Control::Invalid(value) = &rule.control else {
+                return None;
+            };
+            let span = rule.source.clone();
+            let error = match ControlActions::from_str(value) {
+                Err(reason) if value.starts_with('[') => ParseError::InvalidControl {
+                    span,
+                    value: value.clone(),
+                    reason,
+                },
+                _ => ParseError::UnknownControl {
+                    span,
+                    value: value.clone(),
+                },
+            };
+            Some((rule, describe(error)))
+        })
+        .collect()
+}
+
+fn unsupported_facility(context: &Context) -> Vec<(&Rule, String)> {
+    context
+        .stacks
+        .iter()
+        .flat_map(|stack| stack.rules.iter())
+        .filter_map(|rule| {
+            let info = module_info(&rule.module)?;
+            if info.supports(&rule.facility) {
+                return None;
+            }
+            let facilities: Vec<String> = info
+                .facilities()
+                .map(|facility| facility.to_string())
+                .collect();
+            Some((
+                rule,
+                format!(
+                    "{} doesn't implement the {} facility, only {}, so libpam has it return module_unknown which counts as a failure",
+                    rule.module,
+                    rule.facility,
+                    facilities.join(", ")
+                ),
+            ))
+        })
+        .collect()
+}
+
+fn jump_past_end(context: &Context) -> Vec<(&Rule, String)> {
+    let mut found = Vec::new();
+    for stack in context.stacks.iter() {
+        for (index, rule) in stack.rules.iter().enumerate() {
+            let Control::Complex(actions) = &rule.control else {
+                continue;
+            };
+            let Some(jump) = actions
+                .values
+                .values()
+                .chain(actions.default.iter())
+                .filter_map(|action| match action {
+                    Action::Jump(count) => Some(*count),
+                    _ => None,
+                })
+                .max()
+            else {
+                continue;
+            };
+            // a substack counts as one rule, and a jump can't leave the substack it's in
+            let level = rule.stack_level;
+            let after = stack.rules[index + 1..]
+                .iter()
+                .take_while(|next| next.stack_level >= level)
+                .filter(|next| next.stack_level == level)
+                .count();
+            if jump as usize > after {
+                found.push((
+                    rule,
+                    format!(
+                        "Jumps over {} rule(s), but only {} follow it, so when the jump is taken the stack fails",
+                        jump, after
+                    ),
+                ));
+            }
+        }
+    }
+    found
+}
+
+fn unreachable_rule(context: &Context) -> Vec<(&Rule, String)> {
+    let mut found = Vec::new();
+    for stack in context.stacks.iter().filter(|stack| stack.paths.complete) {
+        for (index, rule) in stack.rules.iter().enumerate() {
+            let skipped: Vec<SkipReason> = stack
+                .paths
+                .paths
+                .iter()
+                .filter_map(|path| match path.trace.step(index)?.outcome {
+                    Outcome::Skipped { reason } => Some(reason),
+                    _ => None,
+                })
+                .collect();
+            if skipped.len() < stack.paths.paths.len() {
+                continue;
+            }
+            // a module that can only return one thing always jumping over the rule is deliberate, like Debian's `[default=1] pam_permit.so` priming the stack before `requisite pam_deny.so`
+            let placeholder = skipped.iter().all(|reason| {
+                matches!(reason, SkipReason::Jumped { by } if fixed_outcome(&stack.rules[*by]).is_some())
+            });
+            if placeholder {
+                continue;
+            }
+            let by = skipped.iter().find_map(|reason| match reason {
+                SkipReason::Done { by } => Some(("always ends the stack before it", by)),
+                SkipReason::Died { by } => Some(("always fails the stack before it", by)),
+                SkipReason::Jumped { by } => Some(("always jumps over it", by)),
+                SkipReason::MissingModule => None,
+            });
+            let message = match by {
+                Some((why, by)) => format!(
+                    "This rule can never run, {} {}",
+                    describe_rule(&stack.rules[*by]),
+                    why
+                ),
+                None => "This rule can never run".to_string(),
+            };
+            found.push((rule, message));
+        }
+    }
+    found
+}
+
+fn optional_only(context: &Context) -> Vec<(&Rule, String)> {
+    context
+        .stacks
+        .iter()
+        .filter_map(|stack| {
+            let mut modules = stack
+                .rules
+                .iter()
+                .filter(|rule| !matches!(rule.control, Control::Include | Control::Substack));
+            let first = modules.clone().next()?;
+            match modules.all(|rule| rule.control == Control::Optional) {
+                true => Some((
+                    first,
+                    format!(
+                        "Every rule in the {} stack is optional, so it succeeds if any one module does, and no failure stops it",
+                        stack.facility
+                    ),
+                )),
+                false => None,
+            }
+        })
+        .collect()
+}
+
+fn no_success_needed(context: &Context) -> Vec<(&Rule, String)> {
+    context
+        .stacks
+        .iter()
+        .filter(|stack| matches!(stack.facility, Facility::Auth | Facility::Account))
+        .filter_map(|stack| {
+            // pam_permit can't fail, so it succeeding doesn't count
+            let can_fail = |index: &usize| fixed_outcome(&stack.rules[*index]).is_none();
+            let path = stack.paths.paths.iter().find(|path| {
+                path.trace.result == crate::ReturnCode::Success
+                    && !path
+                        .outcomes
+                        .keys()
+                        .any(|index| can_fail(index) && path.succeeded(*index))
+            })?;
+            // blame the rule that decided the result
+            let decider = path
+                .trace
+                .steps
+                .iter()
+                .rev()
+                .find(|step| {
+                    matches!(
+                        step.outcome,
+                        Outcome::Invoked {
+                            action: Action::Ok | Action::Done,
+                            changed: true,
+                            ..
+                        }
+                    )
+                })
+                .map(|step| &stack.rules[step.index])
+                .unwrap_or(&stack.rules[0]);
+            Some((
+                decider,
+                format!(
+                    "The {} stack can succeed without any module that can fail succeeding, {} always succeeds",
+                    stack.facility, decider.module
+                ),
+            ))
+        })
+        .collect()
+}
+
+fn requisite_after_required(context: &Context) -> Vec<(&Rule, String)> {
+    let mut found = Vec::new();
+    for stack in context.stacks.iter() {
+        for (index, rule) in stack.rules.iter().enumerate() {
+            if rule.control != Control::Requisite {
+                continue;
+            }
+            let failed_by = stack.paths.paths.iter().find_map(|path| {
+                path.outcomes.get(&index)?;
+                // the first required rule to fail before the requisite one ran
+                path.trace.steps.iter().find_map(|step| match step.outcome {
+                    Outcome::Invoked {
+                        action: Action::Bad,
+                        ..
+                    } if step.index < index
+                        && stack.rules[step.index].control == Control::Required =>
+                    {
+                        Some(&stack.rules[step.index])
+                    }
+                    _ => None,
+                })
+            });
+            if let Some(failed_by) = failed_by {
+                found.push((
+                    rule,
+                    format!(
+                        "Can run after required rule {} has already failed the stack, if this fails too the rest of the stack is skipped, which gives away which module failed",
+                        describe_rule(failed_by)
+                    ),
+                ));
+            }
+        }
+    }
+    found
+}
@@ -1,38 +1,1427 @@
-use enum_iterator::all;
-use log::info;
+use clap::{Args, Parser, Subcommand, ValueEnum};
+use pam_explainer::catalog::ServiceCatalog;
+use pam_explainer::outcome::{CallbackOutcome, TerminalPrompt};
 use pam_explainer::*;
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::fmt::Display;
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+use std::str::FromStr;
+use std::sync::OnceLock;
 
-fn main() {
-    #[cfg(feature = "cli")]
-    pretty_env_logger::init();
+/// Everything went fine, and there was nothing to report
+const EXIT_OK: u8 = 0;
+/// Lint found problems, the configs are different, or a simulation didn't give the expected result
+const EXIT_FINDINGS: u8 = 1;
+/// The input couldn't be read or loaded, clap also uses this for bad arguments
+const EXIT_ERROR: u8 = 2;
+
+/// What the label is for input read from stdin
+const STDIN: &str = "<stdin>";
+
+/// Explains what a PAM configuration does.
+#[derive(Parser)]
+#[command(version, about)]
+struct Cli {
+    /// How to write the output
+    #[arg(long, value_enum, default_value_t = Format::Text, global = true)]
+    format: Format,
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq, ValueEnum)]
+enum Format {
+    Text,
+    Json,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Walk through what each rule does, for every facility
+    Explain {
+        #[command(flatten)]
+        input: InputArgs,
+    },
+    /// Run the stacks with the modules returning what you say they do
+    Simulate {
+        #[command(flatten)]
+        input: InputArgs,
+        #[command(flatten)]
+        outcomes: OutcomeArgs,
+        /// What every facility should hand back, exits with 1 if any of them don't
+        #[arg(long, default_value = "success", value_parser = ReturnCode::from_str)]
+        expect: ReturnCode,
+    },
+    /// Check the config for problems, exits with 1 if there are any warnings or errors
+    Lint {
+        #[command(flatten)]
+        input: InputArgs,
+        /// Don't run the check with this ID, can be given more than once
+        #[arg(long = "allow", value_parser = parse_check)]
+        allow: Vec<String>,
+    },
+    /// Find ways auth can succeed without a credential being checked, exits with 1 if there are any
+    Bypass {
+        #[command(flatten)]
+        input: InputArgs,
+        /// Another module that checks a credential, like pam_mycompany.so, can be given more than once
+        #[arg(long = "credential-module")]
+        credential_modules: Vec<String>,
+    },
+    /// Check that every way auth can succeed needs more than one kind of factor, exits with 1 if one doesn't
+    Mfa {
+        #[command(flatten)]
+        input: InputArgs,
+        /// What a module checks, like pam_mycompany.so=possession, can be given more than once
+        #[arg(long = "factor", value_parser = parse_factor)]
+        factors: Vec<(String, mfa::Factor)>,
+        /// How many different kinds of factor every way to succeed needs
+        #[arg(long, default_value_t = 2)]
+        min_factors: usize,
+    },
+    /// Which modules each facility depends on, the ones that have to succeed, the ones that never matter and the smallest sets that are enough
+    Critical {
+        #[command(flatten)]
+        input: InputArgs,
+    },
+    /// Whether local users, root and directory users can still log in to each service during an outage, exits with 1 if any get locked out
+    Outage {
+        #[command(flatten)]
+        input: InputArgs,
+        /// The outage to try, can be given more than once, every one is tried if none are
+        #[arg(long = "outage", value_parser = parse_outage)]
+        outages: Vec<&'static outage::Outage>,
+    },
+    /// Compare what two configs do for every combination of module results, exits with 1 if they're different
+    Diff {
+        #[command(flatten)]
+        input: InputArgs,
+        /// The config to compare it with, in the same form as the first one
+        other: PathBuf,
+        /// Show the rules that were added and removed instead
+        #[arg(long)]
+        lines: bool,
+    },
+    /// What each facility hands back for every combination of module results, with a - where the result doesn't matter
+    TruthTable {
+        #[command(flatten)]
+        input: InputArgs,
+        /// Give up on a stack after this many ways through it, the table can double in size with each rule
+        #[arg(long, default_value_t = paths::MAX_PATHS)]
+        max_paths: usize,
+    },
+    /// Write the config back out with its includes inlined
+    Export {
+        #[command(flatten)]
+        input: InputArgs,
+        /// Write a single pam.conf, with the service at the start of each rule
+        #[arg(long, conflicts_with = "output")]
+        to_pam_conf: bool,
+        /// Write a pam.d directory here, with a file for each service
+        #[arg(long)]
+        output: Option<PathBuf>,
+    },
+}
+
+#[derive(Args)]
+struct InputArgs {
+    /// A service file, a pam.d directory, or - to read from stdin
+    input: PathBuf,
+    /// The input is a pam.conf, with the service at the start of each rule
+    #[arg(long)]
+    pam_conf: bool,
+    /// Only look at this service, falling back to "other" like libpam does
+    #[arg(long)]
+    service: Option<String>,
+    /// Only look at this facility
+    #[arg(long, value_parser = parse_facility)]
+    facility: Option<Facility>,
+}
+
+#[derive(Args)]
+struct OutcomeArgs {
+    /// A JSON file saying what the modules return, or - to read it from stdin
+    #[arg(long)]
+    scenario: Option<PathBuf>,
+    /// What a module returns, like pam_ldap.so=authinfo_unavail, can be given more than once
+    #[arg(long = "outcome", value_parser = parse_outcome)]
+    outcomes: Vec<(String, ReturnCode)>,
+    /// What any module that isn't otherwise mentioned returns
+    #[arg(long, value_parser = ReturnCode::from_str)]
+    default: Option<ReturnCode>,
+    /// Ask what any module that isn't otherwise mentioned returns, instead of assuming it failed
+    #[arg(long)]
+    interactive: bool,
+}
+
+fn parse_facility(value: &str) -> Result<Facility, String> {
+    match Facility::from(value) {
+        Facility::Invalid(value) => Err(format!("unknown facility '{}'", value)),
+        facility => Ok(facility),
+    }
+}
+
+fn parse_check(value: &str) -> Result<String, String> {
+    match lint::check(value) {
+        Some(check) => Ok(check.id.to_string()),
+        None if value == lint::INVALID_ARGUMENT => Ok(value.to_string()),
+        None => Err(format!(
+            "unknown check '{}', the checks are {}",
+            value,
+            lint::CHECKS
+                .iter()
+                .map(|check| check.id)
+                .chain([lint::INVALID_ARGUMENT])
+                .collect::<Vec<&str>>()
+                .join(", ")
+        )),
+    }
+}
+
+fn parse_outage(value: &str) -> Result<&'static outage::Outage, String> {
+    outage::outage(value).ok_or_else(|| {
+        format!(
+            "unknown outage '{}', the outages are {}",
+            value,
+            outage::OUTAGES
+                .iter()
+                .map(|outage| outage.id)
+                .collect::<Vec<&str>>()
+                .join(", ")
+        )
+    })
+}
+
+fn parse_factor(value: &str) -> Result<(String, mfa::Factor), String> {
+    let (module, factor) = value
+        .split_once('=')
+        .ok_or_else(|| format!("'{}' should be in the form module=factor", value))?;
+    Ok((module.to_string(), mfa::Factor::from_str(factor)?))
+}
+
+fn parse_outcome(value: &str) -> Result<(String, ReturnCode), String> {
+    let (module, code) = value
+        .split_once('=')
+        .ok_or_else(|| format!("'{}' should be in the form module=code", value))?;
+    let code = ReturnCode::from_str(code).map_err(|err| err.to_string())?;
+    Ok((module.to_string(), code))
+}
+
+/// Why a command couldn't run.
+#[derive(Debug)]
+enum CliError {
+    Load(LoadError),
+    /// There's no file for the service, and no "other" to fall back to
+    NoService(String),
+    /// Both the config and the scenario were meant to come from stdin
+    StdinTwice,
+    Output(std::io::Error),
+    Json(serde_json::Error),
+}
+
+impl Display for CliError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            CliError::Load(err) => write!(f, "{}", err),
+            CliError::NoService(name) => write!(f, "No service '{}', and no 'other'", name),
+            CliError::StdinTwice => write!(f, "Only one of the config and scenario can be stdin"),
+            CliError::Output(err) => write!(f, "Failed to write output: {}", err),
+            CliError::Json(err) => write!(f, "Failed to write JSON: {}", err),
+        }
+    }
+}
+
+impl From<LoadError> for CliError {
+    fn from(err: LoadError) -> Self {
+        CliError::Load(err)
+    }
+}
+
+impl From<serde_json::Error> for CliError {
+    fn from(err: serde_json::Error) -> Self {
+        CliError::Json(err)
+    }
+}
+
+/// The services in the input, with every include resolved.
+struct Loaded {
+    services: BTreeMap<String, Vec<Rule>>,
+    diagnostics: Vec<ParseError>,
+    /// Services that couldn't be loaded at all
+    failures: BTreeMap<String, LoadError>,
+}
+
+fn is_stdin(path: &Path) -> bool {
+    path == Path::new("-")
+}
+
+/// Everything on stdin. It can only be read once, so it's kept for anything else that wants it.
+fn read_stdin() -> Result<String, LoadError> {
+    static TEXT: OnceLock<String> = OnceLock::new();
+    if let Some(text) = TEXT.get() {
+        return Ok(text.clone());
+    }
+    let mut text = String::new();
+    std::io::Read::read_to_string(&mut std::io::stdin(), &mut text)?;
+    Ok(TEXT.get_or_init(|| text).clone())
+}
+
+/// Load a service file, pam.d directory or pam.conf, a single file is named after the file.
+fn load(path: &Path, pam_conf: bool, results: &[Rule]) -> Result<Loaded, LoadError> {
+    let label = match is_stdin(path) {
+        true => STDIN.to_string(),
+        false => path.display().to_string(),
+    };
+    if pam_conf {
+        let (services, diagnostics) = match is_stdin(path) {
+            true => pamconf::parse_pam_conf(&read_stdin()?, Some(&label), results),
+            false => pamconf::load_pam_conf(path, results)?,
+        };
+        return Ok(Loaded {
+            services,
+            diagnostics,
+            failures: BTreeMap::new(),
+        });
+    }
+    if path.is_dir() {
+        let catalog = ServiceCatalog::load_dir(path, results)?;
+        return Ok(Loaded {
+            services: catalog
+                .services
+                .into_iter()
+                .map(|(name, service)| (name, service.rules))
+                .collect(),
+            diagnostics: catalog.diagnostics,
+            failures: catalog.failures,
+        });
+    }
+    let (rules, diagnostics) = match is_stdin(path) {
+        true => parse_config(&read_stdin()?, Some(&label), results),
+        false => load_config(path, results)?,
+    };
+    let name = path
+        .file_name()
+        .filter(|_| !is_stdin(path))
+        .map(|name| name.to_string_lossy().to_string())
+        .unwrap_or(label);
+    Ok(Loaded {
+        services: BTreeMap::from([(name, rules)]),
+        diagnostics,
+        failures: BTreeMap::new(),
+    })
+}
+
+impl InputArgs {
+    /// Load the input, keeping only the service that was asked for.
+    fn load(&self, results: &[Rule]) -> Result<Loaded, CliError> {
+        let loaded = load(&self.input, self.pam_conf, results)?;
+        self.select(loaded)
+    }
+
+    fn select(&self, mut loaded: Loaded) -> Result<Loaded, CliError> {
+        let Some(name) = &self.service else {
+            return Ok(loaded);
+        };
+        // pam_start lowercases the service name before looking for the file
+        let name = name.to_lowercase();
+        if let Some(err) = loaded.failures.remove(&name) {
+            return Err(err.into());
+        }
+        let rules = loaded
+            .services
+            .remove(&name)
+            .or_else(|| loaded.services.remove(catalog::OTHER_SERVICE))
+            .ok_or_else(|| CliError::NoService(name.clone()))?;
+        loaded.services = BTreeMap::from([(name, rules)]);
+        loaded.failures.clear();
+        Ok(loaded)
+    }
+
+    /// The input's files as they are, keyed by service, for converting without losing comments or layout. If a service was asked for it's just that one and whatever it includes.
+    fn files(&self) -> Result<BTreeMap<String, String>, CliError> {
+        let path = &self.input;
+        let read = |path: &Path| match is_stdin(path) {
+            true => read_stdin(),
+            false => std::fs::read_to_string(path).map_err(|source| LoadError::Io {
+                path: Some(path.to_path_buf()),
+                source,
+            }),
+        };
+        let (files, service) = if self.pam_conf {
+            (pamconf::to_pam_d(&read(path)?), self.service.as_ref())
+        } else if path.is_dir() {
+            (pamconf::read_pam_d(path)?, self.service.as_ref())
+        } else if is_stdin(path) {
+            (BTreeMap::from([(STDIN.to_string(), read_stdin()?)]), None)
+        } else {
+            // a single file, with what it includes from the directory it's in
+            let name = path
+                .file_name()
+                .map(|name| name.to_string_lossy().to_string())
+                .unwrap_or_default();
+            let text = read(path)?;
+            let dir = path.parent().unwrap_or(Path::new(""));
+            let files = pamconf::service_files(&name, |service| match service == name {
+                true => Some(text.clone()),
+                false => std::fs::read_to_string(dir.join(service)).ok(),
+            });
+            (files, None)
+        };
+        let mut files = match service {
+            Some(name) => {
+                // pam_start lowercases the service name before looking for the file
+                let name = name.to_lowercase();
+                let service = [name.as_str(), catalog::OTHER_SERVICE]
+                    .into_iter()
+                    .find(|service| files.contains_key(*service))
+                    .ok_or_else(|| CliError::NoService(name.clone()))?;
+                pamconf::service_files(service, |service| files.get(service).cloned())
+            }
+            None => files,
+        };
+        if let Some(facility) = &self.facility {
+            for contents in files.values_mut() {
+                *contents = only_facility(contents, facility);
+            }
+        }
+        Ok(files)
+    }
+
+    /// The facilities to look at, in the order libpam's documentation lists them.
+    fn facilities(&self) -> Vec<Facility> {
+        match &self.facility {
+            Some(facility) => vec![facility.clone()],
+            None => [
+                Facility::Auth,
+                Facility::Account,
+                Facility::Password,
+                Facility::Session,
+            ]
+            .to_vec(),
+        }
+    }
+
+    /// Each service's stack for each facility it has rules for.
+    fn stacks<'a>(&self, loaded: &'a Loaded) -> Vec<(&'a str, Facility, Vec<Rule>)> {
+        let mut stacks = Vec::new();
+        for (name, rules) in loaded.services.iter() {
+            for facility in self.facilities() {
+                let stack: Vec<Rule> = rules
+                    .iter()
+                    .filter(|rule| rule.facility == facility)
+                    .cloned()
+                    .collect();
+                if !stack.is_empty() {
+                    stacks.push((name.as_str(), facility, stack));
+                }
+            }
+        }
+        stacks
+    }
+}
+
+fn print_json(value: &impl Serialize) -> Result<(), CliError> {
+    println!("{}", serde_json::to_string_pretty(value)?);
+    Ok(())
+}
+
+/// Problems with the input go to stderr so they don't end up mixed in with the output.
+fn print_problems(loaded: &Loaded) {
+    for diagnostic in loaded.diagnostics.iter() {
+        eprintln!("{}", diagnostic);
+    }
+    for (name, failure) in loaded.failures.iter() {
+        eprintln!("{}: {}", name, failure);
+    }
+}
+
+/// How the rule is numbered in the output, its place in the facility's stack with any includes inlined.
+fn number(index: usize) -> String {
+    format!("#{}", index)
+}
+
+fn indent(rule: &Rule) -> String {
+    "  ".repeat(rule.stack_level)
+}
+
+#[derive(Serialize)]
+struct RuleExplanation {
+    rule: Rule,
+    /// What happens when the module succeeds
+    on_success: Option<Action>,
+    /// What happens when the module fails the way modules in the facility usually do
+    on_failure: Option<Action>,
+    explanation: String,
+    /// What's known about the module, if it's a well-known one
+    module: Option<&'static modules::ModuleInfo>,
+}
+
+#[derive(Serialize)]
+struct StackExplanation {
+    service: String,
+    facility: Facility,
+    rules: Vec<RuleExplanation>,
+    /// What the facility returns if every module succeeds, apart from those that always return the same thing like `pam_deny.so`
+    all_succeed: ReturnCode,
+    /// What the facility returns if every module fails, apart from those that always return the same thing like `pam_permit.so`
+    all_fail: ReturnCode,
+}
+
+fn explain_rule(rule: &Rule) -> RuleExplanation {
+    let failure = rule.facility.failure_code();
+    let actions = rule.control.actions();
+    let on_success = actions.as_ref().map(|a| a.action_for(ReturnCode::Success));
+    let on_failure = actions.as_ref().map(|a| a.action_for(failure));
+    let explanation = match (&rule.control, on_success, on_failure) {
+        (Control::Include | Control::Substack, _, _) => rule.result_string(),
+        (Control::Invalid(value), _, _) => format!(
+            "libpam can't parse the control '{}', so every result counts as a failure.",
+            value
+        ),
+        (_, Some(success), Some(fail)) => format!(
+            "On success ({}), {} On {} ({}), {}",
+            success,
+            success.explain(),
+            failure,
+            fail,
+            fail.explain()
+        ),
+        _ => String::new(),
+    };
+    RuleExplanation {
+        rule: rule.clone(),
+        on_success,
+        on_failure,
+        explanation,
+        module: modules::module_info(&rule.module),
+    }
+}
+
+fn explain(input: &InputArgs, format: Format) -> Result<u8, CliError> {
+    let loaded = input.load(&[])?;
+    print_problems(&loaded);
+    let explanations: Vec<StackExplanation> = input
+        .stacks(&loaded)
+        .into_iter()
+        .map(|(name, facility, stack)| StackExplanation {
+            service: name.to_string(),
+            facility: facility.clone(),
+            rules: stack.iter().map(explain_rule).collect(),
+            all_succeed: RuleSet::new(&facility, stack.clone())
+                .run_rules_with(&mut CallbackOutcome(|rule: &Rule| {
+                    Some(paths::fixed_outcome(rule).unwrap_or(ReturnCode::Success))
+                }))
+                .result,
+            all_fail: RuleSet::new(&facility, stack)
+                .run_rules_with(&mut CallbackOutcome(|rule: &Rule| {
+                    Some(paths::fixed_outcome(rule).unwrap_or(rule.facility.failure_code()))
+                }))
+                .result,
+        })
+        .collect();
+
+    if format == Format::Json {
+        print_json(&explanations)?;
+        return Ok(EXIT_OK);
+    }
+    for stack in explanations.iter() {
+        println!("{} {}:", stack.service, stack.facility);
+        for (index, explanation) in stack.rules.iter().enumerate() {
+            let rule = &explanation.rule;
+            println!(
+                "  {}{} {}  ({})",
+                indent(rule),
+                number(index),
+                rule.to_config_line(),
+                rule.source
+            );
+            println!("  {}    {}", indent(rule), explanation.explanation);
+            let Some(info) = explanation.module else {
+                continue;
+            };
+            println!("  {}    {}", indent(rule), info.description);
+            if !info.supports(&rule.facility) && !matches!(rule.facility, Facility::Invalid(_)) {
+                let facilities: Vec<String> = info
+                    .facilities()
+                    .map(|facility| facility.to_string())
+                    .collect();
+                println!(
+                    "  {}    It doesn't implement {}, only {}.",
+                    indent(rule),
+                    rule.facility,
+                    facilities.join(", ")
+                );
+            }
+            for argument in rule.arguments.iter() {
+                if let Some(known) = info.argument(argument) {
+                    println!(
+                        "  {}      {}: {}",
+                        indent(rule),
+                        argument,
+                        known.description
+                    );
+                }
+            }
+        }
+        println!(
+            "  If every module that can succeed does, {} returns {}.",
+            stack.facility, stack.all_succeed
+        );
+        println!(
+            "  If every module that can fail does, {} returns {}.",
+            stack.facility, stack.all_fail
+        );
+        println!();
+    }
+    Ok(EXIT_OK)
+}
+
+#[derive(Serialize)]
+struct Simulation {
+    service: String,
+    facility: Facility,
+    rules: Vec<Rule>,
+    trace: Trace,
+}
+
+impl OutcomeArgs {
+    fn scenario(&self, input: &Path) -> Result<Scenario, CliError> {
+        let mut scenario = match &self.scenario {
+            Some(path) if is_stdin(path) => {
+                if is_stdin(input) {
+                    return Err(CliError::StdinTwice);
+                }
+                Scenario::read(std::io::stdin())?
+            }
+            Some(path) => Scenario::load(path)?,
+            None => Scenario::default(),
+        };
+        scenario.modules.extend(self.outcomes.iter().cloned());
+        if self.default.is_some() {
+            scenario.default = self.default;
+        }
+        Ok(scenario)
+    }
+}
+
+fn simulate(
+    input: &InputArgs,
+    outcomes: &OutcomeArgs,
+    expect: ReturnCode,
+    format: Format,
+) -> Result<u8, CliError> {
+    let mut scenario = outcomes.scenario(&input.input)?;
+    let loaded = input.load(&scenario.rules)?;
+    print_problems(&loaded);
+
+    let mut simulations = Vec::new();
+    for (name, facility, stack) in input.stacks(&loaded) {
+        let mut ruleset = RuleSet::new(&facility, stack);
+        let mut provider = CallbackOutcome(|rule: &Rule| {
+            scenario
+                .outcome(rule)
+                .or_else(|| match outcomes.interactive {
+                    true => TerminalPrompt.outcome(rule),
+                    false => None,
+                })
+        });
+        let trace = ruleset.run_rules_with(&mut provider);
+        simulations.push(Simulation {
+            service: name.to_string(),
+            facility,
+            rules: ruleset.rules,
+            trace,
+        });
+    }
+    let status = match simulations.iter().all(|sim| sim.trace.result == expect) {
+        true => EXIT_OK,
+        false => EXIT_FINDINGS,
+    };
+
+    if format == Format::Json {
+        print_json(&simulations)?;
+        return Ok(status);
+    }
+    for sim in simulations.iter() {
+        println!(
+            "{} {}: {} (ran {} of {} rules)",
+            sim.service,
+            sim.facility,
+            sim.trace.result,
+            sim.trace.rules_run(),
+            sim.rules.len()
+        );
+        for step in sim.trace.steps.iter() {
+            let rule = &sim.rules[step.index];
+            println!(
+                "  {}{} {}",
+                indent(rule),
+                number(step.index),
+                rule.to_config_line()
+            );
+            println!(
+                "  {}    {} Stack is {}.",
+                indent(rule),
+                step.explain(&sim.rules),
+                step.state
+            );
+        }
+        println!();
+    }
+    Ok(status)
+}
 
-    let file = match load_file() {
-        Ok(val) => val,
-        Err(_) => return,
+#[derive(Serialize)]
+struct ServiceLint {
+    /// Problems found while parsing aren't tied to a service
+    service: Option<String>,
+    #[serde(flatten)]
+    lint: lint::Lint,
+}
+
+#[derive(Serialize)]
+struct LintReport {
+    lints: Vec<ServiceLint>,
+    /// Services that couldn't be loaded, and why
+    failures: BTreeMap<String, String>,
+}
+
+fn lint(input: &InputArgs, allow: &[String], format: Format) -> Result<u8, CliError> {
+    let loaded = input.load(&[])?;
+    let mut lints: Vec<ServiceLint> = lint::lint_diagnostics(&loaded.diagnostics)
+        .into_iter()
+        .filter(|found| !allow.iter().any(|id| id == found.id))
+        .map(|lint| ServiceLint {
+            service: None,
+            lint,
+        })
+        .collect();
+    for (name, rules) in loaded.services.iter() {
+        for found in lint::lint_service(name, rules) {
+            // a file included by several services would be reported for each of them
+            if allow.iter().any(|id| id == found.id)
+                || lints
+                    .iter()
+                    .any(|seen| seen.lint.id == found.id && seen.lint.span == found.span)
+            {
+                continue;
+            }
+            lints.push(ServiceLint {
+                service: Some(name.clone()),
+                lint: found,
+            });
+        }
+    }
+    let report = LintReport {
+        lints,
+        failures: loaded
+            .failures
+            .iter()
+            .map(|(name, err)| (name.clone(), err.to_string()))
+            .collect(),
+    };
+    let failed = report
+        .lints
+        .iter()
+        .any(|found| found.lint.severity >= lint::Severity::Warning);
+    let status = match failed || !report.failures.is_empty() {
+        true => EXIT_FINDINGS,
+        false => EXIT_OK,
+    };
+
+    if format == Format::Json {
+        print_json(&report)?;
+        return Ok(status);
+    }
+    for found in report.lints.iter() {
+        println!("{}", found.lint);
+    }
+    for (name, failure) in report.failures.iter() {
+        println!("{}: {}", name, failure);
+    }
+    Ok(status)
+}
+
+#[derive(Serialize)]
+struct ServiceBypasses {
+    service: String,
+    #[serde(flatten)]
+    report: bypass::BypassReport,
+}
+
+fn find_bypasses(input: &InputArgs, extra: &[String], format: Format) -> Result<u8, CliError> {
+    let loaded = input.load(&[])?;
+    print_problems(&loaded);
+    // credentials are checked by auth, unless another facility was asked for
+    let facility = input.facility.clone().unwrap_or(Facility::Auth);
+    let reports: Vec<ServiceBypasses> = input
+        .stacks(&loaded)
+        .into_iter()
+        .filter(|(_, stack_facility, _)| stack_facility == &facility)
+        .map(|(name, facility, stack)| ServiceBypasses {
+            service: name.to_string(),
+            report: bypass::find_bypasses(&facility, &stack, extra),
+        })
+        .collect();
+    let status = match reports.iter().all(|found| found.report.bypasses.is_empty()) {
+        true => EXIT_OK,
+        false => EXIT_FINDINGS,
     };
 
-    let rules = rules_from_vec_string(file);
+    if format == Format::Json {
+        print_json(&reports)?;
+        return Ok(status);
+    }
+    for found in reports.iter() {
+        let report = &found.report;
+        match report.bypasses.len() {
+            0 => println!(
+                "{} {}: every way to succeed checks a credential",
+                found.service, report.facility
+            ),
+            count => println!(
+                "{} {}: {} way(s) to succeed without checking a credential",
+                found.service, report.facility, count
+            ),
+        }
+        for (position, bypass) in report.bypasses.iter().enumerate() {
+            println!("  {}. when:", position + 1);
+            print_path(&report.rules, &bypass.path, |index| {
+                bypass
+                    .failed_checks
+                    .contains(&index)
+                    .then(|| "the credential check fails".to_string())
+            });
+        }
+        if !report.complete {
+            println!(
+                "  The stack has more than {} ways through it, there may be more.",
+                paths::MAX_PATHS
+            );
+        }
+    }
+    Ok(status)
+}
+
+/// Print what each module returned on a path, marking the rules `note` has something to say about.
+fn print_path(rules: &[Rule], path: &paths::Path, note: impl Fn(usize) -> Option<String>) {
+    for (index, code) in path.outcomes.iter() {
+        let rule = &rules[*index];
+        println!(
+            "     {}{} {} returns {}{}",
+            indent(rule),
+            number(*index),
+            rule.to_config_line(),
+            code,
+            note(*index)
+                .map(|note| format!(" ({})", note))
+                .unwrap_or_default()
+        );
+    }
+}
+
+#[derive(Serialize)]
+struct ServiceMfa {
+    service: String,
+    #[serde(flatten)]
+    report: mfa::MfaReport,
+}
+
+fn verify_mfa(
+    input: &InputArgs,
+    factors: &[(String, mfa::Factor)],
+    min_factors: usize,
+    format: Format,
+) -> Result<u8, CliError> {
+    let loaded = input.load(&[])?;
+    print_problems(&loaded);
+    let facility = input.facility.clone().unwrap_or(Facility::Auth);
+    let reports: Vec<ServiceMfa> = input
+        .stacks(&loaded)
+        .into_iter()
+        .filter(|(_, stack_facility, _)| stack_facility == &facility)
+        .map(|(name, facility, stack)| ServiceMfa {
+            service: name.to_string(),
+            report: mfa::verify(&facility, &stack, factors, min_factors),
+        })
+        .collect();
+    let status = match reports
+        .iter()
+        .all(|found| found.report.violations.is_empty())
+    {
+        true => EXIT_OK,
+        false => EXIT_FINDINGS,
+    };
 
-    for facility in all::<Facility>().collect::<Vec<_>>() {
-        let f_rules = rules.clone();
-        // filter out the ones we want
-        let mut rules: Vec<Rule> = f_rules
-            .into_iter()
-            .filter_map(|r| {
-                if r.facility == facility {
-                    Some(r)
-                } else {
-                    None
+    if format == Format::Json {
+        print_json(&reports)?;
+        return Ok(status);
+    }
+    for found in reports.iter() {
+        let report = &found.report;
+        match report.violations.len() {
+            0 => println!(
+                "{} {}: every way to succeed checks at least {} kinds of factor",
+                found.service, report.facility, report.required
+            ),
+            count => println!(
+                "{} {}: {} way(s) to succeed with fewer than {} kinds of factor",
+                found.service, report.facility, count, report.required
+            ),
+        }
+        for (position, violation) in report.violations.iter().enumerate() {
+            let checked: Vec<String> = violation.factors.iter().map(|f| f.to_string()).collect();
+            match checked.is_empty() {
+                true => println!("  {}. no factor checked, when:", position + 1),
+                false => println!(
+                    "  {}. only {} checked, when:",
+                    position + 1,
+                    checked.join(" and ")
+                ),
+            }
+            print_path(&report.rules, &violation.path, |index| {
+                mfa::factor(&report.rules[index], factors).map(|factor| factor.to_string())
+            });
+        }
+        if !report.complete {
+            println!(
+                "  The stack has more than {} ways through it, there may be more.",
+                paths::MAX_PATHS
+            );
+        }
+    }
+    Ok(status)
+}
+
+#[derive(Serialize)]
+struct ServiceCriticality {
+    service: String,
+    #[serde(flatten)]
+    report: criticality::Criticality,
+}
+
+fn criticality(input: &InputArgs, format: Format) -> Result<u8, CliError> {
+    let loaded = input.load(&[])?;
+    print_problems(&loaded);
+    let reports: Vec<ServiceCriticality> = input
+        .stacks(&loaded)
+        .into_iter()
+        .map(|(name, facility, stack)| ServiceCriticality {
+            service: name.to_string(),
+            report: RuleSet::new(&facility, stack).criticality(),
+        })
+        .collect();
+
+    if format == Format::Json {
+        print_json(&reports)?;
+        return Ok(EXIT_OK);
+    }
+    for found in reports.iter() {
+        let report = &found.report;
+        println!("{} {}:", found.service, report.facility);
+        if !report.can_succeed {
+            println!("  Can't succeed, whatever the modules return.");
+            continue;
+        }
+        let describe = |index: &usize| {
+            let rule = &report.rules[*index];
+            format!("{} {}", number(*index), rule.module)
+        };
+        let list = |rules: &[usize]| match rules.is_empty() {
+            true => "none".to_string(),
+            false => rules
+                .iter()
+                .map(describe)
+                .collect::<Vec<String>>()
+                .join(", "),
+        };
+        println!("  Must succeed: {}", list(&report.must_succeed));
+        println!("  Never change the result: {}", list(&report.never_matters));
+        println!("  Enough to succeed on their own:");
+        for set in report.sufficient.iter() {
+            match set.is_empty() {
+                true => println!("    nothing, it succeeds whatever the modules return"),
+                false => println!("    {}", list(set)),
+            }
+        }
+        if report.sufficient.is_empty() {
+            println!("    none, it only succeeds when some modules fail");
+        }
+        if !report.complete {
+            println!(
+                "    There are more than {} sets, only the smallest are shown.",
+                criticality::MAX_SUFFICIENT
+            );
+        }
+    }
+    Ok(EXIT_OK)
+}
+
+#[derive(Serialize)]
+struct OutageReport {
+    outage: outage::Outage,
+    services: Vec<outage::ServiceImpact>,
+}
+
+fn outage_impact(
+    input: &InputArgs,
+    outages: &[&'static outage::Outage],
+    format: Format,
+) -> Result<u8, CliError> {
+    let loaded = input.load(&[])?;
+    print_problems(&loaded);
+    let outages = match outages.is_empty() {
+        true => outage::OUTAGES.iter().collect(),
+        false => outages.to_vec(),
+    };
+    let reports: Vec<OutageReport> = outages
+        .into_iter()
+        .map(|outage| OutageReport {
+            outage: *outage,
+            services: loaded
+                .services
+                .iter()
+                .map(|(name, rules)| outage::ServiceImpact {
+                    service: name.clone(),
+                    accounts: outage::impact(rules, outage),
+                })
+                .collect(),
+        })
+        .collect();
+    let status = match reports.iter().any(|report| {
+        report
+            .services
+            .iter()
+            .any(|service| service.accounts.iter().any(|account| account.locked_out()))
+    }) {
+        true => EXIT_FINDINGS,
+        false => EXIT_OK,
+    };
+
+    if format == Format::Json {
+        print_json(&reports)?;
+        return Ok(status);
+    }
+    let describe = |login: &outage::Login| match login.allowed {
+        true => "yes".to_string(),
+        false => {
+            let failed: Vec<String> = login
+                .results
+                .iter()
+                .filter(|(_, result)| *result != ReturnCode::Success)
+                .map(|(facility, result)| format!("{} {}", facility, result))
+                .collect();
+            format!("no, {}", failed.join(", "))
+        }
+    };
+    for report in reports.iter() {
+        println!(
+            "{}, with {} returning authinfo_unavail:",
+            report.outage.name,
+            report.outage.modules.join(", ")
+        );
+        for service in report.services.iter() {
+            println!("  {}:", service.service);
+            for impact in service.accounts.iter() {
+                match impact.before == impact.during {
+                    true => println!("    {}: {}", impact.account, describe(&impact.during)),
+                    false => println!(
+                        "    {}: {} (was {})",
+                        impact.account,
+                        describe(&impact.during),
+                        describe(&impact.before)
+                    ),
+                }
+            }
+        }
+    }
+    Ok(status)
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+enum Change {
+    Removed,
+    Added,
+}
+
+#[derive(Serialize)]
+struct RuleChange {
+    service: String,
+    change: Change,
+    rule: String,
+}
+
+/// The lines only in `old` or only in `new`, in order, from the longest common subsequence of the two.
+fn diff_lines(old: &[String], new: &[String]) -> Vec<(Change, String)> {
+    let mut common = vec![vec![0usize; new.len() + 1]; old.len() + 1];
+    for i in (0..old.len()).rev() {
+        for j in (0..new.len()).rev() {
+            common[i][j] = match old[i] == new[j] {
+                true => common[i + 1][j + 1] + 1,
+                false => common[i + 1][j].max(common[i][j + 1]),
+            };
+        }
+    }
+    let (mut i, mut j) = (0, 0);
+    let mut changes = Vec::new();
+    while i < old.len() || j < new.len() {
+        if i < old.len() && j < new.len() && old[i] == new[j] {
+            i += 1;
+            j += 1;
+        } else if j == new.len() || (i < old.len() && common[i + 1][j] >= common[i][j + 1]) {
+            changes.push((Change::Removed, old[i].clone()));
+            i += 1;
+        } else {
+            changes.push((Change::Added, new[j].clone()));
+            j += 1;
+        }
+    }
+    changes
+}
+
+#[derive(Serialize)]
+struct ServiceDiff {
+    service: String,
+    /// How many of the changes are to the result, rather than only how many rules were run
+    result_changes: usize,
+    #[serde(flatten)]
+    diff: diff::StackDiff,
+}
+
+fn diff(input: &InputArgs, other: &Path, lines: bool, format: Format) -> Result<u8, CliError> {
+    if is_stdin(&input.input) && is_stdin(other) {
+        return Err(CliError::StdinTwice);
+    }
+    let old = input.load(&[])?;
+    let new = input.select(load(other, input.pam_conf, &[])?)?;
+    print_problems(&old);
+    print_problems(&new);
+
+    // two single files are compared with each other, whatever they're called
+    let pairs: Vec<(String, Vec<Rule>, Vec<Rule>)> = match (old.services.len(), new.services.len())
+    {
+        (1, 1) if !input.input.is_dir() && !other.is_dir() => {
+            let (name, old_rules) = old.services.iter().next().expect("checked the length");
+            let new_rules = new.services.values().next().expect("checked the length");
+            vec![(name.clone(), old_rules.clone(), new_rules.clone())]
+        }
+        _ => {
+            // a service without a file gets "other", like libpam does
+            let service = |loaded: &Loaded, name: &String| -> Vec<Rule> {
+                loaded
+                    .services
+                    .get(name)
+                    .or_else(|| loaded.services.get(catalog::OTHER_SERVICE))
+                    .cloned()
+                    .unwrap_or_default()
+            };
+            let names: std::collections::BTreeSet<&String> =
+                old.services.keys().chain(new.services.keys()).collect();
+            names
+                .into_iter()
+                .map(|name| (name.clone(), service(&old, name), service(&new, name)))
+                .collect()
+        }
+    };
+
+    if lines {
+        return diff_rules(input, &pairs, format);
+    }
+    let diffs: Vec<ServiceDiff> = pairs
+        .iter()
+        .flat_map(|(name, old, new)| {
+            input.facilities().into_iter().filter_map(move |facility| {
+                let stack = |rules: &Vec<Rule>| -> Vec<Rule> {
+                    rules
+                        .iter()
+                        .filter(|rule| rule.facility == facility)
+                        .cloned()
+                        .collect()
+                };
+                let (old, new) = (stack(old), stack(new));
+                if old.is_empty() && new.is_empty() {
+                    return None;
+                }
+                let diff = diff::diff_stacks(&facility, &old, &new);
+                match diff.changes.is_empty() {
+                    true => None,
+                    false => Some(ServiceDiff {
+                        service: name.clone(),
+                        result_changes: diff
+                            .changes
+                            .iter()
+                            .filter(|change| change.result_changed())
+                            .count(),
+                        diff,
+                    }),
                 }
             })
-            .collect();
-        // sort them just to be sure
-        rules.sort_by_key(|item| item.rule_order);
-        let mut ruleset = RuleSet::new(&facility, rules);
-        let ruleset_result = ruleset.run_rules();
-        info!(
-            "{:?} -> {:?} (Ran {} rules)",
-            facility, ruleset_result, ruleset.rules_run
+        })
+        .collect();
+    let status = match diffs.is_empty() {
+        true => EXIT_OK,
+        false => EXIT_FINDINGS,
+    };
+
+    if format == Format::Json {
+        print_json(&diffs)?;
+        return Ok(status);
+    }
+    if diffs.is_empty() {
+        println!("No changes in behaviour.");
+    }
+    for found in diffs.iter() {
+        let diff = &found.diff;
+        println!(
+            "{} {}: {} combinations of module results end differently, {} with a different result",
+            found.service,
+            diff.facility,
+            diff.changes.len(),
+            found.result_changes
         );
+        for change in diff.changes.iter() {
+            let outcomes: Vec<String> = diff
+                .modules
+                .iter()
+                .zip(change.outcomes.iter())
+                .filter_map(|(module, code)| code.map(|code| format!("{}={}", module, code)))
+                .collect();
+            let outcomes = match outcomes.is_empty() {
+                true => "whatever the modules return".to_string(),
+                false => outcomes.join(" "),
+            };
+            println!(
+                "  {}: {} after {} rules, was {} after {}",
+                outcomes,
+                change.new.result,
+                change.new.rules_run,
+                change.old.result,
+                change.old.rules_run
+            );
+        }
+        if !diff.complete {
+            println!(
+                "  There are more than {} changes, only the first are shown.",
+                paths::MAX_PATHS
+            );
+        }
+    }
+    Ok(status)
+}
+
+/// The top level rules that were added and removed from each service.
+fn diff_rules(
+    input: &InputArgs,
+    pairs: &[(String, Vec<Rule>, Vec<Rule>)],
+    format: Format,
+) -> Result<u8, CliError> {
+    let lines = |rules: &Vec<Rule>| -> Vec<String> {
+        input
+            .facilities()
+            .iter()
+            .flat_map(|facility| rules.iter().filter(move |rule| &rule.facility == facility))
+            .filter(|rule| rule.stack_level == 0)
+            .map(|rule| rule.to_config_line())
+            .collect()
+    };
+    let changes: Vec<RuleChange> = pairs
+        .iter()
+        .flat_map(|(name, old, new)| {
+            diff_lines(&lines(old), &lines(new))
+                .into_iter()
+                .map(|(change, rule)| RuleChange {
+                    service: name.clone(),
+                    change,
+                    rule,
+                })
+        })
+        .collect();
+    let status = match changes.is_empty() {
+        true => EXIT_OK,
+        false => EXIT_FINDINGS,
+    };
+
+    if format == Format::Json {
+        print_json(&changes)?;
+        return Ok(status);
+    }
+    let mut last_service = None;
+    for change in changes.iter() {
+        if last_service != Some(&change.service) {
+            println!("{}:", change.service);
+            last_service = Some(&change.service);
+        }
+        let sign = match change.change {
+            Change::Removed => '-',
+            Change::Added => '+',
+        };
+        println!("{} {}", sign, change.rule);
+    }
+    Ok(status)
+}
+
+#[derive(Serialize)]
+struct ServiceTruthTable {
+    service: String,
+    #[serde(flatten)]
+    table: truth_table::TruthTable,
+}
+
+fn truth_table(input: &InputArgs, max_paths: usize, format: Format) -> Result<u8, CliError> {
+    let loaded = input.load(&[])?;
+    print_problems(&loaded);
+    let tables: Vec<ServiceTruthTable> = input
+        .stacks(&loaded)
+        .into_iter()
+        .map(|(name, facility, stack)| ServiceTruthTable {
+            service: name.to_string(),
+            table: RuleSet::new(&facility, stack).truth_table(max_paths),
+        })
+        .collect();
+
+    if format == Format::Json {
+        print_json(&tables)?;
+        return Ok(EXIT_OK);
+    }
+    for found in tables.iter() {
+        let table = &found.table;
+        println!("{} {}:", found.service, table.facility);
+        let numbers: Vec<String> = table.columns.iter().map(|index| number(*index)).collect();
+        for (index, number) in table.columns.iter().zip(numbers.iter()) {
+            println!("  {} {}", number, table.rules[*index].to_config_line());
+        }
+        let cells: Vec<Vec<String>> = table
+            .rows
+            .iter()
+            .map(|row| {
+                row.outcomes
+                    .iter()
+                    .map(|code| code.map_or("-".to_string(), |code| code.to_string()))
+                    .collect()
+            })
+            .collect();
+        let widths: Vec<usize> = numbers
+            .iter()
+            .enumerate()
+            .map(|(column, number)| {
+                cells
+                    .iter()
+                    .map(|row| row[column].len())
+                    .chain([number.len()])
+                    .max()
+                    .unwrap_or_default()
+            })
+            .collect();
+        let pad = |values: &[String]| -> String {
+            values
+                .iter()
+                .zip(widths.iter())
+                .map(|(value, width)| format!("{:width$}", value, width = width))
+                .collect::<Vec<String>>()
+                .join(" ")
+        };
+        println!("  {} result (rules run)", pad(&numbers));
+        for (row, cells) in table.rows.iter().zip(cells.iter()) {
+            println!("  {} {} ({})", pad(cells), row.result, row.rules_run);
+        }
+        if !table.complete {
+            println!(
+                "  The stack has more than {} ways through it, the table is incomplete.",
+                max_paths
+            );
+        }
+        println!();
+    }
+    Ok(EXIT_OK)
+}
+
+/// A service file with the rules for any other facility taken out, and everything else left as it is.
+fn only_facility(contents: &str, facility: &Facility) -> String {
+    let mut file = syntax::SyntaxFile::parse(contents);
+    file.entries.retain(|entry| {
+        entry.kind != syntax::EntryKind::Rule
+            || entry
+                .field(syntax::TokenKind::Facility)
+                .map(|field| Facility::from(field.text.trim_start_matches('-')))
+                .as_ref()
+                == Some(facility)
+    });
+    file.to_string()
+}
+
+fn export(
+    input: &InputArgs,
+    to_pam_conf: bool,
+    output: Option<&Path>,
+    format: Format,
+) -> Result<u8, CliError> {
+    let loaded = input.load(&[])?;
+    print_problems(&loaded);
+
+    if format == Format::Json {
+        print_json(&loaded.services)?;
+        return Ok(EXIT_OK);
+    }
+    // converted as text, so comments, blank lines and alignment are kept
+    let files = input.files()?;
+    if let Some(dir) = output {
+        pamconf::write_pam_d(dir, &files).map_err(CliError::Output)?;
+    } else if to_pam_conf {
+        print!("{}", pamconf::from_pam_d(&files)?);
+    } else if files.len() == 1 {
+        print!("{}", files.values().next().expect("checked the length"));
+    } else {
+        for (name, contents) in files.iter() {
+            println!("# {}", name);
+            println!("{}", contents);
+        }
+    }
+    Ok(EXIT_OK)
+}
+
+fn main() -> ExitCode {
+    pretty_env_logger::init();
+    let cli = Cli::parse();
+
+    let res = match &cli.command {
+        Command::Explain { input } => explain(input, cli.format),
+        Command::Simulate {
+            input,
+            outcomes,
+            expect,
+        } => simulate(input, outcomes, *expect, cli.format),
+        Command::Lint { input, allow } => lint(input, allow, cli.format),
+        Command::Bypass {
+            input,
+            credential_modules,
+        } => find_bypasses(input, credential_modules, cli.format),
+        Command::Mfa {
+            input,
+            factors,
+            min_factors,
+        } => verify_mfa(input, factors, *min_factors, cli.format),
+        Command::Critical { input } => criticality(input, cli.format),
+        Command::Outage { input, outages } => outage_impact(input, outages, cli.format),
+        Command::Diff {
+            input,
+            other,
+            lines,
+        } => diff(input, other, *lines, cli.format),
+        Command::TruthTable { input, max_paths } => truth_table(input, *max_paths, cli.format),
+        Command::Export {
+            input,
+            to_pam_conf,
+            output,
+        } => export(input, *to_pam_conf, output.as_deref(), cli.format),
+    };
+    match res {
+        Ok(status) => ExitCode::from(status),
+        Err(err) => {
+            eprintln!("{}", err);
+            ExitCode::from(EXIT_ERROR)
+        }
     }
 }
@@ -0,0 +1,111 @@
+//! Checking that every way to succeed needs more than one kind of authentication factor.
+
+use crate::modules::module_info;
+use crate::paths::{module_name, Path, MAX_PATHS};
+use crate::{Facility, ReturnCode, Rule};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeSet;
+use std::fmt::Display;
+use std::str::FromStr;
+
+/// The kind of proof a module checks.
+#[derive(Clone, Copy, Debug, Deserialize, Serialize, Eq, PartialEq, Hash, PartialOrd, Ord)]
+#[serde(rename_all = "lowercase")]
+pub enum Factor {
+    /// Something the user knows, like a password
+    Knowledge,
+    /// Something the user has, like a security key, phone or smart card
+    Possession,
+    /// Something the user is, like a fingerprint or face
+    Inherence,
+}
+
+impl Display for Factor {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Factor::Knowledge => write!(f, "knowledge"),
+            Factor::Possession => write!(f, "possession"),
+            Factor::Inherence => write!(f, "inherence"),
+        }
+    }
+}
+
+impl FromStr for Factor {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "knowledge" => Ok(Factor::Knowledge),
+            "possession" => Ok(Factor::Possession),
+            "inherence" => Ok(Factor::Inherence),
+            _ => Err(format!(
+                "unknown factor '{}', it should be knowledge, possession or inherence",
+                value
+            )),
+        }
+    }
+}
+
+/// The factor the rule's module checks, from its [ModuleInfo::factor](crate::modules::ModuleInfo::factor) or `extra`, which take precedence.
+pub fn factor(rule: &Rule, extra: &[(String, Factor)]) -> Option<Factor> {
+    let name = module_name(&rule.module);
+    extra
+        .iter()
+        .find(|(module, _)| module_name(module) == name)
+        .map(|(_, factor)| *factor)
+        .or_else(|| module_info(name)?.factor)
+}
+
+/// A way through the stack that succeeds with too few kinds of factor.
+#[derive(Clone, Debug, Serialize, Eq, PartialEq)]
+pub struct Violation {
+    /// The counterexample, what each module that was run returned
+    pub path: Path,
+    /// The factors that were checked on the way
+    pub factors: BTreeSet<Factor>,
+}
+
+#[derive(Clone, Debug, Serialize, Eq, PartialEq)]
+pub struct MfaReport {
+    pub facility: Facility,
+    pub rules: Vec<Rule>,
+    /// How many kinds of factor every way to succeed needs
+    pub required: usize,
+    pub violations: Vec<Violation>,
+    /// Every way through the stack was looked at, if not there may be more violations
+    pub complete: bool,
+}
+
+/// Every way through the stack that succeeds with fewer than `required` different factors checked, a factor only counts if its module succeeded.
+pub fn verify(
+    facility: &Facility,
+    rules: &[Rule],
+    extra: &[(String, Factor)],
+    required: usize,
+) -> MfaReport {
+    let paths = crate::paths::paths(facility, rules, MAX_PATHS);
+    let violations = paths
+        .paths
+        .into_iter()
+        .filter(|path| path.trace.result == ReturnCode::Success)
+        .filter_map(|path| {
+            let factors: BTreeSet<Factor> = path
+                .outcomes
+                .keys()
+                .filter(|index| path.succeeded(**index))
+                .filter_map(|index| factor(&rules[*index], extra))
+                .collect();
+            match factors.len() < required {
+                true => Some(Violation { path, factors }),
+                false => None,
+            }
+        })
+        .collect();
+    MfaReport {
+        facility: facility.clone(),
+        rules: rules.to_vec(),
+        required,
+        violations,
+        complete: paths.complete,
+    }
+}
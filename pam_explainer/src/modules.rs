@@ -0,0 +1,188 @@
+//! What's known about well-known modules, from Linux-PAM and elsewhere: what they do, the facilities they implement, their arguments and what they typically return.
+//!
+//! The data lives in `modules.json` next to this file, so adding a module doesn't need any code.
+
+use crate::mfa::Factor;
+use crate::paths::module_name;
+use crate::{Facility, ReturnCode};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fmt::Display;
+use std::sync::OnceLock;
+
+/// What kind of value an argument takes.
+#[derive(Clone, Copy, Debug, Deserialize, Serialize, Eq, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum ArgumentKind {
+    /// On its own, like `nullok`
+    Flag,
+    /// `name=number`, like `deny=3`
+    Integer,
+    /// `name=value`
+    String,
+    /// `name=/path/to/something`
+    Path,
+}
+
+impl Display for ArgumentKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ArgumentKind::Flag => write!(f, "flag"),
+            ArgumentKind::Integer => write!(f, "integer"),
+            ArgumentKind::String => write!(f, "string"),
+            ArgumentKind::Path => write!(f, "path"),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize, Eq, PartialEq)]
+pub struct ArgumentInfo {
+    pub name: String,
+    #[serde(rename = "type")]
+    pub kind: ArgumentKind,
+    pub description: String,
+    /// Words an integer argument takes as well as numbers, like `unlock_time=never`
+    #[serde(default)]
+    pub values: Vec<String>,
+    /// Why it shouldn't be used any more, if it shouldn't
+    #[serde(default)]
+    pub deprecated: Option<String>,
+    /// It can be given with nothing after the `=`, like `authtok_type=`
+    #[serde(default)]
+    pub empty: bool,
+    /// What the module can return only when it's given this, like `ignore` for users without a key with `pam_u2f.so nouserok`
+    #[serde(default)]
+    pub returns: Vec<ReturnCode>,
+}
+
+/// Why an argument isn't one the module will do what's expected with.
+#[derive(Clone, Debug, Deserialize, Serialize, Eq, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum ArgumentError {
+    Unknown,
+    /// It's `name` or `name=` when it needs a value
+    MissingValue(ArgumentKind),
+    NotAnInteger(String),
+    /// It's `name=value` when it's a flag
+    UnexpectedValue,
+    /// It's used with this other argument, which it can't be
+    Conflicts(String),
+    Deprecated(String),
+}
+
+impl Display for ArgumentError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ArgumentError::Unknown => write!(f, "the module doesn't take it, so it's ignored"),
+            ArgumentError::MissingValue(_) => write!(f, "it needs a value after the ="),
+            ArgumentError::NotAnInteger(value) => write!(f, "'{}' isn't a number", value),
+            ArgumentError::UnexpectedValue => write!(f, "it's a flag, it doesn't take a value"),
+            ArgumentError::Conflicts(other) => write!(f, "it can't be used with '{}'", other),
+            ArgumentError::Deprecated(why) => write!(f, "it's deprecated, {}", why),
+        }
+    }
+}
+
+impl std::error::Error for ArgumentError {}
+
+#[derive(Clone, Debug, Deserialize, Serialize, Eq, PartialEq)]
+pub struct ModuleInfo {
+    /// Without any directory or `.so`
+    pub name: String,
+    pub description: String,
+    /// The factor it checks, if it checks a credential
+    #[serde(default)]
+    pub factor: Option<Factor>,
+    /// If it always returns the same thing, the first of [ModuleInfo::returns] for the facility
+    #[serde(default)]
+    pub fixed: bool,
+    /// What it typically returns in each facility it implements, most likely first, see [ArgumentInfo::returns] for what arguments add
+    pub returns: BTreeMap<Facility, Vec<ReturnCode>>,
+    /// Groups of arguments that only one of can be used at a time
+    #[serde(default)]
+    pub conflicts: Vec<Vec<String>>,
+    pub arguments: Vec<ArgumentInfo>,
+    /// It takes arguments that aren't options, like `pam_exec.so`'s command, so unknown ones are fine
+    #[serde(default)]
+    pub positional: bool,
+}
+
+impl ModuleInfo {
+    /// The facilities the module implements.
+    pub fn facilities(&self) -> impl Iterator<Item = &Facility> {
+        self.returns.keys()
+    }
+
+    /// If the module implements the facility.
+    pub fn supports(&self, facility: &Facility) -> bool {
+        self.returns.contains_key(facility)
+    }
+
+    /// The argument with this name, `name` can be the whole `name=value`.
+    pub fn argument(&self, name: &str) -> Option<&ArgumentInfo> {
+        let name = name.split_once('=').map_or(name, |(name, _)| name);
+        self.arguments.iter().find(|argument| argument.name == name)
+    }
+
+    /// Everything wrong with the arguments a rule gives the module, by the index of the argument.
+    pub fn check_arguments(&self, arguments: &[String]) -> Vec<(usize, ArgumentError)> {
+        let mut problems = Vec::new();
+        for (index, argument) in arguments.iter().enumerate() {
+            let Some(known) = self.argument(argument) else {
+                if !self.positional {
+                    problems.push((index, ArgumentError::Unknown));
+                }
+                continue;
+            };
+            let value = argument.split_once('=').map(|(_, value)| value);
+            let problem = match (known.kind, value) {
+                (ArgumentKind::Flag, None) => None,
+                (ArgumentKind::Flag, Some(_)) => Some(ArgumentError::UnexpectedValue),
+                (_, Some("")) if known.empty => None,
+                (kind, None | Some("")) => Some(ArgumentError::MissingValue(kind)),
+                (ArgumentKind::Integer, Some(value))
+                    if value.parse::<i64>().is_err()
+                        && !known.values.iter().any(|v| v == value) =>
+                {
+                    Some(ArgumentError::NotAnInteger(value.to_string()))
+                }
+                _ => None,
+            };
+            problems.extend(problem.map(|problem| (index, problem)));
+            if let Some(why) = &known.deprecated {
+                problems.push((index, ArgumentError::Deprecated(why.clone())));
+            }
+            // only the later of two conflicting arguments is reported
+            let earlier = self
+                .conflicts
+                .iter()
+                .filter(|group| group.contains(&known.name))
+                .find_map(|group| {
+                    arguments[..index].iter().find(|other| {
+                        self.argument(other).is_some_and(|other| {
+                            other.name != known.name && group.contains(&other.name)
+                        })
+                    })
+                });
+            if let Some(earlier) = earlier {
+                problems.push((index, ArgumentError::Conflicts(earlier.clone())));
+            }
+        }
+        problems
+    }
+}
+
+/// Every module there's something known about.
+pub fn modules() -> &'static [ModuleInfo] {
+    static MODULES: OnceLock<Vec<ModuleInfo>> = OnceLock::new();
+    MODULES.get_or_init(|| {
+        serde_json::from_str(include_str!("modules.json"))
+            .expect("the embedded module data is valid")
+    })
+}
+
+/// What's known about a module, which is looked up without any directory or `.so`.
+pub fn module_info(module: &str) -> Option<&'static ModuleInfo> {
+    let name = module_name(module);
+    modules().iter().find(|info| info.name == name)
+}
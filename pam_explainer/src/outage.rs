@@ -0,0 +1,219 @@
+//! What still works when a network service goes down, like whether root can still log in while LDAP is unreachable.
+//!
+//! Each kind of account is run through the login facilities with its modules returning what they usually would for it, apart from the ones the outage takes down which return `authinfo_unavail`.
+
+use crate::paths::{fixed_outcome, module_name};
+use crate::{Facility, OutcomeProvider, ReturnCode, Rule, RuleSet};
+use serde::Serialize;
+use std::fmt::Display;
+
+/// Modules that check users against a directory on the network.
+pub const DIRECTORY_MODULES: &[&str] = &["pam_ldap", "pam_sss", "pam_krb5", "pam_winbind"];
+
+/// Modules that check users against the local password files.
+pub const LOCAL_MODULES: &[&str] = &["pam_unix", "pam_unix2", "pam_pwdb"];
+
+/// The facilities an application goes through to log someone in.
+pub const LOGIN_FACILITIES: [Facility; 3] = [Facility::Auth, Facility::Account, Facility::Session];
+
+#[derive(Clone, Copy, Debug, Serialize, Eq, PartialEq)]
+pub struct Outage {
+    pub id: &'static str,
+    pub name: &'static str,
+    /// The modules that can't reach their server
+    pub modules: &'static [&'static str],
+    /// If looking up directory users fails too, because the same service is behind NSS, so `pam_unix.so` can't find them either
+    pub lookups: bool,
+}
+
+pub const OUTAGES: &[Outage] = &[
+    Outage {
+        id: "ldap",
+        name: "LDAP down",
+        modules: &["pam_ldap"],
+        lookups: true,
+    },
+    Outage {
+        id: "sssd",
+        name: "SSSD down",
+        modules: &["pam_sss"],
+        lookups: true,
+    },
+    Outage {
+        id: "kerberos",
+        name: "Kerberos KDC unreachable",
+        modules: &["pam_krb5"],
+        lookups: false,
+    },
+    Outage {
+        id: "winbind",
+        name: "Winbind down",
+        modules: &["pam_winbind"],
+        lookups: true,
+    },
+    Outage {
+        id: "directory",
+        name: "Every directory down",
+        modules: DIRECTORY_MODULES,
+        lookups: true,
+    },
+];
+
+/// The outage profile with this ID.
+pub fn outage(id: &str) -> Option<&'static Outage> {
+    OUTAGES.iter().find(|outage| outage.id == id)
+}
+
+/// Who's logging in.
+#[derive(Clone, Copy, Debug, Serialize, Eq, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Account {
+    /// A user in the local password files
+    Local,
+    Root,
+    /// A user that only exists in a directory
+    Directory,
+}
+
+impl Display for Account {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Account::Local => write!(f, "local"),
+            Account::Root => write!(f, "root"),
+            Account::Directory => write!(f, "directory"),
+        }
+    }
+}
+
+pub const ACCOUNTS: [Account; 3] = [Account::Local, Account::Root, Account::Directory];
+
+/// What modules return for the account while `outage` is happening, or normally if there isn't one.
+///
+/// Modules that aren't about who the user is, like `pam_env.so` or `pam_limits.so`, succeed.
+#[derive(Clone, Copy, Debug)]
+pub struct AccountOutcome<'a> {
+    pub account: Account,
+    pub outage: Option<&'a Outage>,
+}
+
+impl OutcomeProvider for AccountOutcome<'_> {
+    fn outcome(&mut self, rule: &Rule) -> Option<ReturnCode> {
+        if let Some(code) = fixed_outcome(rule) {
+            return Some(code);
+        }
+        let name = module_name(&rule.module);
+        if self
+            .outage
+            .is_some_and(|outage| outage.modules.contains(&name))
+        {
+            return Some(ReturnCode::AuthinfoUnavail);
+        }
+        let lookups_fail = self.outage.is_some_and(|outage| outage.lookups);
+        let code = if DIRECTORY_MODULES.contains(&name) {
+            match self.account {
+                Account::Directory => ReturnCode::Success,
+                _ if rule
+                    .arguments
+                    .iter()
+                    .any(|arg| arg == "ignore_unknown_user") =>
+                {
+                    ReturnCode::Ignore
+                }
+                _ => ReturnCode::UserUnknown,
+            }
+        } else if LOCAL_MODULES.contains(&name) {
+            match (self.account, &rule.facility) {
+                (Account::Local | Account::Root, _) => ReturnCode::Success,
+                _ if lookups_fail => ReturnCode::UserUnknown,
+                // the user can be looked up, but there's no local password to check
+                (Account::Directory, Facility::Auth | Facility::Password) => {
+                    rule.facility.failure_code()
+                }
+                (Account::Directory, _) => ReturnCode::Success,
+            }
+        } else if name == "pam_rootok" {
+            match self.account {
+                Account::Root => ReturnCode::Success,
+                _ => rule.facility.failure_code(),
+            }
+        } else {
+            ReturnCode::Success
+        };
+        Some(code)
+    }
+}
+
+/// What each login facility returned, and if that means the user got in.
+#[derive(Clone, Debug, Serialize, Eq, PartialEq)]
+pub struct Login {
+    pub results: Vec<(Facility, ReturnCode)>,
+    pub allowed: bool,
+}
+
+/// Runs a service's login facilities for the account, while `outage` is happening or normally if there isn't one.
+pub fn login(rules: &[Rule], account: Account, outage: Option<&Outage>) -> Login {
+    let results: Vec<(Facility, ReturnCode)> = LOGIN_FACILITIES
+        .iter()
+        .map(|facility| {
+            let stack = rules
+                .iter()
+                .filter(|rule| &rule.facility == facility)
+                .cloned()
+                .collect();
+            let result = RuleSet::new(facility, stack)
+                .run_rules_with(&mut AccountOutcome { account, outage })
+                .result;
+            (facility.clone(), result)
+        })
+        .collect();
+    Login {
+        allowed: results
+            .iter()
+            .all(|(_, result)| *result == ReturnCode::Success),
+        results,
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Eq, PartialEq)]
+pub struct AccountImpact {
+    pub account: Account,
+    pub before: Login,
+    pub during: Login,
+}
+
+impl AccountImpact {
+    /// If the account could log in before the outage and can't during it.
+    pub fn locked_out(&self) -> bool {
+        self.before.allowed && !self.during.allowed
+    }
+}
+
+/// How each kind of account gets on logging in to a service, before and during the outage.
+pub fn impact(rules: &[Rule], outage: &Outage) -> Vec<AccountImpact> {
+    ACCOUNTS
+        .iter()
+        .map(|account| AccountImpact {
+            account: *account,
+            before: login(rules, *account, None),
+            during: login(rules, *account, Some(outage)),
+        })
+        .collect()
+}
+
+#[derive(Clone, Debug, Serialize, Eq, PartialEq)]
+pub struct ServiceImpact {
+    pub service: String,
+    pub accounts: Vec<AccountImpact>,
+}
+
+/// The [impact] of the outage on every service in the catalog.
+pub fn assess(catalog: &crate::catalog::ServiceCatalog, outage: &Outage) -> Vec<ServiceImpact> {
+    catalog
+        .services
+        .iter()
+        .map(|(name, service)| ServiceImpact {
+            service: name.clone(),
+            accounts: impact(&service.rules, outage),
+        })
+        .collect()
+}
@@ -0,0 +1,150 @@
+//! Where the engine gets a module's return code from when the rule doesn't already have one.
+
+use crate::{try_find_matching_rule_result, LoadError, ReturnCode, Rule};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::io::Read;
+use std::path::Path;
+
+/// Decides what a module returns, for rules that don't have a [Rule::final_result] set.
+///
+/// Returning `None` means the provider doesn't know, and the engine treats the module as failing with [crate::Facility::failure_code].
+pub trait OutcomeProvider {
+    fn outcome(&mut self, rule: &Rule) -> Option<ReturnCode>;
+}
+
+/// Every module returns the same code.
+#[derive(Clone, Copy, Debug)]
+pub struct FixedOutcome(pub ReturnCode);
+
+impl OutcomeProvider for FixedOutcome {
+    fn outcome(&mut self, _rule: &Rule) -> Option<ReturnCode> {
+        Some(self.0)
+    }
+}
+
+/// Doesn't know anything, so every module fails.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct NoOutcome;
+
+impl OutcomeProvider for NoOutcome {
+    fn outcome(&mut self, _rule: &Rule) -> Option<ReturnCode> {
+        None
+    }
+}
+
+/// Asks a function, for when the outcomes come from somewhere else like a web page.
+pub struct CallbackOutcome<F>(pub F)
+where
+    F: FnMut(&Rule) -> Option<ReturnCode>;
+
+impl<F> OutcomeProvider for CallbackOutcome<F>
+where
+    F: FnMut(&Rule) -> Option<ReturnCode>,
+{
+    fn outcome(&mut self, rule: &Rule) -> Option<ReturnCode> {
+        (self.0)(rule)
+    }
+}
+
+/// A set of outcomes read from a file.
+///
+/// The file is JSON, either a list of rules with their `final_result` set (the results file the CLI has always taken), or an object:
+///
+/// ```json
+/// {
+///     "rules": [],
+///     "modules": { "pam_unix.so": "success", "pam_ldap.so": "authinfo_unavail" },
+///     "default": "success"
+/// }
+/// ```
+///
+/// Rules are matched on their facility, control, module and arguments first, then the module name, then the default is used.
+#[derive(Clone, Debug, Default, Deserialize, Serialize, Eq, PartialEq)]
+pub struct Scenario {
+    #[serde(default)]
+    pub rules: Vec<Rule>,
+    /// Keyed by the module as it's written in the rule
+    #[serde(default)]
+    pub modules: BTreeMap<String, ReturnCode>,
+    #[serde(default)]
+    pub default: Option<ReturnCode>,
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum ScenarioFile {
+    Rules(Vec<Rule>),
+    Scenario(Scenario),
+}
+
+impl Scenario {
+    pub fn from_json(value: &str) -> Result<Self, LoadError> {
+        let file = serde_json::from_str(value)
+            .map_err(|source| LoadError::Results { path: None, source })?;
+        Ok(match file {
+            ScenarioFile::Rules(rules) => Scenario {
+                rules,
+                ..Default::default()
+            },
+            ScenarioFile::Scenario(scenario) => scenario,
+        })
+    }
+
+    pub fn read(mut reader: impl Read) -> Result<Self, LoadError> {
+        let mut contents = String::new();
+        reader.read_to_string(&mut contents)?;
+        Self::from_json(&contents)
+    }
+
+    pub fn load(path: &Path) -> Result<Self, LoadError> {
+        let contents = std::fs::read_to_string(path).map_err(LoadError::io(path))?;
+        Self::from_json(&contents).map_err(|err| match err {
+            LoadError::Results { source, .. } => LoadError::Results {
+                path: Some(path.to_path_buf()),
+                source,
+            },
+            err => err,
+        })
+    }
+}
+
+impl OutcomeProvider for Scenario {
+    fn outcome(&mut self, rule: &Rule) -> Option<ReturnCode> {
+        try_find_matching_rule_result(&self.rules, rule)
+            .or_else(|| self.modules.get(&rule.module).copied())
+            .or(self.default)
+    }
+}
+
+/// Asks whoever's at the terminal what each module returned.
+#[cfg(feature = "cli")]
+#[derive(Clone, Copy, Debug, Default)]
+pub struct TerminalPrompt;
+
+#[cfg(feature = "cli")]
+impl OutcomeProvider for TerminalPrompt {
+    fn outcome(&mut self, rule: &Rule) -> Option<ReturnCode> {
+        // offer the likely answers first
+        let likely = [
+            ReturnCode::Success,
+            rule.facility.failure_code(),
+            ReturnCode::Ignore,
+        ];
+        let choices: Vec<ReturnCode> = likely
+            .into_iter()
+            .chain(enum_iterator::all::<ReturnCode>().filter(|code| !likely.contains(code)))
+            .collect();
+        dialoguer::Select::new()
+            .with_prompt(format!(
+                "What did this return: {} {}",
+                rule.facility,
+                rule.to_shortstring()
+            ))
+            .items(&choices)
+            .default(0)
+            .interact()
+            .ok()
+            .map(|index| choices[index])
+    }
+}
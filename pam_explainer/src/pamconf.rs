@@ -0,0 +1,330 @@
+//! The legacy single-file `/etc/pam.conf` format, where every rule starts with the name of the service it belongs to.
+//!
+//! ```text
+//! login   auth     required  pam_unix.so
+//! other   account  required  pam_deny.so
+//! ```
+//!
+//! libpam compares service names case-insensitively, so they're lowercased here.
+
+use crate::include::MAX_INCLUDE_DEPTH;
+use crate::syntax::{lex, Allowances, Entry, EntryKind, Lexeme, SyntaxFile, TokenKind};
+use crate::{parse_line, Control, Facility, LoadError, ParseError, Rule, Span};
+use std::collections::{BTreeMap, BTreeSet};
+use std::path::Path;
+
+/// The rules in a pam.conf, grouped by service.
+pub type Services = BTreeMap<String, Vec<Rule>>;
+
+/// Parse a pam.conf into the rules for each service. `file` is used to label the diagnostics.
+pub fn parse_pam_conf(
+    text: &str,
+    file: Option<&str>,
+    results: &[Rule],
+) -> (Services, Vec<ParseError>) {
+    let mut services = Services::new();
+    let mut diagnostics = Vec::new();
+    let mut allowances = Allowances::default();
+    for entry in SyntaxFile::parse(text).entries.iter() {
+        let allow = allowances.next(entry);
+        if !matches!(entry.kind, EntryKind::Rule | EntryKind::Include) {
+            continue;
+        }
+        let text = entry.text();
+        let Some((service, rule)) = split_service(&text) else {
+            continue;
+        };
+        if rule.trim().is_empty() {
+            let end = text.trim_end().len();
+            diagnostics.push(
+                ParseError::MissingFacility {
+                    span: Span {
+                        columns: end..end,
+                        ..Default::default()
+                    },
+                }
+                .within(&text, file, entry.line),
+            );
+            continue;
+        }
+        let rules = services.entry(service.to_lowercase()).or_default();
+        // blank out the service so the columns in any diagnostics still line up
+        let masked = format!("{}{}", " ".repeat(text.len() - rule.len()), rule);
+        if let Some(rule) = parse_line(
+            &masked,
+            file,
+            entry.line,
+            rules.len() as u32,
+            results,
+            allow,
+            &mut diagnostics,
+        ) {
+            rules.push(rule);
+        }
+    }
+    (services, diagnostics)
+}
+
+/// Read and parse a pam.conf from disk.
+pub fn load_pam_conf(
+    path: &Path,
+    results: &[Rule],
+) -> Result<(Services, Vec<ParseError>), LoadError> {
+    let text = std::fs::read_to_string(path).map_err(LoadError::io(path))?;
+    Ok(parse_pam_conf(&text, path.to_str(), results))
+}
+
+/// Split the service name off the front of a pam.conf rule, returning it and the rest of the text.
+fn split_service(text: &str) -> Option<(&str, &str)> {
+    let (_, range) = lex(text)
+        .into_iter()
+        .find(|(lexeme, _)| *lexeme == Lexeme::Field)?;
+    Some((&text[range.clone()], &text[range.end..]))
+}
+
+/// Convert a pam.conf into the contents of a pam.d directory, one file per service.
+///
+/// Comments and blank lines go along with the rule that follows them, anything after the last rule goes into the last service's file.
+pub fn to_pam_d(text: &str) -> BTreeMap<String, String> {
+    let mut files: BTreeMap<String, String> = BTreeMap::new();
+    let mut pending = String::new();
+    let mut last_service = None;
+    for entry in SyntaxFile::parse(text).entries.iter() {
+        let text = entry.text();
+        let service = match entry.kind {
+            EntryKind::Rule | EntryKind::Include => split_service(&text),
+            EntryKind::Blank | EntryKind::Comment => None,
+        };
+        let Some((service, rule)) = service else {
+            pending.push_str(&entry.to_string());
+            continue;
+        };
+        // a service with nothing after it, parse_pam_conf reports these
+        if rule.trim().is_empty() {
+            continue;
+        }
+        let service = service.to_lowercase();
+        let contents = files.entry(service.clone()).or_default();
+        contents.push_str(&pending);
+        pending.clear();
+        contents.push_str(rule.trim_start());
+        contents.push_str(if entry.ending.is_empty() {
+            "\n"
+        } else {
+            &entry.ending
+        });
+        last_service = Some(service);
+    }
+    if let Some(service) = last_service {
+        files.entry(service).or_default().push_str(&pending);
+    }
+    files
+}
+
+/// Convert the contents of a pam.d directory, keyed by service, into a single pam.conf.
+///
+/// libpam only reads pam.conf when there's no /etc/pam.d, and it looks for anything included in /etc/pam.d, so `include` and Debian's `@include` can't be written out as they are. The included file's rules are inlined in their place instead, only the ones for the rule's facility in the case of `include`, and files that are included aren't written out as services of their own. A substack can't be inlined without changing what done, die and reset do, so it's left pointing at its file.
+pub fn from_pam_d(files: &BTreeMap<String, String>) -> Result<String, LoadError> {
+    let included: BTreeSet<String> = files
+        .values()
+        .flat_map(|contents| SyntaxFile::parse(contents).entries)
+        .filter_map(|entry| include_target(&entry))
+        .map(|(target, _)| target)
+        .collect();
+    let services: Vec<(&String, &String)> = files
+        .iter()
+        .filter(|(service, _)| !included.contains(*service))
+        .collect();
+    let width = services
+        .iter()
+        .map(|(service, _)| service.len())
+        .max()
+        .unwrap_or_default();
+    let mut output = String::new();
+    for (service, contents) in services {
+        if !output.is_empty() {
+            output.push('\n');
+        }
+        output.push_str(&format!("# {}\n", service));
+        let mut writer = Inliner {
+            files,
+            service,
+            width,
+            chain: vec![service.clone()],
+            output: &mut output,
+        };
+        writer.write(contents, None, true)?;
+    }
+    Ok(output)
+}
+
+/// The file an `include` rule or `@include` pulls in, and the facility it's limited to if it's an `include`.
+fn include_target(entry: &Entry) -> Option<(String, Option<Facility>)> {
+    match entry.kind {
+        EntryKind::Include => entry
+            .field(TokenKind::IncludeTarget)
+            .map(|target| (target.text.clone(), None)),
+        EntryKind::Rule => {
+            let control = entry.field(TokenKind::Control)?;
+            if Control::from(control.text.as_str()) != Control::Include {
+                return None;
+            }
+            let target = entry.field(TokenKind::Module)?;
+            Some((target.text.clone(), rule_facility(entry)))
+        }
+        EntryKind::Blank | EntryKind::Comment => None,
+    }
+}
+
+fn rule_facility(entry: &Entry) -> Option<Facility> {
+    entry.field(TokenKind::Facility).map(|facility| {
+        let facility = facility.text.as_str();
+        Facility::from(facility.strip_prefix('-').unwrap_or(facility))
+    })
+}
+
+/// Writes one service's rules into a pam.conf, inlining whatever it includes.
+struct Inliner<'a> {
+    files: &'a BTreeMap<String, String>,
+    service: &'a str,
+    width: usize,
+    /// The files currently being inlined, to catch include loops
+    chain: Vec<String>,
+    output: &'a mut String,
+}
+
+impl Inliner<'_> {
+    /// Write out the rules in `contents`, only those for `facility` if there is one. Comments and blank lines are only kept from the service's own file.
+    fn write(
+        &mut self,
+        contents: &str,
+        facility: Option<&Facility>,
+        top: bool,
+    ) -> Result<(), LoadError> {
+        for entry in SyntaxFile::parse(contents).entries.iter() {
+            let ending = if entry.ending.is_empty() {
+                "\n"
+            } else {
+                entry.ending.as_str()
+            };
+            match entry.kind {
+                EntryKind::Blank | EntryKind::Comment => {
+                    if top {
+                        self.output.push_str(&entry.text());
+                        self.output.push_str(ending);
+                    }
+                    continue;
+                }
+                EntryKind::Rule
+                    if facility.is_some_and(|facility| {
+                        rule_facility(entry).as_ref() != Some(facility)
+                    }) =>
+                {
+                    continue;
+                }
+                EntryKind::Rule | EntryKind::Include => {}
+            }
+            let Some((target, only)) = include_target(entry) else {
+                if entry.kind == EntryKind::Rule {
+                    self.output.push_str(&format!(
+                        "{:width$} {}{}",
+                        self.service,
+                        entry.text().trim_start(),
+                        ending,
+                        width = self.width
+                    ));
+                }
+                continue;
+            };
+            // leave a note of where the rules came from
+            self.output
+                .push_str(&format!("# {}{}", entry.text().trim(), ending));
+            self.inline(&target, only.as_ref().or(facility))?;
+        }
+        Ok(())
+    }
+
+    fn inline(&mut self, target: &str, facility: Option<&Facility>) -> Result<(), LoadError> {
+        let mut chain = self.chain.clone();
+        chain.push(target.to_string());
+        if self.chain.iter().any(|name| name == target) {
+            return Err(LoadError::IncludeLoop { chain });
+        }
+        if self.chain.len() > MAX_INCLUDE_DEPTH {
+            return Err(LoadError::IncludeTooDeep { chain });
+        }
+        let files = self.files;
+        let contents = files
+            .get(target)
+            .ok_or_else(|| LoadError::IncludeNotFound {
+                target: target.to_string(),
+            })?;
+        self.chain.push(target.to_string());
+        let res = self.write(contents, facility, false);
+        self.chain.pop();
+        res
+    }
+}
+
+/// `service`'s file and every file it includes or substacks, directly or not, looked up with `read`. Anything `read` can't find is left out, for [from_pam_d] to report.
+pub fn service_files(
+    service: &str,
+    mut read: impl FnMut(&str) -> Option<String>,
+) -> BTreeMap<String, String> {
+    let mut files = BTreeMap::new();
+    let mut wanted = vec![service.to_string()];
+    while let Some(name) = wanted.pop() {
+        if files.contains_key(&name) {
+            continue;
+        }
+        let Some(contents) = read(&name) else {
+            continue;
+        };
+        for entry in SyntaxFile::parse(&contents).entries.iter() {
+            let substack = entry.kind == EntryKind::Rule
+                && entry
+                    .field(TokenKind::Control)
+                    .map(|control| Control::from(control.text.as_str()))
+                    == Some(Control::Substack);
+            if let Some((target, _)) = include_target(entry) {
+                wanted.push(target);
+            } else if let Some(target) = entry.field(TokenKind::Module).filter(|_| substack) {
+                wanted.push(target.text.clone());
+            }
+        }
+        files.insert(name, contents);
+    }
+    files
+}
+
+/// Read every service file in a pam.d directory, ready for [from_pam_d].
+pub fn read_pam_d(dir: &Path) -> Result<BTreeMap<String, String>, LoadError> {
+    let mut files = BTreeMap::new();
+    for entry in std::fs::read_dir(dir).map_err(LoadError::io(dir))? {
+        let entry = entry.map_err(LoadError::io(dir))?;
+        let path = entry.path();
+        if !path.is_file() {
+            continue;
+        }
+        if let Some(service) = entry.file_name().to_str() {
+            let contents = std::fs::read_to_string(&path).map_err(LoadError::io(&path))?;
+            files.insert(service.to_string(), contents);
+        }
+    }
+    Ok(files)
+}
+
+/// Write the output of [to_pam_d] out as a pam.d directory, creating it if it doesn't exist.
+pub fn write_pam_d(dir: &Path, files: &BTreeMap<String, String>) -> Result<(), std::io::Error> {
+    std::fs::create_dir_all(dir)?;
+    for (service, contents) in files.iter() {
+        if service.contains(['/', '\\']) || service.starts_with('.') {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::Other,
+                format!("Invalid service name: {}", service),
+            ));
+        }
+        std::fs::write(dir.join(service), contents)?;
+    }
+    Ok(())
+}
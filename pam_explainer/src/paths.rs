@@ -0,0 +1,164 @@
+//! Every way a stack can run, found by running it with each module returning each of the results that make a difference.
+//!
+//! A module can return any of around thirty codes, but the stack only cares which action the control maps the code to, so each rule is tried with one code for each distinct action. Modules that always return the same thing, like `pam_permit.so`, aren't branched on at all.
+
+use crate::{Action, Facility, OutcomeProvider, ReturnCode, Rule, RuleSet, Trace};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+/// How many paths to follow before giving up, a stack of required rules doubles the count with every rule.
+pub const MAX_PATHS: usize = 4096;
+
+/// One way the stack can run.
+#[derive(Clone, Debug, Deserialize, Serialize, Eq, PartialEq)]
+pub struct Path {
+    /// What each module that was run returned, by the index of its rule
+    pub outcomes: BTreeMap<usize, ReturnCode>,
+    pub trace: Trace,
+}
+
+impl Path {
+    /// If the rule at `index` was run and returned success.
+    pub fn succeeded(&self, index: usize) -> bool {
+        self.outcomes.get(&index) == Some(&ReturnCode::Success)
+    }
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize, Eq, PartialEq)]
+pub struct Paths {
+    pub paths: Vec<Path>,
+    /// Every path was followed, if this is false there are more than the limit and anything worked out from them is incomplete
+    pub complete: bool,
+}
+
+/// The module name without any directory or `.so`, so `/lib/security/pam_unix.so` is `pam_unix`.
+pub fn module_name(module: &str) -> &str {
+    let name = module.rsplit('/').next().unwrap_or(module);
+    name.strip_suffix(".so").unwrap_or(name)
+}
+
+/// What the rule's module returns if it always returns the same thing, like `pam_permit.so`.
+pub fn fixed_outcome(rule: &Rule) -> Option<ReturnCode> {
+    let info = crate::modules::module_info(&rule.module).filter(|info| info.fixed)?;
+    info.returns.get(&rule.facility)?.first().copied()
+}
+
+/// A code for each different action the rule's control can take, out of the codes the module can return.
+///
+/// For a well-known module those are what it returns in the facility, see [crate::modules::ModuleInfo::returns], plus any its arguments add, like `ignore` from `pam_u2f.so nouserok`. A known module in a facility it doesn't implement can only return `module_unknown`. Anything else could return anything, so success and the facility's usual failure are tried, then each code the control names as one of them may be why it was written, like `ignore` for `required` and `requisite`, plus a code that falls through to its default. A control libpam can't parse treats everything as a failure, so it only gets one.
+pub fn outcome_classes(rule: &Rule) -> Vec<ReturnCode> {
+    if let Some(code) = fixed_outcome(rule) {
+        return vec![code];
+    }
+    let failure = rule.facility.failure_code();
+    let Some(actions) = rule.control.actions() else {
+        return vec![failure];
+    };
+    let candidates =
+        match crate::modules::module_info(&rule.module) {
+            Some(info) => match info.returns.get(&rule.facility) {
+                Some(returns) => returns
+                    .iter()
+                    .chain(
+                        rule.arguments
+                            .iter()
+                            .filter_map(|argument| info.argument(argument))
+                            .flat_map(|argument| argument.returns.iter()),
+                    )
+                    .copied()
+                    .collect(),
+                None => vec![ReturnCode::ModuleUnknown],
+            },
+            None => {
+                let mut candidates = vec![ReturnCode::Success, failure];
+                candidates.extend(actions.values.keys().copied());
+                candidates.extend(enum_iterator::all::<ReturnCode>().find(|code| {
+                    *code != ReturnCode::Success && !actions.values.contains_key(code)
+                }));
+                candidates
+            }
+        };
+    let mut classes: Vec<(Action, ReturnCode)> = Vec::new();
+    for code in candidates {
+        let action = actions.action_for(code);
+        if !classes.iter().any(|(seen, _)| *seen == action) {
+            classes.push((action, code));
+        }
+    }
+    classes.into_iter().map(|(_, code)| code).collect()
+}
+
+/// Follows the choices it's given, then takes the first outcome for every module after that, noting the ones it could have picked.
+struct Replay<'a> {
+    prefix: &'a [ReturnCode],
+    /// Every choice made, and the other ones that could have been made
+    choices: Vec<(ReturnCode, Vec<ReturnCode>)>,
+}
+
+impl OutcomeProvider for Replay<'_> {
+    fn outcome(&mut self, rule: &Rule) -> Option<ReturnCode> {
+        let classes = outcome_classes(rule);
+        let chosen = match self.prefix.get(self.choices.len()) {
+            Some(code) => *code,
+            None => classes[0],
+        };
+        let others = classes.into_iter().filter(|code| *code != chosen).collect();
+        self.choices.push((chosen, others));
+        Some(chosen)
+    }
+}
+
+impl RuleSet {
+    /// Every way the stack can run, stopping once `limit` paths have been found. Rules that already have a [Rule::final_result] keep it.
+    ///
+    /// The paths come in order, starting with every module succeeding, and the earlier a rule is the longer it keeps its outcome.
+    pub fn paths(&self, limit: usize) -> Paths {
+        paths(&self.facility, &self.rules, limit)
+    }
+}
+
+/// Like [RuleSet::paths], for rules that aren't in a [RuleSet].
+pub fn paths(facility: &Facility, rules: &[Rule], limit: usize) -> Paths {
+    let mut paths = Vec::new();
+    let mut pending: Vec<Vec<ReturnCode>> = vec![Vec::new()];
+    while let Some(prefix) = pending.pop() {
+        if paths.len() >= limit {
+            return Paths {
+                paths,
+                complete: false,
+            };
+        }
+        let mut replay = Replay {
+            prefix: &prefix,
+            choices: Vec::new(),
+        };
+        let mut ruleset = RuleSet::new(facility, rules.to_vec());
+        let trace = ruleset.run_rules_with(&mut replay);
+
+        // the deepest choice is changed first, so it's pushed last
+        for depth in prefix.len()..replay.choices.len() {
+            for other in replay.choices[depth].1.iter().rev() {
+                let mut next: Vec<ReturnCode> = replay.choices[..depth]
+                    .iter()
+                    .map(|(code, _)| *code)
+                    .collect();
+                next.push(*other);
+                pending.push(next);
+            }
+        }
+
+        let outcomes = trace
+            .steps
+            .iter()
+            .filter_map(|step| match step.outcome {
+                crate::Outcome::Invoked { code, .. } => Some((step.index, code)),
+                _ => None,
+            })
+            .collect();
+        paths.push(Path { outcomes, trace });
+    }
+    Paths {
+        paths,
+        complete: true,
+    }
+}
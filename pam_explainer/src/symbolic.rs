@@ -0,0 +1,643 @@
+//! A facility's decision logic as a reduced, ordered decision diagram over what each module returns.
+//!
+//! Rather than running the stack once for every combination of results, it's run once for each state the stack can be in when it reaches a rule whose result matters. Combinations that leave the stack in the same state share the rest of the diagram, so its size depends on how the stack is put together rather than doubling with every rule, which is what makes questions about fully flattened services answerable.
+
+use crate::paths::{module_name, outcome_classes};
+use crate::{Action, Control, Facility, Impression, ReturnCode, Rule, RuleSet};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fmt::Display;
+
+/// A module in a stack, by its name and which use of it in the stack it is, so the rules of two stacks can be matched up wherever they are.
+#[derive(Clone, Debug, Deserialize, Serialize, Eq, PartialEq, Hash, PartialOrd, Ord)]
+pub struct ModuleId {
+    /// The module name without any directory or `.so`
+    pub name: String,
+    /// 0 for the first rule using the module, 1 for the second and so on
+    pub occurrence: usize,
+}
+
+impl Display for ModuleId {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.occurrence {
+            0 => write!(f, "{}", self.name),
+            occurrence => write!(f, "{}#{}", self.name, occurrence + 1),
+        }
+    }
+}
+
+/// The [ModuleId] of each rule.
+pub fn module_ids(rules: &[Rule]) -> Vec<ModuleId> {
+    let mut seen: HashMap<&str, usize> = HashMap::new();
+    rules
+        .iter()
+        .map(|rule| {
+            let name = module_name(&rule.module);
+            let occurrence = seen.entry(name).or_default();
+            let id = ModuleId {
+                name: name.to_string(),
+                occurrence: *occurrence,
+            };
+            *occurrence += 1;
+            id
+        })
+        .collect()
+}
+
+/// How the stack ends for a combination of module results.
+#[derive(Clone, Copy, Debug, Deserialize, Serialize, Eq, PartialEq, Hash)]
+pub struct Ending {
+    pub result: ReturnCode,
+    pub rules_run: usize,
+}
+
+/// The index of a node in [Diagram::nodes].
+pub type NodeId = usize;
+
+#[derive(Clone, Debug, Deserialize, Serialize, Eq, PartialEq, Hash)]
+#[serde(rename_all = "snake_case")]
+pub enum Node {
+    /// The stack ends here
+    Result(Ending),
+    /// Where the stack goes depends on what the rule's module returns
+    Decision {
+        rule: usize,
+        branches: Vec<(ReturnCode, NodeId)>,
+    },
+}
+
+#[derive(Clone, Debug, Serialize, Eq, PartialEq)]
+pub struct Diagram {
+    pub facility: Facility,
+    pub rules: Vec<Rule>,
+    /// What each rule that can make a difference is tried with, by the index of the rule
+    pub domains: BTreeMap<usize, Vec<ReturnCode>>,
+    /// Every node comes after the nodes it leads to
+    pub nodes: Vec<Node>,
+    pub root: NodeId,
+    #[serde(skip)]
+    unique: HashMap<Node, NodeId>,
+}
+
+/// Where the stack is up to, everything [RuleSet::run_rules_with] keeps between rules apart from the trace.
+#[derive(Clone, Debug, Eq, PartialEq, Hash)]
+struct Machine {
+    index: usize,
+    prev_level: usize,
+    impression: Impression,
+    status: ReturnCode,
+    substates: Vec<(Impression, ReturnCode)>,
+    rules_run: usize,
+}
+
+struct Builder {
+    engine: RuleSet,
+    /// What each rule's module is tried with, a single code if it doesn't need to be branched on
+    codes: Vec<Vec<ReturnCode>>,
+    diagram: Diagram,
+    memo: HashMap<Machine, NodeId>,
+}
+
+impl Builder {
+    /// Runs the rule the machine is up to, with `code` as what its module returned.
+    fn step(&mut self, machine: &mut Machine, code: ReturnCode) {
+        let rule = &self.engine.rules[machine.index];
+        let level = rule.stack_level;
+        let action = match &rule.control {
+            _ if rule.is_skipped_missing_module() => None,
+            Control::Include | Control::Substack => None,
+            control => Some(
+                control
+                    .actions()
+                    .map_or(Action::Bad, |actions| actions.action_for(code)),
+            ),
+        };
+        self.engine.impression = machine.impression;
+        self.engine.status = machine.status;
+        self.engine
+            .enter(level, machine.prev_level, &mut machine.substates);
+        machine.prev_level = level;
+        let mut next = machine.index + 1;
+        if let Some(action) = action {
+            let flow = self.engine.apply_action(action, code);
+            let (skipped, _) =
+                self.engine
+                    .follow(machine.index, level, action, flow, &machine.substates);
+            next += skipped.len();
+            machine.rules_run += 1;
+        }
+        machine.index = next;
+        machine.impression = self.engine.impression;
+        machine.status = self.engine.status;
+    }
+
+    fn build(&mut self, start: Machine) -> NodeId {
+        if let Some(node) = self.memo.get(&start) {
+            return *node;
+        }
+        let mut machine = start.clone();
+        let node = loop {
+            if machine.index >= self.engine.rules.len() {
+                self.engine.impression = machine.impression;
+                self.engine.status = machine.status;
+                break self.diagram.node(Node::Result(Ending {
+                    result: self.engine.final_result(),
+                    rules_run: machine.rules_run,
+                }));
+            }
+            let codes = self.codes[machine.index].clone();
+            if let [code] = codes.as_slice() {
+                self.step(&mut machine, *code);
+                continue;
+            }
+            let branches = codes
+                .into_iter()
+                .map(|code| {
+                    let mut next = machine.clone();
+                    self.step(&mut next, code);
+                    (code, self.build(next))
+                })
+                .collect();
+            break self.diagram.node(Node::Decision {
+                rule: machine.index,
+                branches,
+            });
+        };
+        self.memo.insert(start, node);
+        node
+    }
+}
+
+impl RuleSet {
+    /// The stack as a [Diagram], with each module tried with the results from [outcome_classes]. Rules that already have a [Rule::final_result] keep it.
+    pub fn diagram(&self) -> Diagram {
+        Diagram::build(&self.facility, &self.rules, |_, rule| outcome_classes(rule))
+    }
+}
+
+impl Diagram {
+    /// Builds the diagram for a stack, trying each module whose rule doesn't have a [Rule::final_result] with the codes `domain` gives for it.
+    pub fn build(
+        facility: &Facility,
+        rules: &[Rule],
+        domain: impl Fn(usize, &Rule) -> Vec<ReturnCode>,
+    ) -> Self {
+        let codes: Vec<Vec<ReturnCode>> = rules
+            .iter()
+            .enumerate()
+            .map(|(index, rule)| match rule.final_result {
+                Some(code) => vec![code],
+                None if rule.is_skipped_missing_module()
+                    || matches!(rule.control, Control::Include | Control::Substack) =>
+                {
+                    // never run, so what it's tried with doesn't matter
+                    vec![rule.facility.failure_code()]
+                }
+                None => {
+                    let mut codes: Vec<ReturnCode> = Vec::new();
+                    for code in domain(index, rule) {
+                        if !codes.contains(&code) {
+                            codes.push(code);
+                        }
+                    }
+                    match codes.is_empty() {
+                        true => vec![rule.facility.failure_code()],
+                        false => codes,
+                    }
+                }
+            })
+            .collect();
+        let mut builder = Builder {
+            engine: RuleSet::new(facility, rules.to_vec()),
+            diagram: Diagram {
+                facility: facility.clone(),
+                rules: rules.to_vec(),
+                domains: codes
+                    .iter()
+                    .enumerate()
+                    .filter(|(_, codes)| codes.len() > 1)
+                    .map(|(index, codes)| (index, codes.clone()))
+                    .collect(),
+                nodes: Vec::new(),
+                root: 0,
+                unique: HashMap::new(),
+            },
+            codes,
+            memo: HashMap::new(),
+        };
+        let root = builder.build(Machine {
+            index: 0,
+            prev_level: 0,
+            impression: Impression::Undefined,
+            status: ReturnCode::PermDenied,
+            substates: vec![(Impression::Undefined, ReturnCode::PermDenied)],
+            rules_run: 0,
+        });
+        let mut diagram = builder.diagram;
+        diagram.root = root;
+        diagram
+    }
+
+    /// Adds a node, unless an identical one exists or it's a decision that goes the same way whatever the module returns.
+    fn node(&mut self, node: Node) -> NodeId {
+        if let Node::Decision { branches, .. } = &node {
+            if let Some((_, first)) = branches.first() {
+                if branches.iter().all(|(_, next)| next == first) {
+                    return *first;
+                }
+            }
+        }
+        if let Some(id) = self.unique.get(&node) {
+            return *id;
+        }
+        self.nodes.push(node.clone());
+        self.unique.insert(node, self.nodes.len() - 1);
+        self.nodes.len() - 1
+    }
+
+    /// The same diagram with every [Ending::rules_run] set to 0, so only the modules that change the result are left in it.
+    pub fn results_only(&self) -> Diagram {
+        let mut diagram = Diagram {
+            facility: self.facility.clone(),
+            rules: self.rules.clone(),
+            domains: self.domains.clone(),
+            nodes: Vec::new(),
+            root: 0,
+            unique: HashMap::new(),
+        };
+        let mut ids: Vec<NodeId> = Vec::with_capacity(self.nodes.len());
+        for node in self.nodes.iter() {
+            let node = match node {
+                Node::Result(ending) => Node::Result(Ending {
+                    result: ending.result,
+                    rules_run: 0,
+                }),
+                Node::Decision { rule, branches } => Node::Decision {
+                    rule: *rule,
+                    branches: branches
+                        .iter()
+                        .map(|(code, next)| (*code, ids[*next]))
+                        .collect(),
+                },
+            };
+            ids.push(diagram.node(node));
+        }
+        diagram.root = ids[self.root];
+        diagram
+    }
+
+    /// The nodes that can be reached from the root, in the order they're in [Diagram::nodes].
+    pub fn reachable(&self) -> Vec<NodeId> {
+        let mut seen = vec![false; self.nodes.len()];
+        seen[self.root] = true;
+        for node in (0..self.nodes.len()).rev() {
+            if let (true, Node::Decision { branches, .. }) = (seen[node], &self.nodes[node]) {
+                branches.iter().for_each(|(_, next)| seen[*next] = true);
+            }
+        }
+        (0..self.nodes.len()).filter(|node| seen[*node]).collect()
+    }
+
+    /// The rule a node decides on, `None` for a result.
+    fn rule(&self, node: NodeId) -> Option<usize> {
+        match &self.nodes[node] {
+            Node::Result(_) => None,
+            Node::Decision { rule, .. } => Some(*rule),
+        }
+    }
+
+    /// Where a node goes if its module returns `code`, `None` if it isn't one of the codes it was tried with.
+    fn branch(&self, node: NodeId, code: ReturnCode) -> Option<NodeId> {
+        match &self.nodes[node] {
+            Node::Result(_) => Some(node),
+            Node::Decision { branches, .. } => branches
+                .iter()
+                .find(|(branch, _)| *branch == code)
+                .map(|(_, next)| *next),
+        }
+    }
+
+    /// How the stack ends when each module returns what `outcome` says it does. `None` if a module returned something it wasn't tried with.
+    pub fn evaluate(&self, outcome: impl Fn(usize) -> ReturnCode) -> Option<Ending> {
+        let mut node = self.root;
+        loop {
+            match &self.nodes[node] {
+                Node::Result(ending) => return Some(*ending),
+                Node::Decision { rule, .. } => node = self.branch(node, outcome(*rule))?,
+            }
+        }
+    }
+
+    /// For each node, if the stack can end with `result` from there.
+    fn reaching(&self, result: ReturnCode) -> Vec<bool> {
+        let mut reaches: Vec<bool> = Vec::with_capacity(self.nodes.len());
+        for node in self.nodes.iter() {
+            let value = match node {
+                Node::Result(ending) => ending.result == result,
+                Node::Decision { branches, .. } => branches.iter().any(|(_, next)| reaches[*next]),
+            };
+            reaches.push(value);
+        }
+        reaches
+    }
+
+    /// If the stack can end with `result` when the module of `rule` returns `code`, whatever the others return.
+    pub fn reaches_with(&self, result: ReturnCode, rule: usize, code: ReturnCode) -> bool {
+        let mut reaches: Vec<bool> = Vec::with_capacity(self.nodes.len());
+        for node in self.nodes.iter() {
+            let value = match node {
+                Node::Result(ending) => ending.result == result,
+                Node::Decision {
+                    rule: decided,
+                    branches,
+                } => branches
+                    .iter()
+                    .filter(|(branch, _)| *decided != rule || *branch == code)
+                    .any(|(_, next)| reaches[*next]),
+            };
+            reaches.push(value);
+        }
+        reaches[self.root]
+    }
+
+    /// If the stack can end with `result`, and an example of what the modules that matter return for it to.
+    pub fn reaches(&self, result: ReturnCode) -> Option<BTreeMap<usize, ReturnCode>> {
+        let reaches = self.reaching(result);
+        if !reaches[self.root] {
+            return None;
+        }
+        let mut outcomes = BTreeMap::new();
+        let mut node = self.root;
+        while let Node::Decision { rule, branches } = &self.nodes[node] {
+            let (code, next) = branches.iter().find(|(_, next)| reaches[*next])?;
+            outcomes.insert(*rule, *code);
+            node = *next;
+        }
+        Some(outcomes)
+    }
+
+    /// What each module has to return for the stack to end with `result`, for the modules that can't return everything they're tried with. `None` if the stack can't end with `result` at all.
+    pub fn required(&self, result: ReturnCode) -> Option<BTreeMap<usize, Vec<ReturnCode>>> {
+        let reaches = self.reaching(result);
+        if !reaches[self.root] {
+            return None;
+        }
+        let variables: Vec<usize> = self.domains.keys().copied().collect();
+        let position = |node: NodeId| match self.rule(node) {
+            Some(rule) => variables.binary_search(&rule).unwrap_or(variables.len()),
+            None => variables.len(),
+        };
+        // a module that's passed over on the way to the result can return anything
+        let mut free = vec![false; variables.len()];
+        let mut allowed: Vec<Vec<ReturnCode>> = vec![Vec::new(); variables.len()];
+        free[..position(self.root)].fill(true);
+        let mut pending = vec![self.root];
+        let mut seen = HashSet::new();
+        while let Some(node) = pending.pop() {
+            if !seen.insert(node) {
+                continue;
+            }
+            let Node::Decision { branches, .. } = &self.nodes[node] else {
+                continue;
+            };
+            let from = position(node);
+            for (code, next) in branches.iter().filter(|(_, next)| reaches[*next]) {
+                allowed[from].push(*code);
+                free[from + 1..position(*next)].fill(true);
+                pending.push(*next);
+            }
+        }
+        Some(
+            variables
+                .iter()
+                .enumerate()
+                .filter(|(position, _)| !free[*position])
+                .filter_map(|(position, rule)| {
+                    let codes: Vec<ReturnCode> = self.domains[rule]
+                        .iter()
+                        .copied()
+                        .filter(|code| allowed[position].contains(code))
+                        .collect();
+                    match codes.len() < self.domains[rule].len() {
+                        true => Some((*rule, codes)),
+                        false => None,
+                    }
+                })
+                .collect(),
+        )
+    }
+
+    /// The node `node` becomes when the module at `rule` is known to return `code`.
+    fn restrict(
+        &mut self,
+        node: NodeId,
+        rule: usize,
+        code: ReturnCode,
+        memo: &mut HashMap<NodeId, NodeId>,
+    ) -> NodeId {
+        let Some(top) = self.rule(node) else {
+            return node;
+        };
+        if top == rule {
+            return self.branch(node, code).unwrap_or(node);
+        }
+        // rules only come after the ones before them, so it can't be further down
+        if top > rule {
+            return node;
+        }
+        if let Some(restricted) = memo.get(&node) {
+            return *restricted;
+        }
+        let Node::Decision { branches, .. } = self.nodes[node].clone() else {
+            return node;
+        };
+        let branches = branches
+            .into_iter()
+            .map(|(branch, next)| (branch, self.restrict(next, rule, code, memo)))
+            .collect();
+        let restricted = self.node(Node::Decision {
+            rule: top,
+            branches,
+        });
+        memo.insert(node, restricted);
+        restricted
+    }
+}
+
+/// A combination of module results two stacks handle differently.
+#[derive(Clone, Debug, Serialize, Eq, PartialEq)]
+pub struct Difference {
+    /// What the modules that matter return, any others can return anything
+    pub outcomes: Vec<(ModuleId, ReturnCode)>,
+    pub left: Ending,
+    pub right: Ending,
+}
+
+#[derive(Clone, Debug, Serialize, Eq, PartialEq)]
+pub struct Differences {
+    /// The modules in either stack with what they're tried with, the left stack's then any only in the right one
+    pub modules: Vec<(ModuleId, Vec<ReturnCode>)>,
+    pub differences: Vec<Difference>,
+    /// Every difference was found, if not there are more than the limit
+    pub complete: bool,
+}
+
+struct Comparison {
+    left: Diagram,
+    right: Diagram,
+    /// Where each module is in the order the diagrams are walked, the left stack's modules then any only in the right one
+    order: HashMap<ModuleId, usize>,
+    left_ids: Vec<ModuleId>,
+    right_ids: Vec<ModuleId>,
+    /// The index of each of the right stack's rules
+    right_rules: HashMap<ModuleId, usize>,
+    domains: HashMap<ModuleId, Vec<ReturnCode>>,
+    /// If running a different number of rules counts as a difference
+    rules_run: bool,
+    limit: usize,
+    found: Vec<Difference>,
+    stopped: bool,
+    /// Pairs of nodes with no differences under them
+    same: HashSet<(NodeId, NodeId)>,
+}
+
+impl Comparison {
+    /// Looks for differences between the stacks from these nodes on, returning if there were any.
+    fn walk(
+        &mut self,
+        left: NodeId,
+        right: NodeId,
+        outcomes: &mut Vec<(ModuleId, ReturnCode)>,
+    ) -> bool {
+        if self.stopped || self.same.contains(&(left, right)) {
+            return false;
+        }
+        let left_id = self.left.rule(left).map(|rule| self.left_ids[rule].clone());
+        let right_id = self
+            .right
+            .rule(right)
+            .map(|rule| self.right_ids[rule].clone());
+        let module = match (left_id, right_id) {
+            (None, None) => {
+                let (Node::Result(left_ending), Node::Result(right_ending)) =
+                    (&self.left.nodes[left], &self.right.nodes[right])
+                else {
+                    return false;
+                };
+                if left_ending.result == right_ending.result
+                    && (!self.rules_run || left_ending.rules_run == right_ending.rules_run)
+                {
+                    self.same.insert((left, right));
+                    return false;
+                }
+                if self.found.len() >= self.limit {
+                    self.stopped = true;
+                    return true;
+                }
+                self.found.push(Difference {
+                    outcomes: outcomes.clone(),
+                    left: *left_ending,
+                    right: *right_ending,
+                });
+                return true;
+            }
+            (Some(id), None) | (None, Some(id)) => id,
+            (Some(left_id), Some(right_id)) => {
+                match self.order[&left_id] <= self.order[&right_id] {
+                    true => left_id,
+                    false => right_id,
+                }
+            }
+        };
+        let mut differs = false;
+        for code in self.domains[&module].clone() {
+            // the left diagram is in the order the walk is, so the module can only be at the top
+            let next_left = match self.left.rule(left) {
+                Some(rule) if self.left_ids[rule] == module => {
+                    self.left.branch(left, code).unwrap_or(left)
+                }
+                _ => left,
+            };
+            let next_right = match self.right_rules.get(&module) {
+                Some(rule) => self.right.restrict(right, *rule, code, &mut HashMap::new()),
+                None => right,
+            };
+            outcomes.push((module.clone(), code));
+            differs |= self.walk(next_left, next_right, outcomes);
+            outcomes.pop();
+        }
+        if !differs {
+            self.same.insert((left, right));
+        }
+        differs
+    }
+}
+
+/// Every combination of module results the stacks end differently for, by their result or also by how many rules were run if `rules_run` is set, stopping after `limit` of them.
+///
+/// Modules are matched up by [ModuleId], and each is tried with the results [outcome_classes] gives for it in either stack. Modules that don't matter for a difference are left out of it, so each can cover many combinations.
+pub fn differences(left: &RuleSet, right: &RuleSet, rules_run: bool, limit: usize) -> Differences {
+    let left_ids = module_ids(&left.rules);
+    let right_ids = module_ids(&right.rules);
+    let mut modules: Vec<(ModuleId, Vec<ReturnCode>)> = Vec::new();
+    for (id, rule) in left_ids
+        .iter()
+        .zip(left.rules.iter())
+        .chain(right_ids.iter().zip(right.rules.iter()))
+    {
+        let position = match modules.iter().position(|(module, _)| module == id) {
+            Some(position) => position,
+            None => {
+                modules.push((id.clone(), Vec::new()));
+                modules.len() - 1
+            }
+        };
+        for code in outcome_classes(rule) {
+            if !modules[position].1.contains(&code) {
+                modules[position].1.push(code);
+            }
+        }
+    }
+    let domains: HashMap<ModuleId, Vec<ReturnCode>> = modules.iter().cloned().collect();
+    let mut comparison = Comparison {
+        left: Diagram::build(&left.facility, &left.rules, |index, _| {
+            domains[&left_ids[index]].clone()
+        }),
+        right: Diagram::build(&right.facility, &right.rules, |index, _| {
+            domains[&right_ids[index]].clone()
+        }),
+        order: modules
+            .iter()
+            .enumerate()
+            .map(|(position, (id, _))| (id.clone(), position))
+            .collect(),
+        right_rules: right_ids
+            .iter()
+            .enumerate()
+            .map(|(index, id)| (id.clone(), index))
+            .collect(),
+        left_ids,
+        right_ids,
+        domains,
+        rules_run,
+        limit,
+        found: Vec::new(),
+        stopped: false,
+        same: HashSet::new(),
+    };
+    let (left_root, right_root) = (comparison.left.root, comparison.right.root);
+    comparison.walk(left_root, right_root, &mut Vec::new());
+    Differences {
+        modules,
+        differences: comparison.found,
+        complete: !comparison.stopped,
+    }
+}
+
+/// A combination of module results the stacks give different results for, `None` if they're equivalent, see [differences].
+pub fn difference(left: &RuleSet, right: &RuleSet) -> Option<Difference> {
+    differences(left, right, false, 1)
+        .differences
+        .into_iter()
+        .next()
+}
@@ -0,0 +1,361 @@
+//! A lossless syntax tree for pam.d files.
+//!
+//! Every byte of the input is kept, including comments, blank lines, alignment and line endings, so a file can be modified and written back out without destroying anything the operator wrote. The semantic [Rule]s are built on top of it.
+
+use crate::{parse_line, ParseError, Rule};
+use serde::{Deserialize, Serialize};
+use std::fmt::Display;
+use std::ops::Range;
+
+#[derive(Clone, Copy, Debug, Deserialize, Serialize, Eq, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum TokenKind {
+    /// Spaces, tabs, and the line endings of blank or comment lines within a continued rule
+    Whitespace,
+    Comment,
+    /// A `\` at the end of a line, with anything after it up to and including the line ending
+    Continuation,
+    Facility,
+    Control,
+    Module,
+    Argument,
+    /// The `@include` keyword
+    Include,
+    /// The file named by an `@include`
+    IncludeTarget,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize, Eq, PartialEq)]
+pub struct Token {
+    pub kind: TokenKind,
+    pub text: String,
+}
+
+impl Token {
+    fn new(kind: TokenKind, text: &str) -> Self {
+        Self {
+            kind,
+            text: text.to_string(),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Deserialize, Serialize, Eq, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum EntryKind {
+    Blank,
+    Comment,
+    Rule,
+    /// Debian's `@include file`
+    Include,
+}
+
+/// A logical line of the file, made up of tokens which between them hold all of its text.
+///
+/// A rule continued with `\` spans several physical lines, as do any blank or comment lines in the middle of it, which libpam skips over.
+#[derive(Clone, Debug, Deserialize, Serialize, Eq, PartialEq)]
+pub struct Entry {
+    pub kind: EntryKind,
+    /// 1-based line number of the first line
+    pub line: usize,
+    pub tokens: Vec<Token>,
+    /// The line ending, which is empty if the file doesn't end with one
+    pub ending: String,
+}
+
+impl Entry {
+    fn lex(text: &str, ending: &str, line: usize) -> Self {
+        let lexemes = lex(text);
+        let mut fields = lexemes
+            .iter()
+            .filter(|(kind, _)| *kind == Lexeme::Field)
+            .map(|(_, range)| &text[range.clone()]);
+
+        let (kind, field_kind): (EntryKind, fn(usize) -> TokenKind) = match fields.next() {
+            None if lexemes.iter().any(|(kind, _)| *kind == Lexeme::Comment) => {
+                (EntryKind::Comment, |_| TokenKind::Argument)
+            }
+            None => (EntryKind::Blank, |_| TokenKind::Argument),
+            Some("@include") => (EntryKind::Include, |index| match index {
+                0 => TokenKind::Include,
+                1 => TokenKind::IncludeTarget,
+                _ => TokenKind::Argument,
+            }),
+            Some(_) => (EntryKind::Rule, |index| match index {
+                0 => TokenKind::Facility,
+                1 => TokenKind::Control,
+                2 => TokenKind::Module,
+                _ => TokenKind::Argument,
+            }),
+        };
+
+        let mut index = 0;
+        let tokens = lexemes
+            .into_iter()
+            .map(|(lexeme, range)| {
+                let kind = match lexeme {
+                    Lexeme::Whitespace => TokenKind::Whitespace,
+                    Lexeme::Comment => TokenKind::Comment,
+                    Lexeme::Continuation => TokenKind::Continuation,
+                    Lexeme::Field => {
+                        index += 1;
+                        field_kind(index - 1)
+                    }
+                };
+                Token::new(kind, &text[range])
+            })
+            .collect();
+
+        Self {
+            kind,
+            line,
+            tokens,
+            ending: ending.to_string(),
+        }
+    }
+
+    /// The text of the entry, without the final line ending
+    pub fn text(&self) -> String {
+        self.tokens
+            .iter()
+            .map(|token| token.text.as_str())
+            .collect()
+    }
+
+    /// The tokens that aren't whitespace, comments or continuations
+    pub fn fields(&self) -> impl Iterator<Item = &Token> {
+        self.tokens.iter().filter(|token| {
+            !matches!(
+                token.kind,
+                TokenKind::Whitespace | TokenKind::Comment | TokenKind::Continuation
+            )
+        })
+    }
+
+    pub fn field(&self, kind: TokenKind) -> Option<&Token> {
+        self.tokens.iter().find(|token| token.kind == kind)
+    }
+
+    /// The lints allowed by any `# pam-explainer: allow(ID, ...)` comments in the entry.
+    pub fn allowed_lints(&self) -> Vec<String> {
+        self.tokens
+            .iter()
+            .filter(|token| token.kind == TokenKind::Comment)
+            .flat_map(|token| parse_allow(&token.text))
+            .collect()
+    }
+}
+
+/// The IDs in a `# pam-explainer: allow(ID, ...)` comment.
+fn parse_allow(comment: &str) -> Vec<String> {
+    let ids = comment
+        .trim_start_matches('#')
+        .trim()
+        .strip_prefix("pam-explainer:")
+        .and_then(|rest| rest.trim().strip_prefix("allow("))
+        .and_then(|rest| rest.split_once(')'))
+        .map(|(ids, _)| ids)
+        .unwrap_or_default();
+    ids.split(',')
+        .map(str::trim)
+        .filter(|id| !id.is_empty())
+        .map(str::to_string)
+        .collect()
+}
+
+/// Keeps track of the lints allowed by comment lines, which apply to the rule right after them.
+#[derive(Debug, Default)]
+pub(crate) struct Allowances(Vec<String>);
+
+impl Allowances {
+    /// The lints allowed for `entry` if it's a rule, by its own comments and the comment lines just before it. A blank line ends a run of comments.
+    pub(crate) fn next(&mut self, entry: &Entry) -> Vec<String> {
+        match entry.kind {
+            EntryKind::Blank => {
+                self.0.clear();
+                Vec::new()
+            }
+            EntryKind::Comment => {
+                self.0.extend(entry.allowed_lints());
+                Vec::new()
+            }
+            EntryKind::Rule | EntryKind::Include => {
+                let mut allowed = std::mem::take(&mut self.0);
+                allowed.extend(entry.allowed_lints());
+                allowed
+            }
+        }
+    }
+}
+
+impl Display for Entry {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for token in self.tokens.iter() {
+            write!(f, "{}", token.text)?;
+        }
+        write!(f, "{}", self.ending)
+    }
+}
+
+/// The pieces [lex] splits a logical line into.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub(crate) enum Lexeme {
+    Whitespace,
+    Comment,
+    Continuation,
+    Field,
+}
+
+/// Split a logical line into pieces the way libpam does.
+///
+/// - Fields are separated by whitespace.
+/// - A field starting with `[` runs to the first `]` that isn't escaped as `\]`, spaces and all. If there isn't one it takes the rest of the line.
+/// - A `#` anywhere, even in the middle of a field or brackets, comments out the rest of the physical line.
+/// - A `\` straight before the newline continues the rule on the next one.
+pub(crate) fn lex(text: &str) -> Vec<(Lexeme, Range<usize>)> {
+    let mut lexemes = Vec::new();
+    let mut pos = 0;
+    while pos < text.len() {
+        let rest = &text[pos..];
+        let (lexeme, len) = if let Some(len) = continuation_len(rest) {
+            (Lexeme::Continuation, len)
+        } else if rest.starts_with(char::is_whitespace) {
+            (Lexeme::Whitespace, rest.len() - rest.trim_start().len())
+        } else if rest.starts_with('#') {
+            let line = &rest[..rest.find('\n').unwrap_or(rest.len())];
+            (Lexeme::Comment, line.trim_end_matches('\r').len())
+        } else if rest.starts_with('[') {
+            let comment = rest.find('#').unwrap_or(rest.len());
+            (
+                Lexeme::Field,
+                bracket_end(&rest[..comment]).unwrap_or(comment),
+            )
+        } else {
+            let len = rest
+                .char_indices()
+                .find(|(index, c)| {
+                    c.is_whitespace() || *c == '#' || continuation_len(&rest[*index..]).is_some()
+                })
+                .map(|(index, _)| index)
+                .unwrap_or(rest.len());
+            (Lexeme::Field, len)
+        };
+        lexemes.push((lexeme, pos..pos + len));
+        pos += len;
+    }
+    lexemes
+}
+
+/// The length of the line continuation at the start of `value`, if there is one. Like libpam, anything between the `\\` and the newline means it isn't one, even a `\r`.
+fn continuation_len(value: &str) -> Option<usize> {
+    match value.strip_prefix('\\')? {
+        "" => Some(1),
+        rest if rest.starts_with('\n') => Some(2),
+        _ => None,
+    }
+}
+
+/// Where a `[ ... ]` field starting at the start of `value` ends, just after the closing bracket.
+pub(crate) fn bracket_end(value: &str) -> Option<usize> {
+    let bytes = value.as_bytes();
+    let mut index = 1;
+    while index < bytes.len() {
+        match bytes[index] {
+            b'\\' if bytes.get(index + 1) == Some(&b']') => index += 2,
+            b']' => return Some(index + 1),
+            _ => index += 1,
+        }
+    }
+    None
+}
+
+/// A whole pam.d file.
+#[derive(Clone, Debug, Default, Deserialize, Serialize, Eq, PartialEq)]
+pub struct SyntaxFile {
+    pub entries: Vec<Entry>,
+}
+
+impl SyntaxFile {
+    pub fn parse(text: &str) -> Self {
+        let mut lines = text
+            .split_inclusive('\n')
+            .map(|raw| {
+                let text = raw.trim_end_matches(['\r', '\n']);
+                (text, &raw[text.len()..])
+            })
+            .peekable();
+
+        let mut entries = Vec::new();
+        let mut line = 1;
+        while let Some((text, ending)) = lines.next() {
+            let first = line;
+            line += 1;
+            let start = text.trim_start();
+            if start.is_empty() || start.starts_with('#') || !continues(text, ending) {
+                entries.push(Entry::lex(text, ending, first));
+                continue;
+            }
+            // gather up the rest of the rule, libpam skips blank and comment lines while it does
+            let mut joined = format!("{}{}", text, ending);
+            let mut ending = "";
+            while let Some((text, next_ending)) = lines.next() {
+                line += 1;
+                let start = text.trim_start();
+                let done =
+                    !start.is_empty() && !start.starts_with('#') && !continues(text, next_ending);
+                if done || lines.peek().is_none() {
+                    joined.push_str(text);
+                    ending = next_ending;
+                    break;
+                }
+                joined.push_str(text);
+                joined.push_str(next_ending);
+            }
+            entries.push(Entry::lex(&joined, ending, first));
+        }
+        Self { entries }
+    }
+
+    /// Build the semantic rules from the file. `file` is used to label the diagnostics.
+    pub fn rules(&self, file: Option<&str>, results: &[Rule]) -> (Vec<Rule>, Vec<ParseError>) {
+        let mut rules = Vec::new();
+        let mut diagnostics = Vec::new();
+        let mut allowances = Allowances::default();
+        for entry in self.entries.iter() {
+            let allow = allowances.next(entry);
+            if let Some(rule) = parse_line(
+                &entry.text(),
+                file,
+                entry.line,
+                rules.len() as u32,
+                results,
+                allow,
+                &mut diagnostics,
+            ) {
+                rules.push(rule);
+            }
+        }
+        (rules, diagnostics)
+    }
+}
+
+impl Display for SyntaxFile {
+    /// Writes the file back out exactly as it was parsed, plus any changes made to the tokens.
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for entry in self.entries.iter() {
+            write!(f, "{}", entry)?;
+        }
+        Ok(())
+    }
+}
+
+/// If the physical line ends with a `\\` straight before the newline, so the rule carries on onto the next one. A `\\` after a `#` is part of the comment.
+fn continues(line: &str, ending: &str) -> bool {
+    ending == "\n" && line.ends_with('\\') && !line.contains('#')
+}
+
+impl From<&str> for SyntaxFile {
+    fn from(value: &str) -> Self {
+        Self::parse(value)
+    }
+}
@@ -0,0 +1,149 @@
+//! A record of what happened to each rule when a stack was run, so everything showing the result explains it the same way.
+
+use crate::{Action, Impression, ReturnCode, Rule};
+use serde::{Deserialize, Serialize};
+use std::fmt::Display;
+
+/// The state of the stack, libpam's `impression` and `status`.
+#[derive(Clone, Copy, Debug, Deserialize, Serialize, Eq, PartialEq)]
+pub struct StackState {
+    pub impression: Impression,
+    pub status: ReturnCode,
+}
+
+impl Display for StackState {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.impression {
+            Impression::Undefined => write!(f, "undecided"),
+            Impression::Positive => write!(f, "succeeding with {}", self.status),
+            Impression::Negative => write!(f, "failing with {}", self.status),
+        }
+    }
+}
+
+/// Why a rule's module wasn't run.
+#[derive(Clone, Copy, Debug, Deserialize, Serialize, Eq, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum SkipReason {
+    /// Jumped over by the rule at this index
+    Jumped { by: usize },
+    /// The rule at this index returned `done`, like a `sufficient` rule succeeding
+    Done { by: usize },
+    /// The rule at this index returned `die`, like a `requisite` rule failing
+    Died { by: usize },
+    /// The module isn't installed and the rule starts with a `-`
+    MissingModule,
+}
+
+/// What happened to a rule.
+#[derive(Clone, Copy, Debug, Deserialize, Serialize, Eq, PartialEq)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum Outcome {
+    Invoked {
+        code: ReturnCode,
+        action: Action,
+        /// The stack state changed, it doesn't if the stack had already failed or frozen a different code
+        changed: bool,
+        /// A jump that went past the end of the (sub)stack, which fails it
+        bad_jump: bool,
+    },
+    /// The header of a substack, which doesn't run anything itself
+    Substack,
+    /// An `include` that wasn't resolved, so there was nothing to run
+    UnresolvedInclude,
+    Skipped {
+        reason: SkipReason,
+    },
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize, Eq, PartialEq)]
+pub struct Step {
+    /// Which rule in the [crate::RuleSet] this is about
+    pub index: usize,
+    pub outcome: Outcome,
+    /// The state of the stack once the rule was dealt with
+    pub state: StackState,
+}
+
+impl Step {
+    /// Explain what happened, `rules` are the rules of the stack the step is from, used to number the rules the way the config does.
+    pub fn explain(&self, rules: &[Rule]) -> String {
+        let number = |index: usize| {
+            rules
+                .get(index)
+                .and_then(|rule| rule.rule_order)
+                .map(|order| format!("#{}", order))
+                .unwrap_or_else(|| format!("#{}", index))
+        };
+        match self.outcome {
+            Outcome::Invoked {
+                code,
+                action,
+                changed,
+                bad_jump,
+            } => {
+                let mut explanation =
+                    format!("Returned {}, so {}: {}", code, action, action.explain());
+                if bad_jump {
+                    explanation.push_str(" That goes past the end of the stack, so it fails.");
+                } else if !changed && !matches!(action, Action::Ignore | Action::Jump(_)) {
+                    explanation.push_str(&format!(
+                        " It doesn't change anything, the stack was already {}.",
+                        self.state
+                    ));
+                }
+                explanation
+            }
+            Outcome::Substack => "Starts a substack.".to_string(),
+            Outcome::UnresolvedInclude => {
+                "The include wasn't resolved, so there's nothing to run.".to_string()
+            }
+            Outcome::Skipped { reason } => match reason {
+                SkipReason::Jumped { by } => {
+                    format!("Skipped, rule {} jumped over it.", number(by))
+                }
+                SkipReason::Done { by } => {
+                    format!(
+                        "Skipped, rule {} already decided the (sub)stack.",
+                        number(by)
+                    )
+                }
+                SkipReason::Died { by } => {
+                    format!(
+                        "Skipped, rule {} ended the (sub)stack with a failure.",
+                        number(by)
+                    )
+                }
+                SkipReason::MissingModule => {
+                    "Skipped, the module isn't installed and the leading '-' allows that."
+                        .to_string()
+                }
+            },
+        }
+    }
+
+    pub fn was_run(&self) -> bool {
+        matches!(self.outcome, Outcome::Invoked { .. })
+    }
+}
+
+/// Everything that happened when a stack was run.
+#[derive(Clone, Debug, Deserialize, Serialize, Eq, PartialEq)]
+pub struct Trace {
+    /// One for each rule, in the order of the rules
+    pub steps: Vec<Step>,
+    /// What the facility hands back to the application
+    pub result: ReturnCode,
+}
+
+impl Trace {
+    /// How many modules were actually run.
+    pub fn rules_run(&self) -> usize {
+        self.steps.iter().filter(|step| step.was_run()).count()
+    }
+
+    /// The step for the rule at `index`.
+    pub fn step(&self, index: usize) -> Option<&Step> {
+        self.steps.iter().find(|step| step.index == index)
+    }
+}
@@ -0,0 +1,119 @@
+//! What a facility hands back for every combination of module results, with rows that only differ in a result that doesn't matter collapsed into one.
+
+use crate::paths::outcome_classes;
+use crate::{Control, Facility, ReturnCode, Rule, RuleSet};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::hash::Hash;
+
+#[derive(Clone, Debug, Deserialize, Serialize, Eq, PartialEq)]
+pub struct Row {
+    /// What each column's module returned, `None` if it doesn't matter, either because it wasn't run or the row is the same whatever it returned
+    pub outcomes: Vec<Option<ReturnCode>>,
+    pub result: ReturnCode,
+    pub rules_run: usize,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize, Eq, PartialEq)]
+pub struct TruthTable {
+    pub facility: Facility,
+    pub rules: Vec<Rule>,
+    /// The index of the rule each column is for, substack headers and unresolved includes don't run anything so they don't get one
+    pub columns: Vec<usize>,
+    pub rows: Vec<Row>,
+    /// Every combination was tried, if not the table is missing rows
+    pub complete: bool,
+}
+
+impl RuleSet {
+    /// Every combination of what the modules can return, with one result for each action the control can take, see [crate::paths::outcome_classes]. The results already set on the rules are ignored.
+    ///
+    /// Stops once `limit` ways through the stack have been found, it can double with every rule.
+    pub fn truth_table(&self, limit: usize) -> TruthTable {
+        let mut rules = self.rules.clone();
+        rules.iter_mut().for_each(|rule| rule.final_result = None);
+        let columns: Vec<usize> = (0..rules.len())
+            .filter(|index| !matches!(rules[*index].control, Control::Include | Control::Substack))
+            .collect();
+        let paths = crate::paths::paths(&self.facility, &rules, limit);
+        let rows = paths
+            .paths
+            .iter()
+            .map(|path| {
+                let outcomes = columns
+                    .iter()
+                    .map(|index| path.outcomes.get(index).copied())
+                    .collect();
+                (outcomes, (path.trace.result, path.trace.rules_run()))
+            })
+            .collect();
+        let classes: Vec<Vec<ReturnCode>> = columns
+            .iter()
+            .map(|index| outcome_classes(&rules[*index]))
+            .collect();
+        TruthTable {
+            facility: self.facility.clone(),
+            rows: collapse(rows, &classes)
+                .into_iter()
+                .map(|(outcomes, (result, rules_run))| Row {
+                    outcomes,
+                    result,
+                    rules_run,
+                })
+                .collect(),
+            rules,
+            columns,
+            complete: paths.complete,
+        }
+    }
+}
+
+/// Merge rows that end the same way and are the same apart from one column, where between them they have every result that column's module can give, into one row that doesn't care about the column. Keeps going until nothing else can be merged.
+///
+/// Each pass over a column groups the rows by how they end and what they have in the other columns, so it's linear in the rows rather than comparing every pair.
+pub(crate) fn collapse<T: Clone + Eq + Hash>(
+    mut rows: Vec<(Vec<Option<ReturnCode>>, T)>,
+    classes: &[Vec<ReturnCode>],
+) -> Vec<(Vec<Option<ReturnCode>>, T)> {
+    let mut merged = true;
+    while merged {
+        merged = false;
+        for (column, codes) in classes.iter().enumerate() {
+            if codes.len() < 2 {
+                continue;
+            }
+            let mut groups: HashMap<(Vec<Option<ReturnCode>>, &T), Vec<usize>> = HashMap::new();
+            for (index, (outcomes, ending)) in rows.iter().enumerate() {
+                if outcomes[column].is_some() {
+                    let mut masked = outcomes.clone();
+                    masked[column] = None;
+                    groups.entry((masked, ending)).or_default().push(index);
+                }
+            }
+            let mut keep = vec![true; rows.len()];
+            let mut cleared = Vec::new();
+            for group in groups.into_values() {
+                let covered = codes.iter().all(|code| {
+                    group
+                        .iter()
+                        .any(|index| rows[*index].0[column] == Some(*code))
+                });
+                if covered {
+                    // the merged row takes the place of the first one, to keep the order
+                    cleared.push(group[0]);
+                    group[1..].iter().for_each(|index| keep[*index] = false);
+                }
+            }
+            if cleared.is_empty() {
+                continue;
+            }
+            merged = true;
+            for index in cleared {
+                rows[index].0[column] = None;
+            }
+            let mut keep = keep.into_iter();
+            rows.retain(|_| keep.next().unwrap_or(true));
+        }
+    }
+    rows
+}
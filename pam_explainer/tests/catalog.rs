@@ -0,0 +1,131 @@
+//! Loading every service from a sysroot, with the vendor directory filling in and `other` as the fallback, like libpam.
+
+use pam_explainer::catalog::{ServiceCatalog, OTHER_SERVICE, PAM_D, VENDOR_PAM_D};
+use pam_explainer::{Facility, LoadError, Rule};
+use std::path::{Path, PathBuf};
+
+/// Write the files out under a fresh sysroot in the target directory, the paths are relative to it.
+fn sysroot(name: &str, files: &[(&str, &str)]) -> PathBuf {
+    let root = PathBuf::from(env!("CARGO_TARGET_TMPDIR")).join(name);
+    if root.exists() {
+        std::fs::remove_dir_all(&root).expect("removed the old sysroot");
+    }
+    for (path, contents) in files {
+        let path = root.join(path);
+        std::fs::create_dir_all(path.parent().expect("in a directory"))
+            .expect("created the directory");
+        std::fs::write(path, contents).expect("wrote the file");
+    }
+    root
+}
+
+fn lines(rules: &[Rule]) -> Vec<String> {
+    rules.iter().map(Rule::to_config_line).collect()
+}
+
+/// The file each rule came from, relative to the sysroot, and the line it's on.
+fn sources(root: &Path, rules: &[Rule]) -> Vec<(String, usize)> {
+    rules
+        .iter()
+        .map(|rule| {
+            let file = PathBuf::from(rule.source.file.clone().expect("from a file"));
+            let file = file.strip_prefix(root).expect("inside the sysroot");
+            (file.display().to_string(), rule.source.line)
+        })
+        .collect()
+}
+
+#[test]
+fn vendor_directory_fills_in() {
+    let root = sysroot(
+        "catalog-vendor",
+        &[
+            (
+                "etc/pam.d/login",
+                "# local changes\nauth include system-auth\naccount required pam_unix.so\n",
+            ),
+            ("usr/lib/pam.d/login", "auth required pam_deny.so\n"),
+            (
+                "usr/lib/pam.d/system-auth",
+                "auth required pam_env.so\n\nauth required pam_unix.so\n",
+            ),
+        ],
+    );
+    let catalog = ServiceCatalog::load_sysroot(&root, &[]).expect("loads");
+    assert_eq!(catalog.dirs, [root.join(PAM_D), root.join(VENDOR_PAM_D)]);
+    assert!(catalog.failures.is_empty());
+
+    // /etc/pam.d takes precedence
+    let login = catalog.service("login").expect("there's a login");
+    assert_eq!(login.path, root.join("etc/pam.d/login"));
+    assert_eq!(
+        lines(&login.rules),
+        [
+            "auth required pam_env.so",
+            "auth required pam_unix.so",
+            "account required pam_unix.so",
+        ]
+    );
+    // the included rules say where they came from
+    assert_eq!(
+        sources(&root, &login.rules),
+        [
+            ("usr/lib/pam.d/system-auth".to_string(), 1),
+            ("usr/lib/pam.d/system-auth".to_string(), 3),
+            ("etc/pam.d/login".to_string(), 3),
+        ]
+    );
+    assert_eq!(
+        lines(&catalog.stack("login", &Facility::Auth)),
+        ["auth required pam_env.so", "auth required pam_unix.so"]
+    );
+    // and services only in the vendor directory are there too
+    assert_eq!(
+        catalog.service("system-auth").map(|service| &service.path),
+        Some(&root.join("usr/lib/pam.d/system-auth"))
+    );
+}
+
+#[test]
+fn unknown_services_fall_back_to_other() {
+    let root = sysroot(
+        "catalog-other",
+        &[
+            ("etc/pam.d/login", "auth required pam_unix.so\n"),
+            (
+                "etc/pam.d/other",
+                "auth required pam_warn.so\nauth required pam_deny.so\n",
+            ),
+            ("etc/pam.d/broken", "auth include nowhere\n"),
+        ],
+    );
+    let catalog = ServiceCatalog::load_sysroot(&root, &[]).expect("loads");
+    let ftp = catalog.service("ftp").expect("falls back");
+    assert_eq!(ftp.name, OTHER_SERVICE);
+    assert_eq!(
+        lines(&catalog.stack("ftp", &Facility::Auth)),
+        ["auth required pam_warn.so", "auth required pam_deny.so"]
+    );
+    // service names are lowercased like pam_start does
+    assert_eq!(
+        catalog
+            .service("LOGIN")
+            .map(|service| service.name.as_str()),
+        Some("login")
+    );
+    // a service that failed to load doesn't fall back, libpam refuses to run it
+    assert!(matches!(
+        catalog.failures.get("broken"),
+        Some(LoadError::IncludeNotFound { target }) if target == "nowhere"
+    ));
+    assert!(catalog.service("broken").is_none());
+}
+
+#[test]
+fn sysroot_without_a_pam_d() {
+    let root = sysroot("catalog-empty", &[("etc/pam.conf", "")]);
+    assert!(matches!(
+        ServiceCatalog::load_sysroot(&root, &[]),
+        Err(LoadError::NoPamDir { sysroot }) if sysroot == root
+    ));
+}
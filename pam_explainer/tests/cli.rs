@@ -0,0 +1,132 @@
+//! Running the command line tool, checking what it writes out.
+
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+/// A fresh directory under the target directory.
+fn fresh_dir(name: &str) -> PathBuf {
+    let dir = PathBuf::from(env!("CARGO_TARGET_TMPDIR")).join(name);
+    if dir.exists() {
+        std::fs::remove_dir_all(&dir).expect("removed the old directory");
+    }
+    std::fs::create_dir_all(&dir).expect("created the directory");
+    dir
+}
+
+/// Run the tool, returning what it printed. It has to succeed.
+fn run(args: &[&str]) -> String {
+    let output = Command::new(env!("CARGO_BIN_EXE_pam_explainer"))
+        .args(args)
+        .output()
+        .expect("ran the tool");
+    assert!(
+        output.status.success(),
+        "{:?} failed: {}",
+        args,
+        String::from_utf8_lossy(&output.stderr)
+    );
+    String::from_utf8(output.stdout).expect("printed UTF-8")
+}
+
+fn read(path: &Path) -> String {
+    std::fs::read_to_string(path).expect("read the file")
+}
+
+const LOGIN: &str = "\
+# local passwords first
+auth      required    pam_env.so
+auth      [success=1 default=ignore]  pam_unix.so nullok
+
+auth      requisite   pam_deny.so
+account   required    pam_unix.so
+";
+
+#[test]
+fn export_round_trips_pam_d_through_a_pam_conf() {
+    let dir = fresh_dir("cli-export");
+    let pam_d = dir.join("pam.d");
+    std::fs::create_dir(&pam_d).expect("created pam.d");
+    std::fs::write(pam_d.join("login"), LOGIN).expect("wrote login");
+    std::fs::write(pam_d.join("other"), "auth\t\trequired\tpam_deny.so\n").expect("wrote other");
+    let pam_d = pam_d.to_str().expect("a UTF-8 path");
+
+    // comments, blank lines and alignment are all kept
+    let pam_conf = run(&["export", "--to-pam-conf", pam_d]);
+    assert_eq!(
+        pam_conf,
+        "\
+# login
+# local passwords first
+login auth      required    pam_env.so
+login auth      [success=1 default=ignore]  pam_unix.so nullok
+
+login auth      requisite   pam_deny.so
+login account   required    pam_unix.so
+
+# other
+other auth\t\trequired\tpam_deny.so
+"
+    );
+
+    let pam_conf_path = dir.join("pam.conf");
+    std::fs::write(&pam_conf_path, &pam_conf).expect("wrote pam.conf");
+    let back = dir.join("back");
+    let back = back.to_str().expect("a UTF-8 path");
+    run(&[
+        "export",
+        "--pam-conf",
+        pam_conf_path.to_str().expect("a UTF-8 path"),
+        "--output",
+        back,
+    ]);
+    assert_eq!(read(&dir.join("back/login")), format!("# login\n{}", LOGIN));
+    assert_eq!(
+        read(&dir.join("back/other")),
+        "\n# other\nauth\t\trequired\tpam_deny.so\n"
+    );
+    // and they still do the same thing
+    run(&["diff", pam_d, back]);
+}
+
+#[test]
+fn export_inlines_includes_into_a_pam_conf() {
+    let dir = fresh_dir("cli-export-include");
+    std::fs::write(
+        dir.join("sshd"),
+        "# sshd\n@include common-auth  # shared\naccount  required  pam_unix.so\n",
+    )
+    .expect("wrote sshd");
+    std::fs::write(
+        dir.join("common-auth"),
+        "# not copied\nauth  required  pam_unix.so\naccount  required  pam_deny.so\n",
+    )
+    .expect("wrote common-auth");
+
+    let dir = dir.to_str().expect("a UTF-8 path");
+    assert_eq!(
+        run(&["export", "--to-pam-conf", dir]),
+        "\
+# sshd
+# sshd
+# @include common-auth  # shared
+sshd auth  required  pam_unix.so
+sshd account  required  pam_deny.so
+sshd account  required  pam_unix.so
+"
+    );
+    // just the service that's asked for, and what it includes
+    assert_eq!(
+        run(&["export", "--service", "sshd", "--facility", "account", dir]),
+        "\
+# common-auth
+# not copied
+account  required  pam_deny.so
+
+# sshd
+# sshd
+@include common-auth  # shared
+account  required  pam_unix.so
+
+"
+    );
+}
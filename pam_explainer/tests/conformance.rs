@@ -0,0 +1,745 @@
+//! Stacks run through [RuleSet::run_rules], checked against what Linux-PAM's `_pam_dispatch_aux` returns for them.
+//!
+//! Each line of a stack is a rule, prefixed with a `>` for every level of substack it's in. The results are what each module returns, in order, skipping `substack` lines as they don't run a module.
+
+use pam_explainer::{
+    bypass, diff, paths, symbolic, Control, Facility, Impression, Outcome, ReturnCode, Rule,
+    RuleSet, SkipReason, StackState,
+};
+use ReturnCode::*;
+
+/// Runs the stack, returning the facility's result and how many modules were run.
+fn run(stack: &str, results: &[ReturnCode]) -> (ReturnCode, usize) {
+    let mut results = results.iter();
+    let rules = stack
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty())
+        .enumerate()
+        .map(|(order, line)| {
+            let rule_text = line.trim_start_matches('>');
+            let mut rule = Rule::new(rule_text, &(order as u32), &[]).expect("valid rule");
+            rule.stack_level = line.len() - rule_text.len();
+            if rule.control != Control::Substack {
+                rule.final_result = Some(*results.next().expect("a result for every module"));
+            }
+            rule
+        })
+        .collect();
+    assert!(results.next().is_none(), "more results than modules");
+    let trace = RuleSet::new(&Facility::Auth, rules).run_rules();
+    (trace.result, trace.rules_run())
+}
+
+const SUFFICIENT: &str = "
+    auth required pam_env.so
+    auth sufficient pam_unix.so
+    auth required pam_deny.so
+";
+
+#[test]
+fn sufficient_success_ends_the_stack() {
+    assert_eq!(run(SUFFICIENT, &[Success, Success, AuthErr]), (Success, 2));
+}
+
+#[test]
+fn sufficient_success_after_a_failure_keeps_going() {
+    assert_eq!(
+        run(SUFFICIENT, &[SystemErr, Success, AuthErr]),
+        (SystemErr, 3)
+    );
+}
+
+#[test]
+fn sufficient_failure_is_ignored() {
+    assert_eq!(run(SUFFICIENT, &[Success, AuthErr, Success]), (Success, 3));
+}
+
+#[test]
+fn sufficient_alone_in_an_undecided_stack() {
+    let stack = "
+        auth optional pam_a.so
+        auth sufficient pam_b.so
+        auth required pam_deny.so
+    ";
+    assert_eq!(run(stack, &[AuthErr, Success, AuthErr]), (Success, 2));
+}
+
+#[test]
+fn every_sufficient_rule_is_tried_until_one_succeeds() {
+    let stack = "
+        auth sufficient pam_a.so
+        auth sufficient pam_b.so
+        auth required pam_deny.so
+    ";
+    assert_eq!(run(stack, &[AuthErr, Success, AuthErr]), (Success, 2));
+    assert_eq!(run(stack, &[AuthErr, AuthErr, AuthErr]), (AuthErr, 3));
+}
+
+#[test]
+fn sole_optional_rule_decides_the_result() {
+    assert_eq!(run("auth optional pam_a.so", &[Success]), (Success, 1));
+    // the failure is ignored, leaving nothing to say the stack succeeded
+    assert_eq!(run("auth optional pam_a.so", &[AuthErr]), (PermDenied, 1));
+}
+
+#[test]
+fn first_optional_failure_is_not_fatal() {
+    let stack = "
+        auth optional pam_a.so
+        auth required pam_b.so
+    ";
+    assert_eq!(run(stack, &[AuthErr, Success]), (Success, 2));
+}
+
+#[test]
+fn optional_success_is_overridden_by_a_required_failure() {
+    let stack = "
+        auth optional pam_a.so
+        auth required pam_b.so
+    ";
+    assert_eq!(run(stack, &[Success, AuthErr]), (AuthErr, 2));
+}
+
+#[test]
+fn optional_success_counts_when_the_rest_is_ignored() {
+    let stack = "
+        auth optional pam_a.so
+        auth required pam_b.so
+    ";
+    assert_eq!(run(stack, &[Success, Ignore]), (Success, 2));
+}
+
+#[test]
+fn required_failure_keeps_the_first_code() {
+    let stack = "
+        auth required pam_a.so
+        auth required pam_b.so
+        auth required pam_c.so
+    ";
+    assert_eq!(
+        run(stack, &[UserUnknown, AuthErr, Success]),
+        (UserUnknown, 3)
+    );
+}
+
+#[test]
+fn required_failure_runs_the_rest_of_the_stack() {
+    let stack = "
+        auth required pam_a.so
+        auth required pam_b.so
+    ";
+    assert_eq!(run(stack, &[AuthErr, Success]), (AuthErr, 2));
+}
+
+#[test]
+fn requisite_failure_stops_immediately() {
+    let stack = "
+        auth requisite pam_a.so
+        auth required pam_b.so
+    ";
+    assert_eq!(run(stack, &[AuthErr, Success]), (AuthErr, 1));
+}
+
+#[test]
+fn ignored_stack_fails() {
+    assert_eq!(run("auth required pam_a.so", &[Ignore]), (PermDenied, 1));
+}
+
+#[test]
+fn new_authtok_reqd_counts_as_success() {
+    let stack = "
+        auth required pam_a.so
+        auth required pam_b.so
+    ";
+    assert_eq!(run(stack, &[NewAuthtokReqd, Success]), (NewAuthtokReqd, 2));
+}
+
+#[test]
+fn success_after_another_code_does_not_replace_it() {
+    let stack = "
+        auth required pam_a.so
+        auth required pam_b.so
+    ";
+    assert_eq!(run(stack, &[Success, NewAuthtokReqd]), (NewAuthtokReqd, 2));
+}
+
+const DEBIAN_COMMON_AUTH: &str = "
+    auth [success=1 default=ignore] pam_unix.so nullok
+    auth requisite pam_deny.so
+    auth required pam_permit.so
+";
+
+#[test]
+fn debian_common_auth_success() {
+    assert_eq!(
+        run(DEBIAN_COMMON_AUTH, &[Success, AuthErr, Success]),
+        (Success, 2)
+    );
+}
+
+#[test]
+fn debian_common_auth_failure() {
+    assert_eq!(
+        run(DEBIAN_COMMON_AUTH, &[AuthErr, AuthErr, Success]),
+        (AuthErr, 2)
+    );
+}
+
+#[test]
+fn jump_does_not_record_a_result() {
+    assert_eq!(
+        run(
+            "auth [success=1 default=ignore] pam_a.so\nauth required pam_b.so",
+            &[Success, Success]
+        ),
+        (PermDenied, 1)
+    );
+}
+
+#[test]
+fn jump_past_the_end_fails() {
+    let stack = "
+        auth required pam_a.so
+        auth [success=2 default=ignore] pam_b.so
+        auth required pam_c.so
+    ";
+    assert_eq!(run(stack, &[Success, Success, Success]), (PermDenied, 2));
+}
+
+#[test]
+fn done_stops_unless_already_failed() {
+    let stack = "
+        auth [success=done default=bad] pam_a.so
+        auth required pam_b.so
+    ";
+    assert_eq!(run(stack, &[Success, AuthErr]), (Success, 1));
+    assert_eq!(run(stack, &[AuthErr, Success]), (AuthErr, 2));
+}
+
+#[test]
+fn die_stops_with_its_code() {
+    let stack = "
+        auth required pam_a.so
+        auth [success=ok default=die] pam_b.so
+        auth required pam_c.so
+    ";
+    assert_eq!(run(stack, &[Success, Maxtries, Success]), (Maxtries, 2));
+}
+
+#[test]
+fn bad_with_ignore_fails_with_perm_denied() {
+    let stack = "
+        auth [ignore=bad default=ok] pam_a.so
+        auth required pam_b.so
+    ";
+    assert_eq!(run(stack, &[Ignore, Success]), (PermDenied, 2));
+}
+
+#[test]
+fn bad_with_success_fails_with_perm_denied() {
+    let stack = "
+        auth [success=bad default=ok] pam_a.so
+        auth required pam_b.so
+    ";
+    assert_eq!(run(stack, &[Success, Success]), (PermDenied, 2));
+    let stack = "
+        auth [success=die default=ok] pam_a.so
+        auth required pam_b.so
+    ";
+    assert_eq!(run(stack, &[Success, Success]), (PermDenied, 1));
+}
+
+#[test]
+fn unlisted_value_defaults_to_bad() {
+    let stack = "
+        auth [success=ok] pam_a.so
+        auth required pam_b.so
+    ";
+    assert_eq!(run(stack, &[AuthErr, Success]), (AuthErr, 2));
+}
+
+#[test]
+fn reset_forgets_earlier_results() {
+    let stack = "
+        auth required pam_a.so
+        auth [success=ok default=reset] pam_b.so
+        auth required pam_c.so
+    ";
+    assert_eq!(run(stack, &[AuthErr, AuthErr, Success]), (Success, 3));
+}
+
+#[test]
+fn invalid_control_treats_every_result_as_bad() {
+    let stack = "
+        auth requird pam_a.so
+        auth required pam_b.so
+    ";
+    assert_eq!(run(stack, &[Success, Success]), (PermDenied, 2));
+}
+
+const SUBSTACK: &str = "
+    auth substack sub
+    >auth [success=done default=die] pam_a.so
+    >auth required pam_deny.so
+    auth required pam_b.so
+";
+
+#[test]
+fn done_only_ends_the_substack() {
+    assert_eq!(run(SUBSTACK, &[Success, AuthErr, Success]), (Success, 2));
+}
+
+#[test]
+fn die_only_ends_the_substack() {
+    assert_eq!(run(SUBSTACK, &[AuthErr, Success, Success]), (AuthErr, 2));
+}
+
+#[test]
+fn substack_counts_as_one_rule_for_jumps() {
+    let stack = "
+        auth [success=1 default=ignore] pam_a.so
+        auth substack sub
+        >auth required pam_deny.so
+        >auth required pam_deny.so
+        auth required pam_b.so
+    ";
+    assert_eq!(
+        run(stack, &[Success, AuthErr, AuthErr, Success]),
+        (Success, 2)
+    );
+}
+
+#[test]
+fn jump_cannot_leave_a_substack() {
+    let stack = "
+        auth substack sub
+        >auth [success=1 default=ignore] pam_a.so
+        auth required pam_b.so
+    ";
+    assert_eq!(run(stack, &[Success, Success]), (PermDenied, 2));
+}
+
+#[test]
+fn reset_in_a_substack_returns_to_its_start() {
+    let stack = "
+        auth required pam_a.so
+        auth substack sub
+        >auth required pam_b.so
+        >auth [default=reset] pam_c.so
+    ";
+    assert_eq!(run(stack, &[Success, AuthErr, AuthErr]), (Success, 3));
+}
+
+/// The rules of a stack with every module returning `result`.
+fn ruleset(stack: &str, results: &[ReturnCode]) -> RuleSet {
+    let mut results = results.iter();
+    let rules = stack
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty())
+        .enumerate()
+        .map(|(order, line)| {
+            let mut rule = Rule::new(line, &(order as u32), &[]).expect("valid rule");
+            rule.final_result = results.next().copied();
+            rule
+        })
+        .collect();
+    RuleSet::new(&Facility::Auth, rules)
+}
+
+#[test]
+fn trace_records_why_rules_were_skipped() {
+    let trace = ruleset(SUFFICIENT, &[Success, Success, AuthErr]).run_rules();
+    assert_eq!(trace.steps.len(), 3);
+    assert_eq!(
+        trace.steps[2].outcome,
+        Outcome::Skipped {
+            reason: SkipReason::Done { by: 1 }
+        }
+    );
+
+    let trace = ruleset(DEBIAN_COMMON_AUTH, &[Success, AuthErr, Success]).run_rules();
+    assert_eq!(
+        trace.steps[1].outcome,
+        Outcome::Skipped {
+            reason: SkipReason::Jumped { by: 0 }
+        }
+    );
+}
+
+#[test]
+fn trace_records_the_state_after_each_rule() {
+    let stack = "
+        auth required pam_a.so
+        auth required pam_b.so
+    ";
+    let trace = ruleset(stack, &[AuthErr, UserUnknown]).run_rules();
+    let failing = StackState {
+        impression: Impression::Negative,
+        status: AuthErr,
+    };
+    assert_eq!(trace.steps[0].state, failing);
+    assert_eq!(trace.steps[1].state, failing);
+    assert!(matches!(
+        trace.steps[1].outcome,
+        Outcome::Invoked {
+            code: UserUnknown,
+            changed: false,
+            ..
+        }
+    ));
+}
+
+#[test]
+fn dash_prefixed_rule_with_a_missing_module_is_skipped() {
+    let mut systemd = Rule::new("-session optional pam_systemd.so", &1, &[]).expect("valid rule");
+    assert!(systemd.silent_if_missing);
+    assert_eq!(systemd.facility, Facility::Session);
+    assert_eq!(systemd.to_config_line(), "-session optional pam_systemd.so");
+
+    systemd.final_result = Some(ModuleUnknown);
+    assert!(systemd.is_skipped_missing_module());
+    assert!(
+        systemd.result_string().contains("silently skipped"),
+        "{}",
+        systemd.result_string()
+    );
+    let mut unix = Rule::new("session required pam_unix.so", &0, &[]).expect("valid rule");
+    unix.final_result = Some(Success);
+    let trace = RuleSet::new(&Facility::Session, vec![unix, systemd]).run_rules();
+    assert_eq!(trace.result, Success);
+    assert_eq!(
+        trace.steps[1].outcome,
+        Outcome::Skipped {
+            reason: SkipReason::MissingModule
+        }
+    );
+
+    // without the dash, a missing module is a failure like any other
+    let mut strict = Rule::new("session required pam_systemd.so", &0, &[]).expect("valid rule");
+    assert!(!strict.silent_if_missing);
+    strict.final_result = Some(ModuleUnknown);
+    assert!(!strict.is_skipped_missing_module());
+    let trace = RuleSet::new(&Facility::Session, vec![strict]).run_rules();
+    assert_eq!(trace.result, ModuleUnknown);
+}
+
+/// The rules of a stack with `>` marking substacks like [run], but without any results so they can be worked out symbolically.
+fn unresolved(stack: &str) -> RuleSet {
+    let rules = stack
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty())
+        .enumerate()
+        .map(|(order, line)| {
+            let rule_text = line.trim_start_matches('>');
+            let mut rule = Rule::new(rule_text, &(order as u32), &[]).expect("valid rule");
+            rule.stack_level = line.len() - rule_text.len();
+            rule
+        })
+        .collect();
+    RuleSet::new(&Facility::Auth, rules)
+}
+
+#[test]
+fn diagram_agrees_with_running_the_stack() {
+    let reset = "
+        auth required pam_a.so
+        auth substack sub
+        >auth [success=1 default=ignore] pam_b.so
+        >auth [default=reset] pam_c.so
+        >auth [success=done new_authtok_reqd=die default=bad] pam_d.so
+        auth [success=2 default=ignore] pam_e.so
+        auth requisite pam_deny.so
+    ";
+    for stack in [SUFFICIENT, DEBIAN_COMMON_AUTH, SUBSTACK, reset] {
+        let ruleset = unresolved(stack);
+        let diagram = ruleset.diagram();
+        let paths = ruleset.paths(paths::MAX_PATHS);
+        assert!(paths.complete);
+        for path in paths.paths {
+            assert_eq!(
+                diagram
+                    .evaluate(|index| path.outcomes[&index])
+                    .map(|ending| (ending.result, ending.rules_run)),
+                Some((path.trace.result, path.trace.rules_run())),
+                "{:?}",
+                path.outcomes
+            );
+        }
+    }
+}
+
+#[test]
+fn diagram_grows_with_the_stack_not_the_combinations() {
+    let diagram = unresolved(&"auth required pam_u2f.so nouserok\n".repeat(40)).diagram();
+    assert!(diagram.nodes.len() < 200, "{} nodes", diagram.nodes.len());
+    let required = diagram.required(Success).expect("success is reachable");
+    assert_eq!(required.len(), 40);
+    // any of them can return ignore for a user without a key, as long as one of the others succeeds
+    assert!(required.values().all(|codes| codes == &[Success, Ignore]));
+
+    // pam_unix.so can't return ignore, so every one of them has to succeed
+    let diagram = unresolved(&"auth required pam_unix.so\n".repeat(40)).diagram();
+    assert!(diagram.nodes.len() < 200, "{} nodes", diagram.nodes.len());
+    let required = diagram.required(Success).expect("success is reachable");
+    assert!(required.values().all(|codes| codes == &[Success]));
+}
+
+#[test]
+fn required_outcomes_of_debian_common_auth() {
+    let diagram = unresolved(DEBIAN_COMMON_AUTH).diagram();
+    assert_eq!(diagram.reaches(Success), Some([(0, Success)].into()));
+    assert_eq!(diagram.required(Success), Some([(0, vec![Success])].into()));
+    assert_eq!(
+        unresolved("auth requisite pam_deny.so\nauth sufficient pam_unix.so")
+            .diagram()
+            .reaches(Success),
+        None
+    );
+}
+
+#[test]
+fn equivalent_stacks_have_no_difference() {
+    let swapped = "
+        auth required pam_env.so
+        auth sufficient pam_sss.so
+        auth sufficient pam_unix.so
+        auth required pam_deny.so
+    ";
+    let original = "
+        auth required pam_env.so
+        auth sufficient pam_unix.so
+        auth sufficient pam_sss.so
+        auth required pam_deny.so
+    ";
+    assert_eq!(
+        symbolic::difference(&unresolved(original), &unresolved(swapped)),
+        None
+    );
+
+    let required = SUFFICIENT.replace("sufficient", "required");
+    let difference = symbolic::difference(&unresolved(SUFFICIENT), &unresolved(&required))
+        .expect("pam_unix succeeding is only enough in one");
+    assert_eq!(
+        (difference.left.result, difference.right.result),
+        (Success, AuthErr)
+    );
+    assert_ne!(difference.left, difference.right);
+}
+
+#[test]
+fn criticality_of_a_fallback_to_a_directory() {
+    let stack = "
+        auth required pam_env.so
+        auth [success=2 default=ignore] pam_unix.so
+        auth [success=1 default=ignore] pam_sss.so
+        auth requisite pam_deny.so
+        auth required pam_permit.so
+        auth optional pam_cap.so
+    ";
+    let report = unresolved(stack).criticality();
+    assert!(report.can_succeed);
+    // pam_env failing with buf_err is bad, whatever pam_unix or pam_sss return
+    assert_eq!(report.must_succeed, vec![0]);
+    assert_eq!(report.never_matters, vec![5]);
+    assert_eq!(report.sufficient, vec![vec![0, 1], vec![0, 2]]);
+}
+
+#[test]
+fn known_modules_only_return_what_they_can() {
+    let classes =
+        |rule: &str| paths::outcome_classes(&Rule::new(rule, &0, &[]).expect("valid rule"));
+    // everything else pam_unix returns is bad too
+    assert_eq!(classes("auth required pam_unix.so"), [Success, AuthErr]);
+    assert_eq!(classes("auth required pam_u2f.so"), [Success, AuthErr]);
+    assert_eq!(
+        classes("auth required pam_u2f.so nouserok"),
+        [Success, AuthErr, Ignore]
+    );
+    assert_eq!(
+        classes("auth [success=ok user_unknown=ignore default=bad] pam_sss.so"),
+        [Success, AuthErr, UserUnknown]
+    );
+    assert_eq!(
+        classes("auth required pam_env.so"),
+        [Success, Ignore, BufErr]
+    );
+    assert_eq!(classes("auth sufficient pam_deny.so"), [AuthErr]);
+    // it doesn't implement account, so libpam can't call it
+    assert_eq!(classes("account required pam_env.so"), [ModuleUnknown]);
+    // a module nothing's known about could return anything
+    assert_eq!(
+        classes("auth required pam_a.so"),
+        [Success, AuthErr, Ignore]
+    );
+}
+
+#[test]
+fn ignored_credential_check_is_a_bypass() {
+    let stack = unresolved(
+        "
+        auth required pam_sss.so ignore_unknown_user
+        auth required pam_permit.so
+    ",
+    );
+    let report = bypass::find_bypasses(&Facility::Auth, &stack.rules, &[]);
+    assert!(report.complete);
+    let outcomes: Vec<_> = report
+        .bypasses
+        .iter()
+        .map(|bypass| &bypass.path.outcomes)
+        .collect();
+    assert_eq!(outcomes, vec![&[(0, Ignore), (1, Success)].into()]);
+}
+
+#[test]
+fn required_modules_must_each_succeed() {
+    let report = unresolved(
+        "
+        auth required pam_a.so
+        auth required pam_b.so
+    ",
+    )
+    .criticality();
+    assert!(report.can_succeed);
+    // either failing is enough to fail the stack, even though they could return ignore instead
+    assert_eq!(report.must_succeed, vec![0, 1]);
+    assert_eq!(report.never_matters, Vec::<usize>::new());
+    assert_eq!(report.sufficient, vec![vec![0, 1]]);
+}
+
+#[test]
+fn truth_table_has_ignore_rows() {
+    // only pam_u2f.so can return ignore, for users without a key
+    let table = unresolved(
+        "
+        auth required pam_unix.so
+        auth required pam_u2f.so nouserok
+    ",
+    )
+    .truth_table(paths::MAX_PATHS);
+    assert!(table.complete);
+    let rows: Vec<(Vec<Option<ReturnCode>>, ReturnCode)> = table
+        .rows
+        .into_iter()
+        .map(|row| (row.outcomes, row.result))
+        .collect();
+    assert_eq!(
+        rows,
+        vec![
+            (vec![Some(Success), Some(Success)], Success),
+            (vec![None, Some(AuthErr)], AuthErr),
+            (vec![Some(Success), Some(Ignore)], Success),
+            (vec![Some(AuthErr), Some(Success)], AuthErr),
+            (vec![Some(AuthErr), Some(Ignore)], AuthErr),
+        ]
+    );
+}
+
+#[test]
+fn truth_table_of_a_long_stack_is_quick() {
+    let stack = "auth required pam_unix.so\n".repeat(10) + "auth optional pam_a.so\n";
+    let started = std::time::Instant::now();
+    let table = unresolved(&stack).truth_table(paths::MAX_PATHS);
+    assert!(
+        started.elapsed() < std::time::Duration::from_secs(5),
+        "took {:?}",
+        started.elapsed()
+    );
+    assert!(table.complete);
+    // one row for them all succeeding, and one for each module being the last to fail
+    let rows: Vec<(Vec<Option<ReturnCode>>, ReturnCode)> = table
+        .rows
+        .into_iter()
+        .map(|row| (row.outcomes, row.result))
+        .collect();
+    let row = |failed: Option<usize>| {
+        let outcomes = (0..10)
+            .map(|index| match failed {
+                Some(failed) if index < failed => None,
+                Some(failed) if index == failed => Some(AuthErr),
+                _ => Some(Success),
+            })
+            .chain([None])
+            .collect();
+        (outcomes, failed.map_or(Success, |_| AuthErr))
+    };
+    assert_eq!(
+        rows,
+        [None]
+            .into_iter()
+            .chain((0..10).rev().map(Some))
+            .map(row)
+            .collect::<Vec<_>>()
+    );
+}
+
+#[test]
+fn diagram_branches_on_ignore() {
+    let diagram =
+        unresolved("auth required pam_u2f.so nouserok\nauth required pam_unix.so").diagram();
+    let symbolic::Node::Decision { branches, .. } = &diagram.nodes[diagram.root] else {
+        panic!("the first module decides something");
+    };
+    let codes: Vec<ReturnCode> = branches.iter().map(|(code, _)| *code).collect();
+    assert_eq!(codes, vec![Success, AuthErr, Ignore]);
+    assert_eq!(
+        diagram.domains.get(&1),
+        Some(&vec![Success, AuthErr]),
+        "pam_unix.so can't return ignore"
+    );
+
+    // only differs in what ignore does
+    let difference = symbolic::difference(
+        &unresolved(
+            "auth [success=ok ignore=ignore default=bad] pam_u2f.so nouserok\nauth required pam_unix.so",
+        ),
+        &unresolved("auth [success=ok default=bad] pam_u2f.so nouserok\nauth required pam_unix.so"),
+    )
+    .expect("ignore is handled differently");
+    assert_eq!(
+        difference.outcomes[0].1, Ignore,
+        "{:?}",
+        difference.outcomes
+    );
+}
+
+#[test]
+fn diff_finds_a_change_to_ignore() {
+    let old = unresolved(
+        "auth [success=ok ignore=ignore default=bad] pam_u2f.so nouserok\nauth required pam_unix.so",
+    );
+    let new =
+        unresolved("auth [success=ok default=bad] pam_u2f.so nouserok\nauth required pam_unix.so");
+    let found = diff::diff_stacks(&Facility::Auth, &old.rules, &new.rules);
+    assert!(found.complete);
+    let changes: Vec<(Vec<Option<ReturnCode>>, ReturnCode, ReturnCode)> = found
+        .changes
+        .iter()
+        .map(|change| {
+            (
+                change.outcomes.clone(),
+                change.old.result,
+                change.new.result,
+            )
+        })
+        .collect();
+    assert_eq!(
+        changes,
+        vec![
+            (vec![Some(Ignore), Some(Success)], Success, PermDenied),
+            (vec![Some(Ignore), Some(AuthErr)], AuthErr, PermDenied),
+        ]
+    );
+    // without nouserok it can't return ignore, so the change doesn't do anything
+    let old = unresolved(
+        "auth [success=ok ignore=ignore default=bad] pam_u2f.so\nauth required pam_unix.so",
+    );
+    let new = unresolved("auth [success=ok default=bad] pam_u2f.so\nauth required pam_unix.so");
+    assert_eq!(
+        diff::diff_stacks(&Facility::Auth, &old.rules, &new.rules).changes,
+        vec![]
+    );
+}
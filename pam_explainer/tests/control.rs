@@ -0,0 +1,138 @@
+//! Parsing the bracketed `[value=action ...]` control syntax, as described in pam.conf(5).
+
+use pam_explainer::control::ControlError;
+use pam_explainer::{Action, Control, ControlActions, ReturnCode};
+use std::str::FromStr;
+
+fn parse(value: &str) -> Result<ControlActions, ControlError> {
+    ControlActions::from_str(value)
+}
+
+#[test]
+fn values_and_actions() {
+    let actions = parse("[success=ok new_authtok_reqd=done ignore=ignore default=bad]")
+        .expect("valid control");
+    assert_eq!(actions.action_for(ReturnCode::Success), Action::Ok);
+    assert_eq!(actions.action_for(ReturnCode::NewAuthtokReqd), Action::Done);
+    assert_eq!(actions.action_for(ReturnCode::Ignore), Action::Ignore);
+    assert_eq!(actions.action_for(ReturnCode::AuthErr), Action::Bad);
+    assert_eq!(
+        parse("[user_unknown=die perm_denied=reset default=ignore]")
+            .expect("valid control")
+            .action_for(ReturnCode::PermDenied),
+        Action::Reset
+    );
+}
+
+#[test]
+fn jumps() {
+    let actions = parse("[success=2 auth_err=1 default=ignore]").expect("valid control");
+    assert_eq!(actions.action_for(ReturnCode::Success), Action::Jump(2));
+    assert_eq!(actions.action_for(ReturnCode::AuthErr), Action::Jump(1));
+    // libpam treats a jump of zero as ignore
+    assert_eq!(
+        parse("[success=0]")
+            .expect("valid control")
+            .action_for(ReturnCode::Success),
+        Action::Ignore
+    );
+    assert_eq!(
+        parse("[success=-1]"),
+        Err(ControlError::UnknownAction("-1".to_string()))
+    );
+}
+
+#[test]
+fn default() {
+    let actions = parse("[default=die]").expect("valid control");
+    assert_eq!(actions.values.len(), 0);
+    assert_eq!(actions.default, Some(Action::Die));
+    assert_eq!(actions.action_for(ReturnCode::Success), Action::Die);
+    // without a default, anything not listed is bad
+    let actions = parse("[success=ok]").expect("valid control");
+    assert_eq!(actions.default, None);
+    assert_eq!(actions.action_for(ReturnCode::Ignore), Action::Bad);
+}
+
+#[test]
+fn duplicate_keys_take_the_last_action() {
+    let actions =
+        parse("[success=ok success=done default=bad default=ignore]").expect("valid control");
+    assert_eq!(actions.action_for(ReturnCode::Success), Action::Done);
+    assert_eq!(actions.default, Some(Action::Ignore));
+    assert_eq!(actions.values.len(), 1);
+}
+
+#[test]
+fn whitespace_between_pairs() {
+    assert_eq!(
+        parse("[ success=ok\tdefault=bad  ]"),
+        parse("[success=ok default=bad]")
+    );
+}
+
+#[test]
+fn round_trips_through_display() {
+    for value in [
+        "[success=ok default=bad]",
+        "[success=1 default=ignore]",
+        "[user_unknown=ignore success=done default=die]",
+    ] {
+        let actions = parse(value).expect("valid control");
+        assert_eq!(parse(&actions.to_string()), Ok(actions));
+    }
+}
+
+#[test]
+fn not_bracketed() {
+    assert_eq!(parse("success=ok"), Err(ControlError::NotBracketed));
+    assert_eq!(parse("[success=ok"), Err(ControlError::NotBracketed));
+    assert_eq!(parse("required"), Err(ControlError::NotBracketed));
+}
+
+#[test]
+fn empty() {
+    assert_eq!(parse("[]"), Err(ControlError::Empty));
+    assert_eq!(parse("[   ]"), Err(ControlError::Empty));
+}
+
+#[test]
+fn missing_equals() {
+    assert_eq!(
+        parse("[success=ok bad]"),
+        Err(ControlError::MissingEquals("bad".to_string()))
+    );
+}
+
+#[test]
+fn unknown_value() {
+    assert_eq!(
+        parse("[sucess=ok]"),
+        Err(ControlError::UnknownValue("sucess".to_string()))
+    );
+}
+
+#[test]
+fn unknown_action() {
+    assert_eq!(
+        parse("[success=okay]"),
+        Err(ControlError::UnknownAction("okay".to_string()))
+    );
+    assert_eq!(
+        parse("[success=]"),
+        Err(ControlError::UnknownAction(String::new()))
+    );
+}
+
+#[test]
+fn controls_from_rules() {
+    assert!(matches!(
+        Control::from("[success=ok default=bad]"),
+        Control::Complex(_)
+    ));
+    assert_eq!(Control::from("required"), Control::Required);
+    assert!(matches!(
+        Control::from("[success=oops]"),
+        Control::Invalid(_)
+    ));
+}
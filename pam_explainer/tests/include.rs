@@ -0,0 +1,239 @@
+//! Resolving `include`, `substack` and `@include` against a pam.d directory, checked against how libpam loads a service.
+
+use pam_explainer::include::{load_service, resolve_includes, MAX_INCLUDE_DEPTH};
+use pam_explainer::{LoadError, ParseError, Rule};
+use std::path::{Path, PathBuf};
+
+/// Write the service files out to a fresh directory under the target directory.
+fn pam_d(name: &str, files: &[(&str, &str)]) -> PathBuf {
+    let dir = PathBuf::from(env!("CARGO_TARGET_TMPDIR")).join(name);
+    if dir.exists() {
+        std::fs::remove_dir_all(&dir).expect("removed the old directory");
+    }
+    std::fs::create_dir_all(&dir).expect("created the directory");
+    for (service, contents) in files {
+        std::fs::write(dir.join(service), contents).expect("wrote the service file");
+    }
+    dir
+}
+
+/// Each rule, indented by its substack level.
+fn lines(rules: &[Rule]) -> Vec<String> {
+    rules
+        .iter()
+        .map(|rule| format!("{}{}", ">".repeat(rule.stack_level), rule.to_config_line()))
+        .collect()
+}
+
+fn load(dir: &Path, service: &str) -> Result<Vec<String>, LoadError> {
+    load_service(dir, service, &[]).map(|(rules, _)| lines(&rules))
+}
+
+#[test]
+fn include_only_pulls_in_its_facility() {
+    let dir = pam_d(
+        "include-facility",
+        &[
+            (
+                "login",
+                "auth include common\naccount required pam_unix.so\n",
+            ),
+            (
+                "common",
+                "auth required pam_env.so\naccount required pam_deny.so\nauth required pam_unix.so\n",
+            ),
+        ],
+    );
+    assert_eq!(
+        load(&dir, "login").expect("loads"),
+        [
+            "auth required pam_env.so",
+            "auth required pam_unix.so",
+            "account required pam_unix.so",
+        ]
+    );
+}
+
+#[test]
+fn at_include_pulls_in_every_facility() {
+    let dir = pam_d(
+        "at-include",
+        &[
+            ("login", "@include common\nsession required pam_limits.so\n"),
+            (
+                "common",
+                "auth required pam_unix.so\naccount required pam_unix.so\n",
+            ),
+        ],
+    );
+    assert_eq!(
+        load(&dir, "login").expect("loads"),
+        [
+            "auth required pam_unix.so",
+            "account required pam_unix.so",
+            "session required pam_limits.so",
+        ]
+    );
+}
+
+#[test]
+fn nested_includes_keep_their_facility_filter() {
+    let dir = pam_d(
+        "include-nested",
+        &[
+            ("login", "account include outer\n"),
+            ("outer", "@include inner\nauth required pam_deny.so\n"),
+            (
+                "inner",
+                "auth required pam_unix.so\naccount required pam_unix.so\n",
+            ),
+        ],
+    );
+    assert_eq!(
+        load(&dir, "login").expect("loads"),
+        ["account required pam_unix.so"]
+    );
+}
+
+#[test]
+fn substack_rules_are_a_level_deeper() {
+    let dir = pam_d(
+        "substack",
+        &[
+            (
+                "sshd",
+                "auth substack password-auth\nauth required pam_env.so\n",
+            ),
+            (
+                "password-auth",
+                "auth sufficient pam_unix.so\nauth include common\naccount required pam_deny.so\n",
+            ),
+            ("common", "auth required pam_deny.so\n"),
+        ],
+    );
+    let (rules, _) = load_service(&dir, "sshd", &[]).expect("loads");
+    assert_eq!(
+        lines(&rules),
+        [
+            "auth substack password-auth",
+            ">auth sufficient pam_unix.so",
+            ">auth required pam_deny.so",
+            "auth required pam_env.so",
+        ]
+    );
+    // numbered in the order they run, includes and all
+    assert_eq!(
+        rules.iter().map(|rule| rule.rule_order).collect::<Vec<_>>(),
+        [Some(0), Some(1), Some(2), Some(3)]
+    );
+}
+
+#[test]
+fn resolving_text_against_a_directory() {
+    let dir = pam_d(
+        "resolve-text",
+        &[("common-auth", "auth required pam_unix.so\n")],
+    );
+    let (rules, diagnostics) = resolve_includes(
+        "@include common-auth\nauth required pam_deny.so\n",
+        Some("login"),
+        &dir,
+        &[],
+    )
+    .expect("resolves");
+    assert!(diagnostics.is_empty(), "{:?}", diagnostics);
+    assert_eq!(
+        lines(&rules),
+        ["auth required pam_unix.so", "auth required pam_deny.so"]
+    );
+}
+
+#[test]
+fn include_loop() {
+    let dir = pam_d(
+        "include-loop",
+        &[
+            ("login", "auth include a\n"),
+            ("a", "@include b\n"),
+            ("b", "auth substack a\n"),
+        ],
+    );
+    match load(&dir, "login") {
+        Err(LoadError::IncludeLoop { chain }) => assert_eq!(chain, ["login", "a", "b", "a"]),
+        other => panic!("expected an include loop, got {:?}", other),
+    }
+
+    let dir = pam_d("include-self", &[("login", "@include login\n")]);
+    assert!(matches!(
+        load(&dir, "login"),
+        Err(LoadError::IncludeLoop { chain }) if chain == ["login", "login"]
+    ));
+}
+
+/// A service that includes `depth` files, each including the next.
+fn nested(name: &str, depth: usize) -> PathBuf {
+    let mut files: Vec<(String, String)> = (0..depth)
+        .map(|level| {
+            let from = match level {
+                0 => "login".to_string(),
+                _ => format!("level{}", level),
+            };
+            (from, format!("auth include level{}\n", level + 1))
+        })
+        .collect();
+    files.push((
+        format!("level{}", depth),
+        "auth required pam_unix.so\n".to_string(),
+    ));
+    let files: Vec<(&str, &str)> = files
+        .iter()
+        .map(|(service, contents)| (service.as_str(), contents.as_str()))
+        .collect();
+    pam_d(name, &files)
+}
+
+#[test]
+fn include_depth_limit() {
+    let dir = nested("include-deepest", MAX_INCLUDE_DEPTH);
+    assert_eq!(
+        load(&dir, "login").expect("as deep as libpam allows"),
+        ["auth required pam_unix.so"]
+    );
+
+    let dir = nested("include-too-deep", MAX_INCLUDE_DEPTH + 1);
+    match load(&dir, "login") {
+        Err(LoadError::IncludeTooDeep { chain }) => {
+            assert_eq!(chain.len(), MAX_INCLUDE_DEPTH + 2);
+            assert_eq!(chain.first().map(String::as_str), Some("login"));
+        }
+        other => panic!("expected the includes to be too deep, got {:?}", other),
+    }
+}
+
+#[test]
+fn missing_include() {
+    let dir = pam_d("include-missing", &[("login", "auth include nowhere\n")]);
+    assert!(matches!(
+        load(&dir, "login"),
+        Err(LoadError::IncludeNotFound { target }) if target == "nowhere"
+    ));
+    // a missing service is reported the same way
+    assert!(matches!(
+        load(&dir, "sshd"),
+        Err(LoadError::IncludeNotFound { target }) if target == "sshd"
+    ));
+}
+
+#[test]
+fn at_include_without_a_target() {
+    let dir = pam_d(
+        "include-no-target",
+        &[("login", "@include\nauth required pam_unix.so\n")],
+    );
+    let (rules, diagnostics) = load_service(&dir, "login", &[]).expect("loads");
+    assert_eq!(lines(&rules), ["auth required pam_unix.so"]);
+    assert!(matches!(
+        diagnostics.as_slice(),
+        [ParseError::MissingIncludeTarget { .. }]
+    ));
+}
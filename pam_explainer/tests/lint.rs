@@ -0,0 +1,268 @@
+//! Each lint firing on a config that has the problem, and `# pam-explainer: allow(ID)` comments turning them off.
+
+use pam_explainer::lint::{self, lint_diagnostics, lint_service, Severity, CHECKS};
+use pam_explainer::parse_config;
+
+/// Every lint for the config, by ID, severity and the line it's on.
+fn lints(config: &str) -> Vec<(&str, Severity, usize)> {
+    let (rules, diagnostics) = parse_config(config, None, &[]);
+    lint_diagnostics(&diagnostics)
+        .into_iter()
+        .chain(lint_service("test", &rules))
+        .map(|lint| (lint.id, lint.severity, lint.span.line))
+        .collect()
+}
+
+/// Checks `config` gets the lint on `line`, with the check's severity.
+fn assert_fires(config: &str, id: &str, severity: Severity, line: usize) {
+    let found = lints(config);
+    assert!(
+        found.contains(&(id, severity, line)),
+        "expected {} on line {}, got {:?}",
+        id,
+        line,
+        found
+    );
+}
+
+#[test]
+fn invalid_facility() {
+    assert_fires(
+        "auht required pam_unix.so\n",
+        "invalid-facility",
+        Severity::Error,
+        1,
+    );
+}
+
+#[test]
+fn invalid_control() {
+    assert_fires(
+        "auth requird pam_unix.so\n",
+        "invalid-control",
+        Severity::Error,
+        1,
+    );
+    assert_fires(
+        "auth [success=ok default=nope] pam_unix.so\n",
+        "invalid-control",
+        Severity::Error,
+        1,
+    );
+}
+
+#[test]
+fn unsupported_facility() {
+    assert_fires(
+        "account required pam_env.so\n",
+        "unsupported-facility",
+        Severity::Warning,
+        1,
+    );
+}
+
+#[test]
+fn jump_past_end() {
+    assert_fires(
+        "auth [success=2 default=ignore] pam_unix.so\nauth required pam_deny.so\n",
+        "jump-past-end",
+        Severity::Error,
+        1,
+    );
+    assert!(lints("auth [success=1 default=ignore] pam_unix.so\nauth requisite pam_deny.so\nauth required pam_permit.so\n")
+        .iter()
+        .all(|(id, _, _)| *id != "jump-past-end"));
+}
+
+#[test]
+fn unreachable_rule() {
+    assert_fires(
+        "auth requisite pam_deny.so\nauth required pam_unix.so\n",
+        "unreachable-rule",
+        Severity::Warning,
+        2,
+    );
+}
+
+#[test]
+fn jump_over_a_placeholder_isnt_unreachable() {
+    // Debian's common-session, pam_permit.so always jumps over pam_deny.so
+    let config = "\
+session [default=1]   pam_permit.so
+session requisite     pam_deny.so
+session required      pam_permit.so
+session optional      pam_umask.so
+session required      pam_unix.so
+session optional      pam_systemd.so
+";
+    assert_eq!(lints(config), []);
+}
+
+#[test]
+fn optional_only() {
+    assert_fires(
+        "session optional pam_systemd.so\nsession optional pam_umask.so\n",
+        "optional-only",
+        Severity::Warning,
+        1,
+    );
+}
+
+#[test]
+fn no_success_needed() {
+    assert_fires(
+        "auth sufficient pam_permit.so\nauth required pam_unix.so\n",
+        "no-success-needed",
+        Severity::Warning,
+        1,
+    );
+}
+
+#[test]
+fn requisite_after_required() {
+    assert_fires(
+        "auth required pam_unix.so\nauth requisite pam_faillock.so\n",
+        "requisite-after-required",
+        Severity::Info,
+        2,
+    );
+}
+
+#[test]
+fn invalid_argument() {
+    assert_fires(
+        "auth required pam_unix.so nosuchthing\n",
+        lint::INVALID_ARGUMENT,
+        Severity::Warning,
+        1,
+    );
+    assert_fires(
+        "auth required pam_unix.so likeauth\n",
+        lint::INVALID_ARGUMENT,
+        Severity::Info,
+        1,
+    );
+}
+
+#[test]
+fn parse_error() {
+    assert_fires("auth required\n", lint::PARSE_ERROR, Severity::Error, 1);
+}
+
+#[test]
+fn a_clean_stack_has_no_lints() {
+    let config = "\
+auth     required   pam_env.so
+auth     required   pam_unix.so
+account  required   pam_unix.so
+password required   pam_unix.so sha512
+session  required   pam_unix.so
+";
+    assert_eq!(lints(config), []);
+}
+
+#[test]
+fn rhel_system_auth_account_needs_pam_unix() {
+    let config = "\
+account     required      pam_unix.so
+account     sufficient    pam_localuser.so
+account     sufficient    pam_usertype.so issystem
+account     [default=bad success=ok user_unknown=ignore] pam_sss.so
+account     required      pam_permit.so
+";
+    assert_eq!(lints(config), []);
+}
+
+#[test]
+fn check_ids_are_unique() {
+    for (index, check) in CHECKS.iter().enumerate() {
+        assert!(
+            CHECKS[..index].iter().all(|other| other.id != check.id),
+            "{} is used twice",
+            check.id
+        );
+        assert!(![lint::PARSE_ERROR, lint::INVALID_ARGUMENT].contains(&check.id));
+    }
+}
+
+const UNREACHABLE: &str = "auth requisite pam_deny.so\n";
+
+/// The IDs of the lints in `config`.
+fn ids(config: &str) -> Vec<&str> {
+    lints(config).into_iter().map(|(id, _, _)| id).collect()
+}
+
+#[test]
+fn allow_on_the_line_before() {
+    let config = format!(
+        "{}# pam-explainer: allow(unreachable-rule)\nauth required pam_unix.so\n",
+        UNREACHABLE
+    );
+    assert_eq!(ids(&config), Vec::<&str>::new());
+    let (rules, _) = parse_config(&config, None, &[]);
+    assert_eq!(rules[1].allow, ["unreachable-rule"]);
+}
+
+#[test]
+fn allow_in_a_run_of_comments() {
+    let config = format!(
+        "{}# pam-explainer: allow(unreachable-rule)\n# the rule below is kept for reference\nauth required pam_unix.so\n",
+        UNREACHABLE
+    );
+    assert_eq!(ids(&config), Vec::<&str>::new());
+}
+
+#[test]
+fn blank_line_ends_the_run_of_comments() {
+    let config = format!(
+        "{}# pam-explainer: allow(unreachable-rule)\n\nauth required pam_unix.so\n",
+        UNREACHABLE
+    );
+    assert_eq!(ids(&config), ["unreachable-rule"]);
+    let (rules, _) = parse_config(&config, None, &[]);
+    assert!(rules[1].allow.is_empty());
+}
+
+#[test]
+fn allow_only_applies_to_the_next_rule() {
+    let config = format!(
+        "{}# pam-explainer: allow(unreachable-rule)\nauth required pam_unix.so\nauth required pam_env.so\n",
+        UNREACHABLE
+    );
+    assert_eq!(lints(&config), [("unreachable-rule", Severity::Warning, 4)]);
+}
+
+#[test]
+fn allow_on_the_rule_itself() {
+    let config = format!(
+        "{}auth required pam_unix.so # pam-explainer: allow(unreachable-rule)\n",
+        UNREACHABLE
+    );
+    assert_eq!(ids(&config), Vec::<&str>::new());
+}
+
+#[test]
+fn allow_several_ids() {
+    let config =
+        "auth sufficient pam_permit.so # pam-explainer: allow(no-success-needed, optional-only)\nauth required pam_unix.so # pam-explainer: allow(unreachable-rule)\n";
+    assert_eq!(ids(config), Vec::<&str>::new());
+}
+
+#[test]
+fn allow_only_the_named_id() {
+    let config = format!(
+        "{}auth required pam_unix.so # pam-explainer: allow(optional-only)\n",
+        UNREACHABLE
+    );
+    assert_eq!(ids(&config), ["unreachable-rule"]);
+}
+
+#[test]
+fn allow_invalid_argument() {
+    let config =
+        "# pam-explainer: allow(invalid-argument)\nauth required pam_unix.so nosuchthing\n";
+    assert_eq!(ids(config), Vec::<&str>::new());
+    // parse errors can't be allowed
+    let config = "# pam-explainer: allow(parse-error)\nauth required\n";
+    assert_eq!(ids(config), [lint::PARSE_ERROR]);
+}
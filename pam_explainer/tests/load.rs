@@ -0,0 +1,134 @@
+//! Loading configs and results from paths, strings and readers, and the errors when that fails.
+
+use pam_explainer::{
+    load_config, parse_config, read_config, LoadError, ReturnCode, Rule, Scenario,
+};
+use std::path::PathBuf;
+
+const CONFIG: &str = "auth required pam_env.so\nauth sufficient pam_unix.so nullok\n";
+
+const RESULTS: &str = r#"[
+    { "facility": "auth", "control": "sufficient", "module": "pam_unix.so", "arguments": ["nullok"], "final_result": "auth_err", "rule_order": 1 }
+]"#;
+
+/// A fresh directory under the target directory, with the files written into it.
+fn dir(name: &str, files: &[(&str, &str)]) -> PathBuf {
+    let dir = PathBuf::from(env!("CARGO_TARGET_TMPDIR")).join(name);
+    if dir.exists() {
+        std::fs::remove_dir_all(&dir).expect("removed the old directory");
+    }
+    std::fs::create_dir_all(&dir).expect("created the directory");
+    for (name, contents) in files {
+        std::fs::write(dir.join(name), contents).expect("wrote the file");
+    }
+    dir
+}
+
+fn lines(rules: &[Rule]) -> Vec<String> {
+    rules.iter().map(Rule::to_config_line).collect()
+}
+
+fn results(rules: &[Rule]) -> Vec<Option<ReturnCode>> {
+    rules.iter().map(|rule| rule.final_result).collect()
+}
+
+#[test]
+fn config_from_a_str_a_reader_and_a_path() {
+    let scenario = Scenario::from_json(RESULTS).expect("valid results");
+    let expected = [
+        "auth required pam_env.so",
+        "auth sufficient pam_unix.so nullok",
+    ];
+
+    let (rules, diagnostics) = parse_config(CONFIG, Some("login"), &scenario.rules);
+    assert!(diagnostics.is_empty(), "{:?}", diagnostics);
+    assert_eq!(lines(&rules), expected);
+    assert_eq!(results(&rules), [None, Some(ReturnCode::AuthErr)]);
+    assert_eq!(rules[1].source.file.as_deref(), Some("login"));
+
+    let (rules, _) = read_config(CONFIG.as_bytes(), None, &scenario.rules).expect("reads");
+    assert_eq!(lines(&rules), expected);
+    assert_eq!(results(&rules), [None, Some(ReturnCode::AuthErr)]);
+
+    // includes are followed relative to the file
+    let dir = dir(
+        "load-config",
+        &[("login", "@include common-auth\n"), ("common-auth", CONFIG)],
+    );
+    let (rules, _) = load_config(&dir.join("login"), &scenario.rules).expect("loads");
+    assert_eq!(lines(&rules), expected);
+    assert_eq!(results(&rules), [None, Some(ReturnCode::AuthErr)]);
+}
+
+#[test]
+fn missing_config() {
+    let path = dir("load-missing", &[]).join("login");
+    match load_config(&path, &[]) {
+        Err(LoadError::Io {
+            path: Some(failed),
+            source,
+        }) => {
+            assert_eq!(failed, path);
+            assert_eq!(source.kind(), std::io::ErrorKind::NotFound);
+        }
+        other => panic!("expected an IO error, got {:?}", other),
+    }
+}
+
+#[test]
+fn unreadable_config() {
+    // not UTF-8
+    let bytes: &[u8] = &[0x61, 0x75, 0xff, 0xfe];
+    assert!(matches!(
+        read_config(bytes, None, &[]),
+        Err(LoadError::Io { path: None, .. })
+    ));
+}
+
+#[test]
+fn results_from_a_str_a_reader_and_a_path() {
+    let from_str = Scenario::from_json(RESULTS).expect("valid results");
+    assert_eq!(from_str.rules.len(), 1);
+    assert_eq!(Scenario::read(RESULTS.as_bytes()).expect("reads"), from_str);
+    let dir = dir("load-results", &[("results.json", RESULTS)]);
+    assert_eq!(
+        Scenario::load(&dir.join("results.json")).expect("loads"),
+        from_str
+    );
+}
+
+#[test]
+fn missing_results() {
+    let path = dir("load-results-missing", &[]).join("results.json");
+    assert!(matches!(
+        Scenario::load(&path),
+        Err(LoadError::Io { path: Some(failed), .. }) if failed == path
+    ));
+}
+
+#[test]
+fn malformed_results() {
+    let malformed = r#"[{ "facility": "auth", "module": }]"#;
+    assert!(matches!(
+        Scenario::from_json(malformed),
+        Err(LoadError::Results { path: None, .. })
+    ));
+    assert!(matches!(
+        Scenario::read(malformed.as_bytes()),
+        Err(LoadError::Results { path: None, .. })
+    ));
+    // loading a file says which one it was
+    let dir = dir("load-results-malformed", &[("results.json", malformed)]);
+    let path = dir.join("results.json");
+    let err = Scenario::load(&path).expect_err("isn't valid");
+    assert!(matches!(
+        &err,
+        LoadError::Results { path: Some(failed), .. } if *failed == path
+    ));
+    assert!(
+        err.to_string()
+            .starts_with(&format!("Failed to parse results {}", path.display())),
+        "{}",
+        err
+    );
+}
@@ -0,0 +1,74 @@
+//! Checking every way through a stack needs enough kinds of factor, see [mfa::verify].
+
+use pam_explainer::mfa::{self, Factor};
+use pam_explainer::{parse_config, Facility, ReturnCode, ReturnCode::*};
+
+/// The violations of a two factor requirement, as what each module returned on the way.
+fn violations(stack: &str, extra: &[(String, Factor)]) -> Vec<Vec<(usize, ReturnCode)>> {
+    let (rules, diagnostics) = parse_config(stack, None, &[]);
+    assert!(diagnostics.is_empty(), "{:?}", diagnostics);
+    let report = mfa::verify(&Facility::Auth, &rules, extra, 2);
+    assert!(report.complete);
+    report
+        .violations
+        .into_iter()
+        .map(|violation| violation.path.outcomes.into_iter().collect())
+        .collect()
+}
+
+#[test]
+fn password_and_security_key_both_required() {
+    let stack = "
+        auth [success=ok default=die] pam_unix.so
+        auth [success=ok default=die] pam_u2f.so
+    ";
+    assert_eq!(violations(stack, &[]), Vec::<Vec<_>>::new());
+}
+
+#[test]
+fn sufficient_password_skips_the_second_factor() {
+    let stack = "
+        auth sufficient pam_unix.so
+        auth required pam_u2f.so
+    ";
+    // and if the password's wrong, the security key alone is enough
+    assert_eq!(
+        violations(stack, &[]),
+        vec![vec![(0, Success)], vec![(0, AuthErr), (1, Success)]]
+    );
+}
+
+#[test]
+fn required_password_and_second_factor_verify_clean() {
+    // neither returns ignore without nouserok or nullok
+    for stack in [
+        "auth required pam_unix.so\nauth required pam_u2f.so",
+        "auth required pam_unix.so\nauth required pam_google_authenticator.so",
+    ] {
+        assert_eq!(violations(stack, &[]), Vec::<Vec<_>>::new(), "{}", stack);
+    }
+}
+
+#[test]
+fn second_factor_returning_ignore_is_a_violation() {
+    // pam_u2f.so with nouserok returns ignore for users without a key
+    let stack = "
+        auth required pam_unix.so
+        auth required pam_u2f.so nouserok
+    ";
+    assert_eq!(
+        violations(stack, &[]),
+        vec![vec![(0, Success), (1, Ignore)]]
+    );
+}
+
+#[test]
+fn extra_factors_count() {
+    let stack = "
+        auth [success=ok default=die] pam_unix.so
+        auth [success=ok default=die] pam_mycompany.so
+    ";
+    assert_eq!(violations(stack, &[]).len(), 1);
+    let extra = [("pam_mycompany.so".to_string(), Factor::Possession)];
+    assert_eq!(violations(stack, &extra), Vec::<Vec<_>>::new());
+}
@@ -0,0 +1,102 @@
+//! Checking the arguments rules give well-known modules against what's known about them.
+
+use pam_explainer::modules::{module_info, ArgumentError, ArgumentKind};
+
+/// The problems with the arguments, split on whitespace, that `module` is given.
+fn check(module: &str, arguments: &str) -> Vec<(usize, ArgumentError)> {
+    let arguments: Vec<String> = arguments.split_whitespace().map(String::from).collect();
+    module_info(module)
+        .expect("a known module")
+        .check_arguments(&arguments)
+}
+
+#[test]
+fn stock_pwquality_line_is_fine() {
+    // what RHEL and Fedora ship in system-auth
+    assert_eq!(
+        check(
+            "pam_pwquality.so",
+            "try_first_pass local_users_only retry=3 authtok_type="
+        ),
+        []
+    );
+    assert_eq!(check("pam_pwquality.so", "debug use_authtok"), []);
+    assert_eq!(check("pam_pwquality.so", "use_first_pass"), []);
+}
+
+#[test]
+fn unknown_argument() {
+    assert_eq!(
+        check("pam_unix.so", "nullok nosuchthing"),
+        [(1, ArgumentError::Unknown)]
+    );
+    // modules taking positional arguments can be given anything
+    assert_eq!(check("pam_exec.so", "/usr/local/bin/hook --flag"), []);
+}
+
+#[test]
+fn missing_value() {
+    assert_eq!(
+        check("pam_unix.so", "remember"),
+        [(0, ArgumentError::MissingValue(ArgumentKind::Integer))]
+    );
+    assert_eq!(
+        check("pam_unix.so", "remember="),
+        [(0, ArgumentError::MissingValue(ArgumentKind::Integer))]
+    );
+    // only some arguments can be empty
+    assert_eq!(
+        check("pam_pwquality.so", "badwords="),
+        [(0, ArgumentError::MissingValue(ArgumentKind::String))]
+    );
+}
+
+#[test]
+fn not_an_integer() {
+    assert_eq!(
+        check("pam_faillock.so", "deny=three"),
+        [(0, ArgumentError::NotAnInteger("three".to_string()))]
+    );
+    // the words an integer argument takes are fine
+    assert_eq!(check("pam_faillock.so", "unlock_time=never"), []);
+    assert_eq!(check("pam_pwquality.so", "dcredit=-1"), []);
+}
+
+#[test]
+fn flag_with_a_value() {
+    assert_eq!(
+        check("pam_unix.so", "nullok=1"),
+        [(0, ArgumentError::UnexpectedValue)]
+    );
+}
+
+#[test]
+fn conflicts_are_reported_on_the_later_argument() {
+    assert_eq!(
+        check("pam_unix.so", "try_first_pass nullok use_first_pass"),
+        [(2, ArgumentError::Conflicts("try_first_pass".to_string()))]
+    );
+    assert_eq!(
+        check("pam_unix.so", "sha512 yescrypt"),
+        [(1, ArgumentError::Conflicts("sha512".to_string()))]
+    );
+    // using the same one twice isn't a conflict
+    assert_eq!(check("pam_unix.so", "use_first_pass use_first_pass"), []);
+}
+
+#[test]
+fn deprecated() {
+    assert_eq!(
+        check("pam_unix.so", "likeauth"),
+        [(
+            0,
+            ArgumentError::Deprecated("it's ignored, pam_unix always does".to_string())
+        )]
+    );
+    // a deprecated argument with a bad value is reported for both
+    assert_eq!(
+        check("pam_pwquality.so", "difignore=x").len(),
+        2,
+        "not an integer and deprecated"
+    );
+}
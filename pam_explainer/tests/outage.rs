@@ -0,0 +1,163 @@
+//! Who can still log in while a directory service is down, for each outage profile.
+
+use pam_explainer::outage::{impact, outage, Account, AccountOutcome, OUTAGES};
+use pam_explainer::{parse_config, Facility, OutcomeProvider, ReturnCode, Rule};
+
+fn rules(config: &str) -> Vec<Rule> {
+    let (rules, diagnostics) = parse_config(config, None, &[]);
+    assert!(diagnostics.is_empty(), "{:?}", diagnostics);
+    rules
+}
+
+/// For each kind of account, if it could log in before the outage and if it can during it.
+fn logins(config: &str, id: &str) -> Vec<(Account, bool, bool)> {
+    impact(&rules(config), outage(id).expect("a known outage"))
+        .into_iter()
+        .map(|impact| (impact.account, impact.before.allowed, impact.during.allowed))
+        .collect()
+}
+
+#[test]
+fn ldap_outage_locks_out_directory_users() {
+    let config = "\
+auth     sufficient  pam_unix.so
+auth     required    pam_ldap.so use_first_pass
+account  sufficient  pam_unix.so
+account  required    pam_ldap.so
+session  required    pam_unix.so
+";
+    assert_eq!(
+        logins(config, "ldap"),
+        [
+            (Account::Local, true, true),
+            (Account::Root, true, true),
+            (Account::Directory, true, false),
+        ]
+    );
+}
+
+#[test]
+fn lookups_outage_turns_pam_unix_into_user_unknown() {
+    let rule = &rules("auth required pam_unix.so\n")[0];
+    let outcome = |id: Option<&str>| {
+        AccountOutcome {
+            account: Account::Directory,
+            outage: id.map(|id| outage(id).expect("a known outage")),
+        }
+        .outcome(rule)
+    };
+    // normally the user's found, but there's no local password for them
+    assert_eq!(outcome(None), Some(ReturnCode::AuthErr));
+    // NSS can't find them either while the directory's down
+    assert_eq!(outcome(Some("ldap")), Some(ReturnCode::UserUnknown));
+    assert_eq!(outcome(Some("sssd")), Some(ReturnCode::UserUnknown));
+    // Kerberos isn't behind NSS, so lookups still work
+    assert_eq!(outcome(Some("kerberos")), Some(ReturnCode::AuthErr));
+}
+
+#[test]
+fn sssd_outage_with_a_strict_account_rule_locks_out_everyone() {
+    // pam_sss returns authinfo_unavail for local users too while sssd is down, which this account rule treats as bad
+    let config = "\
+auth     sufficient  pam_unix.so
+auth     sufficient  pam_sss.so use_first_pass
+auth     required    pam_deny.so
+account  required    pam_unix.so
+account  [default=bad success=ok user_unknown=ignore] pam_sss.so
+session  required    pam_unix.so
+";
+    assert_eq!(
+        logins(config, "sssd"),
+        [
+            (Account::Local, true, false),
+            (Account::Root, true, false),
+            (Account::Directory, true, false),
+        ]
+    );
+    let locked_out: Vec<Account> = impact(&rules(config), outage("sssd").expect("known"))
+        .into_iter()
+        .filter(|impact| impact.locked_out())
+        .map(|impact| impact.account)
+        .collect();
+    assert_eq!(
+        locked_out,
+        [Account::Local, Account::Root, Account::Directory]
+    );
+}
+
+#[test]
+fn kerberos_outage_falls_back_to_local_passwords() {
+    let config = "\
+auth     sufficient  pam_krb5.so
+auth     required    pam_unix.so use_first_pass
+account  required    pam_unix.so
+session  required    pam_unix.so
+";
+    assert_eq!(
+        logins(config, "kerberos"),
+        [
+            (Account::Local, true, true),
+            (Account::Root, true, true),
+            (Account::Directory, true, false),
+        ]
+    );
+}
+
+#[test]
+fn winbind_outage_with_unavailable_ignored_in_account() {
+    // without authinfo_unavail=ignore, winbind being down would lock out local users too, like sssd
+    let config = "\
+auth     sufficient  pam_winbind.so
+auth     required    pam_unix.so try_first_pass
+account  required    pam_unix.so
+account  [default=bad success=ok user_unknown=ignore authinfo_unavail=ignore] pam_winbind.so
+session  required    pam_unix.so
+";
+    assert_eq!(
+        logins(config, "winbind"),
+        [
+            (Account::Local, true, true),
+            (Account::Root, true, true),
+            (Account::Directory, true, false),
+        ]
+    );
+}
+
+#[test]
+fn directory_outage_still_lets_local_users_in() {
+    let config = "\
+auth     sufficient  pam_rootok.so
+auth     [success=done default=ignore] pam_unix.so
+auth     sufficient  pam_sss.so use_first_pass
+auth     sufficient  pam_ldap.so use_first_pass
+auth     required    pam_deny.so
+account  required    pam_unix.so
+session  required    pam_unix.so
+";
+    assert_eq!(
+        logins(config, "directory"),
+        [
+            (Account::Local, true, true),
+            (Account::Root, true, true),
+            (Account::Directory, true, false),
+        ]
+    );
+    let during = impact(&rules(config), outage("directory").expect("known"));
+    let directory = &during[2].during;
+    assert_eq!(directory.results[0], (Facility::Auth, ReturnCode::AuthErr));
+}
+
+#[test]
+fn outage_profiles() {
+    let ids: Vec<&str> = OUTAGES.iter().map(|outage| outage.id).collect();
+    assert_eq!(ids, ["ldap", "sssd", "kerberos", "winbind", "directory"]);
+    assert!(outage("nfs").is_none());
+    // every directory module is down in the directory outage
+    let directory = outage("directory").expect("known");
+    for outage in OUTAGES.iter() {
+        assert!(outage
+            .modules
+            .iter()
+            .all(|module| directory.modules.contains(module)));
+    }
+}
@@ -0,0 +1,135 @@
+//! Where the engine gets module results from when the rules don't have them, run through a stack and checked in the trace.
+
+use pam_explainer::outcome::{CallbackOutcome, FixedOutcome};
+use pam_explainer::{
+    Facility, NoOutcome, Outcome, OutcomeProvider, ReturnCode, Rule, RuleSet, Scenario,
+};
+use ReturnCode::*;
+
+const STACK: &str = "
+    auth required pam_env.so
+    auth sufficient pam_unix.so
+    auth required pam_ldap.so
+";
+
+fn ruleset(facility: &Facility, stack: &str) -> RuleSet {
+    let rules = stack
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty())
+        .enumerate()
+        .map(|(order, line)| Rule::new(line, &(order as u32), &[]).expect("valid rule"))
+        .collect();
+    RuleSet::new(facility, rules)
+}
+
+/// The stack's result, and what each module that ran returned.
+fn run(stack: &str, provider: &mut dyn OutcomeProvider) -> (ReturnCode, Vec<ReturnCode>) {
+    let trace = ruleset(&Facility::Auth, stack).run_rules_with(provider);
+    let codes = trace
+        .steps
+        .iter()
+        .filter_map(|step| match step.outcome {
+            Outcome::Invoked { code, .. } => Some(code),
+            _ => None,
+        })
+        .collect();
+    (trace.result, codes)
+}
+
+#[test]
+fn fixed_outcome() {
+    assert_eq!(
+        run(STACK, &mut FixedOutcome(Success)),
+        (Success, vec![Success, Success])
+    );
+    assert_eq!(
+        run(STACK, &mut FixedOutcome(AuthinfoUnavail)),
+        (AuthinfoUnavail, vec![AuthinfoUnavail; 3])
+    );
+}
+
+#[test]
+fn no_outcome_fails_with_the_facility_failure_code() {
+    assert_eq!(run(STACK, &mut NoOutcome), (AuthErr, vec![AuthErr; 3]));
+    let trace =
+        ruleset(&Facility::Account, "account required pam_unix.so").run_rules_with(&mut NoOutcome);
+    assert_eq!(trace.result, PermDenied);
+}
+
+#[test]
+fn callback_outcome() {
+    let mut asked = Vec::new();
+    let mut provider = CallbackOutcome(|rule: &Rule| {
+        asked.push(rule.module.clone());
+        match rule.module.as_str() {
+            "pam_unix.so" => Some(AuthErr),
+            "pam_ldap.so" => Some(Success),
+            _ => None,
+        }
+    });
+    // pam_env.so isn't known, so it fails
+    assert_eq!(
+        run(STACK, &mut provider),
+        (AuthErr, vec![AuthErr, AuthErr, Success])
+    );
+    assert_eq!(asked, ["pam_env.so", "pam_unix.so", "pam_ldap.so"]);
+}
+
+#[test]
+fn results_on_the_rules_come_first() {
+    let mut rules = ruleset(&Facility::Auth, STACK);
+    rules.rules[0].final_result = Some(Success);
+    rules.rules[1].final_result = Some(Success);
+    let trace = rules.run_rules_with(&mut FixedOutcome(AuthErr));
+    assert_eq!(trace.result, Success);
+    assert_eq!(trace.rules_run(), 2);
+}
+
+#[test]
+fn scenario_precedence() {
+    let mut scenario = Scenario::from_json(
+        r#"{
+            "modules": { "pam_unix.so": "auth_err", "pam_ldap.so": "authinfo_unavail" },
+            "default": "success"
+        }"#,
+    )
+    .expect("valid scenario");
+    // a rule in the scenario beats its module
+    let mut ldap = Rule::new("auth required pam_ldap.so", &2, &[]).expect("valid rule");
+    ldap.final_result = Some(Success);
+    scenario.rules.push(ldap);
+    assert_eq!(
+        run(STACK, &mut scenario),
+        (Success, vec![Success, AuthErr, Success])
+    );
+    // which only matches the same rule, not the module anywhere
+    assert_eq!(
+        run("auth required pam_ldap.so use_first_pass", &mut scenario),
+        (AuthinfoUnavail, vec![AuthinfoUnavail])
+    );
+
+    // without a default anything not mentioned fails
+    let mut scenario =
+        Scenario::from_json(r#"{ "modules": { "pam_unix.so": "success" } }"#).expect("valid");
+    assert_eq!(scenario.default, None);
+    assert_eq!(
+        run(STACK, &mut scenario),
+        (AuthErr, vec![AuthErr, Success, AuthErr])
+    );
+}
+
+#[test]
+fn scenario_from_a_list_of_rules() {
+    // the results file the CLI has always taken
+    let json = r#"[
+        { "facility": "auth", "control": "sufficient", "module": "pam_unix.so", "final_result": "success", "rule_order": 1 }
+    ]"#;
+    let mut scenario = Scenario::from_json(json).expect("valid results");
+    assert_eq!(scenario.rules.len(), 1);
+    assert!(scenario.modules.is_empty());
+    assert_eq!(
+        run(STACK, &mut scenario),
+        (AuthErr, vec![AuthErr, Success, AuthErr])
+    );
+}
@@ -0,0 +1,141 @@
+//! Converting between pam.conf and a pam.d directory, checked by loading both sides and comparing the rules libpam would run.
+
+use pam_explainer::include::resolve_includes;
+use pam_explainer::pamconf::{from_pam_d, parse_pam_conf, to_pam_d};
+use pam_explainer::{LoadError, Rule};
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+
+fn lines(rules: &[Rule]) -> Vec<String> {
+    rules.iter().map(Rule::to_config_line).collect()
+}
+
+fn pam_d(files: &[(&str, &str)]) -> BTreeMap<String, String> {
+    files
+        .iter()
+        .map(|(service, contents)| (service.to_string(), contents.to_string()))
+        .collect()
+}
+
+/// Write the files out to a fresh directory under the target directory.
+fn write_dir(name: &str, files: &BTreeMap<String, String>) -> PathBuf {
+    let dir = PathBuf::from(env!("CARGO_TARGET_TMPDIR")).join(name);
+    if dir.exists() {
+        std::fs::remove_dir_all(&dir).expect("removed the old directory");
+    }
+    pam_explainer::pamconf::write_pam_d(&dir, files).expect("wrote the directory");
+    dir
+}
+
+const PAM_CONF: &str = "\
+# the login service
+login   auth     required   pam_unix.so nullok
+login   account  required   pam_unix.so
+
+# everything else
+other   auth     required   pam_deny.so
+other   account  required   pam_deny.so
+";
+
+#[test]
+fn pam_conf_round_trips_through_pam_d() {
+    let files = to_pam_d(PAM_CONF);
+    assert_eq!(
+        files.keys().collect::<Vec<_>>(),
+        ["login", "other"],
+        "one file per service"
+    );
+    assert_eq!(
+        files["login"],
+        "# the login service\nauth     required   pam_unix.so nullok\naccount  required   pam_unix.so\n"
+    );
+
+    let (before, _) = parse_pam_conf(PAM_CONF, None, &[]);
+    let (after, diagnostics) = parse_pam_conf(&from_pam_d(&files).expect("no includes"), None, &[]);
+    assert!(diagnostics.is_empty(), "{:?}", diagnostics);
+    assert_eq!(
+        before.keys().collect::<Vec<_>>(),
+        after.keys().collect::<Vec<_>>()
+    );
+    for (service, rules) in before.iter() {
+        assert_eq!(lines(rules), lines(&after[service]), "{}", service);
+    }
+}
+
+#[test]
+fn includes_are_inlined_for_their_facility() {
+    let files = pam_d(&[
+        (
+            "login",
+            "@include common-auth\naccount include common-account\nsession required pam_limits.so\n",
+        ),
+        (
+            "common-auth",
+            "auth required pam_unix.so\naccount required pam_deny.so\n",
+        ),
+        (
+            "common-account",
+            "auth required pam_deny.so\naccount required pam_unix.so\n",
+        ),
+    ]);
+    let conf = from_pam_d(&files).expect("the includes are all there");
+    let (services, diagnostics) = parse_pam_conf(&conf, None, &[]);
+    assert!(diagnostics.is_empty(), "{:?}", diagnostics);
+    assert_eq!(
+        services.keys().collect::<Vec<_>>(),
+        ["login"],
+        "included files aren't services: {}",
+        conf
+    );
+
+    // the same rules libpam loads from the directory
+    let dir = write_dir("pamconf-includes", &files);
+    let (resolved, _) =
+        resolve_includes(&files["login"], None, &dir, &[]).expect("the includes resolve");
+    assert_eq!(lines(&services["login"]), lines(&resolved));
+    assert_eq!(
+        lines(&resolved),
+        [
+            "auth required pam_unix.so",
+            "account required pam_deny.so",
+            "account required pam_unix.so",
+            "session required pam_limits.so",
+        ]
+    );
+}
+
+#[test]
+fn substacks_are_left_alone() {
+    let files = pam_d(&[
+        ("sshd", "auth substack password-auth\n"),
+        ("password-auth", "auth required pam_unix.so\n"),
+    ]);
+    let conf = from_pam_d(&files).expect("nothing to inline");
+    let (services, _) = parse_pam_conf(&conf, None, &[]);
+    assert_eq!(
+        lines(&services["sshd"]),
+        ["auth substack password-auth"],
+        "{}",
+        conf
+    );
+}
+
+#[test]
+fn include_loops_and_missing_files_are_errors() {
+    let looped = pam_d(&[
+        ("login", "@include common-auth\n"),
+        ("common-auth", "@include common-password\n"),
+        ("common-password", "auth include common-auth\n"),
+        ("other", "auth include common-auth\n"),
+    ]);
+    assert!(matches!(
+        from_pam_d(&looped),
+        Err(LoadError::IncludeLoop { chain }) if chain.first().map(String::as_str) == Some("login")
+    ));
+
+    let missing = pam_d(&[("login", "@include common-auth\n")]);
+    assert!(matches!(
+        from_pam_d(&missing),
+        Err(LoadError::IncludeNotFound { target }) if target == "common-auth"
+    ));
+}
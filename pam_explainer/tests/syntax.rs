@@ -0,0 +1,251 @@
+//! The lossless syntax tree: files come back out byte for byte, and are split up the way libpam reads them.
+
+use pam_explainer::syntax::{EntryKind, SyntaxFile, TokenKind};
+
+fn assert_round_trips(text: &str) {
+    assert_eq!(SyntaxFile::parse(text).to_string(), text);
+}
+
+#[test]
+fn round_trip_crlf() {
+    assert_round_trips("auth required pam_unix.so\r\naccount required pam_unix.so\r\n");
+    assert_round_trips("# comment\r\n\r\nauth required pam_unix.so\r\n");
+    let file = SyntaxFile::parse("auth required pam_unix.so\r\n");
+    assert_eq!(file.entries[0].ending, "\r\n");
+    assert_eq!(file.entries[0].text(), "auth required pam_unix.so");
+}
+
+#[test]
+fn round_trip_without_a_final_newline() {
+    assert_round_trips("auth required pam_unix.so");
+    assert_round_trips("auth required pam_unix.so\naccount required pam_unix.so");
+    assert_round_trips("auth required pam_unix.so\n# the end");
+    let file = SyntaxFile::parse("auth required pam_unix.so");
+    assert_eq!(file.entries.len(), 1);
+    assert_eq!(file.entries[0].ending, "");
+}
+
+#[test]
+fn round_trip_tab_alignment() {
+    assert_round_trips(
+        "auth\t\trequired\tpam_unix.so\tnullok\naccount\t\t[default=bad success=ok]\tpam_unix.so\n",
+    );
+    assert_round_trips("\tauth   \t required pam_unix.so \t\n");
+}
+
+#[test]
+fn round_trip_comment_only() {
+    assert_round_trips("# nothing here\n");
+    assert_round_trips("# nothing here\n#\n\n   # indented\n");
+    assert_round_trips("");
+    assert_round_trips("\n\n");
+    let file = SyntaxFile::parse("# nothing here\n\n");
+    assert_eq!(
+        file.entries
+            .iter()
+            .map(|entry| entry.kind)
+            .collect::<Vec<_>>(),
+        [EntryKind::Comment, EntryKind::Blank]
+    );
+    let (rules, diagnostics) = file.rules(None, &[]);
+    assert!(rules.is_empty());
+    assert!(diagnostics.is_empty());
+}
+
+#[test]
+fn round_trip_continuations_and_includes() {
+    assert_round_trips("auth required \\\n    pam_unix.so \\\n    nullok\r\n");
+    assert_round_trips("auth required \\\n    pam_unix.so \\\r\n    nullok\r\n");
+    assert_round_trips("@include common-auth\n@include\tcommon-account # trailing\n");
+    assert_round_trips("auth [success=1 default=ignore] pam_unix.so\nauth requisite pam_deny.so\n");
+}
+
+#[test]
+fn edits_to_tokens_are_written_out() {
+    let mut file = SyntaxFile::parse("auth\trequired\tpam_unix.so # keep\n");
+    for token in file.entries[0].tokens.iter_mut() {
+        if token.kind == TokenKind::Control {
+            token.text = "requisite".to_string();
+        }
+    }
+    assert_eq!(file.to_string(), "auth\trequisite\tpam_unix.so # keep\n");
+}
+
+/// The tokens of the first entry, other than whitespace.
+fn tokens(text: &str) -> Vec<(TokenKind, String)> {
+    SyntaxFile::parse(text).entries[0]
+        .tokens
+        .iter()
+        .filter(|token| token.kind != TokenKind::Whitespace)
+        .map(|token| (token.kind, token.text.clone()))
+        .collect()
+}
+
+fn token(kind: TokenKind, text: &str) -> (TokenKind, String) {
+    (kind, text.to_string())
+}
+
+#[test]
+fn continuation_needs_the_backslash_straight_before_the_newline() {
+    let text = "auth required pam_unix.so \\\n  nullok\n";
+    let file = SyntaxFile::parse(text);
+    assert_eq!(file.entries.len(), 1);
+    assert_eq!(
+        tokens(text),
+        [
+            token(TokenKind::Facility, "auth"),
+            token(TokenKind::Control, "required"),
+            token(TokenKind::Module, "pam_unix.so"),
+            token(TokenKind::Continuation, "\\\n"),
+            token(TokenKind::Argument, "nullok"),
+        ]
+    );
+    let (rules, diagnostics) = file.rules(None, &[]);
+    assert!(diagnostics.is_empty(), "{:?}", diagnostics);
+    assert_eq!(
+        rules[0].to_config_line(),
+        "auth required pam_unix.so nullok"
+    );
+}
+
+#[test]
+fn backslash_before_trailing_whitespace_isnt_a_continuation() {
+    for text in [
+        "auth required pam_unix.so \\ \nauth required pam_deny.so\n",
+        "auth required pam_unix.so \\\t\nauth required pam_deny.so\n",
+        "auth required pam_unix.so \\\r\nauth required pam_deny.so\r\n",
+    ] {
+        let file = SyntaxFile::parse(text);
+        assert_eq!(file.to_string(), text);
+        assert_eq!(file.entries.len(), 2, "{:?}", text);
+        assert_eq!(
+            file.entries[1]
+                .field(TokenKind::Module)
+                .map(|token| token.text.as_str()),
+            Some("pam_deny.so")
+        );
+        let (rules, _) = file.rules(None, &[]);
+        assert_eq!(
+            rules.last().map(|rule| rule.to_config_line()).as_deref(),
+            Some("auth required pam_deny.so")
+        );
+    }
+}
+
+#[test]
+fn continuation_skips_comment_and_blank_lines() {
+    let text =
+        "auth required \\\n# why nullok\n\n   pam_unix.so nullok\naccount required pam_unix.so\n";
+    let file = SyntaxFile::parse(text);
+    assert_eq!(file.entries.len(), 2);
+    assert_eq!(file.entries[0].kind, EntryKind::Rule);
+    assert_eq!(
+        tokens(text),
+        [
+            token(TokenKind::Facility, "auth"),
+            token(TokenKind::Control, "required"),
+            token(TokenKind::Continuation, "\\\n"),
+            token(TokenKind::Comment, "# why nullok"),
+            token(TokenKind::Module, "pam_unix.so"),
+            token(TokenKind::Argument, "nullok"),
+        ]
+    );
+    // the next rule still knows which line it's on
+    assert_eq!(file.entries[1].line, 5);
+    let (rules, _) = file.rules(None, &[]);
+    assert_eq!(
+        rules[0].to_config_line(),
+        "auth required pam_unix.so nullok"
+    );
+    assert_eq!(rules[1].source.line, 5);
+}
+
+#[test]
+fn hash_in_the_middle_of_a_field_starts_a_comment() {
+    let text = "auth required pam_unix.so foo#bar nullok\n";
+    assert_eq!(
+        tokens(text),
+        [
+            token(TokenKind::Facility, "auth"),
+            token(TokenKind::Control, "required"),
+            token(TokenKind::Module, "pam_unix.so"),
+            token(TokenKind::Argument, "foo"),
+            token(TokenKind::Comment, "#bar nullok"),
+        ]
+    );
+    let (rules, _) = SyntaxFile::parse(text).rules(None, &[]);
+    assert_eq!(rules[0].arguments, ["foo"]);
+    // a comment straight after a field is one too
+    assert_eq!(
+        tokens("auth required pam_unix.so\t#comment\n").last(),
+        Some(&token(TokenKind::Comment, "#comment"))
+    );
+    // and so is one in the middle of brackets, which leaves them unterminated
+    assert_eq!(
+        tokens("auth [success=ok#x default=bad] pam_unix.so\n"),
+        [
+            token(TokenKind::Facility, "auth"),
+            token(TokenKind::Control, "[success=ok"),
+            token(TokenKind::Comment, "#x default=bad] pam_unix.so"),
+        ]
+    );
+    // a backslash after the hash is part of the comment, not a continuation
+    let file = SyntaxFile::parse("auth required pam_unix.so # why \\\nauth required pam_deny.so\n");
+    assert_eq!(file.entries.len(), 2);
+}
+
+#[test]
+fn brackets_with_an_escaped_closing_bracket() {
+    let text = "auth required pam_mysql.so [query=select \\] from x] debug\n";
+    assert_eq!(
+        tokens(text),
+        [
+            token(TokenKind::Facility, "auth"),
+            token(TokenKind::Control, "required"),
+            token(TokenKind::Module, "pam_mysql.so"),
+            token(TokenKind::Argument, "[query=select \\] from x]"),
+            token(TokenKind::Argument, "debug"),
+        ]
+    );
+    let (rules, _) = SyntaxFile::parse(text).rules(None, &[]);
+    assert_eq!(rules[0].arguments, ["query=select ] from x", "debug"]);
+}
+
+#[test]
+fn unterminated_bracket_takes_the_rest_of_the_line() {
+    let text = "auth [success=ok default=bad pam_unix.so nullok\naccount required pam_unix.so\n";
+    let file = SyntaxFile::parse(text);
+    assert_eq!(file.entries.len(), 2);
+    assert_eq!(
+        tokens(text),
+        [
+            token(TokenKind::Facility, "auth"),
+            token(
+                TokenKind::Control,
+                "[success=ok default=bad pam_unix.so nullok"
+            ),
+        ]
+    );
+    let (rules, diagnostics) = file.rules(None, &[]);
+    assert!(!diagnostics.is_empty());
+    assert_eq!(
+        rules.last().map(|rule| rule.to_config_line()).as_deref(),
+        Some("account required pam_unix.so")
+    );
+}
+
+#[test]
+fn file_ending_mid_continuation() {
+    for text in [
+        "auth required pam_unix.so \\\n",
+        "auth required pam_unix.so \\",
+        "auth required pam_unix.so \\\n# trailing comment\n",
+    ] {
+        let file = SyntaxFile::parse(text);
+        assert_eq!(file.to_string(), text);
+        assert_eq!(file.entries.len(), 1, "{:?}", text);
+        let (rules, diagnostics) = file.rules(None, &[]);
+        assert!(diagnostics.is_empty(), "{:?}: {:?}", text, diagnostics);
+        assert_eq!(rules[0].to_config_line(), "auth required pam_unix.so");
+    }
+}
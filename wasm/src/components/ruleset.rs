@@ -1,19 +1,23 @@
 use crate::prelude::*;
-use pam_explainer::{Facility, FinalResult, RuleSet as pam_ruleset};
+use enum_iterator::all;
+use pam_explainer::modules::module_info;
+use pam_explainer::paths::MAX_PATHS;
+use pam_explainer::{Facility, ReturnCode, RuleSet as pam_ruleset};
+use std::str::FromStr;
 use wasm_bindgen::JsCast;
-use web_sys::{Event, HtmlInputElement};
+use web_sys::{Event, HtmlSelectElement};
 
 #[derive(Properties, PartialEq)]
 pub struct RuleSetProps {
     pub ruleset: pam_ruleset,
-    pub rulecallback: Callback<(String, bool)>,
+    pub rulecallback: Callback<(String, ReturnCode)>,
 }
 
 #[allow(dead_code)]
 pub enum RuleSetMessage {
     RuleUpdate {
         rulehash: String,
-        final_result: bool,
+        final_result: ReturnCode,
     },
     Nothing,
 }
@@ -41,51 +45,87 @@ impl Component for RuleSet {
     fn view(&self, ctx: &yew::Context<Self>) -> yew::Html {
         let mut ruleset = self.0.clone();
 
-        let ruleset_final_result = if let Facility::Invalid(_) = ruleset.facility {
-            "".to_string()
-        } else {
-            format!(
-                "Final result: {:?} (Ran {} rules)",
-                ruleset.run_rules(),
-                ruleset.rules_run
-            )
+        let trace = match ruleset.facility {
+            Facility::Invalid(_) => None,
+            _ => Some(ruleset.run_rules()),
+        };
+        let ruleset_final_result = match &trace {
+            Some(trace) => format!(
+                "Final result: {} (Ran {} rules)",
+                trace.result,
+                trace.rules_run()
+            ),
+            None => "".to_string(),
         };
 
         let rules_html = ruleset
             .rules
+            .clone()
             .into_iter()
-            .map(|rule| {
+            .enumerate()
+            .map(|(index, rule)| {
                 let final_result_string: String = rule.result_string();
-                let checked = match rule.final_result {
-                    Some(value) => match value {
-                        FinalResult::Success => true,
-                        FinalResult::Failure => false,
-                    },
-                    None => false,
+                let (step_string, step_class) = match trace.as_ref().and_then(|trace| trace.step(index)) {
+                    Some(step) => (
+                        format!("{} Stack is {}.", step.explain(&ruleset.rules), step.state),
+                        if step.was_run() { "" } else { "ruleSkipped" },
+                    ),
+                    None => ("".to_string(), ""),
                 };
+                let outcome_options = all::<ReturnCode>()
+                    .map(|code| {
+                        html! {
+                            <option value={code.to_string()} selected={rule.final_result == Some(code)}>{code.to_string()}</option>
+                        }
+                    })
+                    .collect::<Html>();
                 html! {
-                    <tr>
+                    <tr class={step_class}>
                         <td>{rule.rule_order.unwrap_or(0).to_string()}</td>
-                        { if let Facility::Invalid(value) = rule.facility {
-                            html!{<th>{value}</th>}
+                        { if let Facility::Invalid(value) = &rule.facility {
+                            html!{<th>{value.clone()}</th>}
                         } else {
                             html!{<></>}
                         }}
 
                         <td>{rule.control.to_string()}</td>
-                        <td>{rule.module.clone()}</td>
-                        <td>{rule.arguments.join(" ")}</td>
-                        <td><input type="checkbox"
+                        { match module_info(&rule.module) {
+                            Some(info) => html!{
+                                <td title={info.description.clone()}>
+                                    {rule.module.clone()}
+                                    <br/><small class="moduleInfo">{info.description.clone()}</small>
+                                    { if info.supports(&rule.facility) || matches!(rule.facility, Facility::Invalid(_)) {
+                                        html!{<></>}
+                                    } else {
+                                        html!{<><br/><small class="moduleInfo">{format!("It doesn't implement {}, so it counts as a failure.", rule.facility)}</small></>}
+                                    }}
+                                </td>
+                            },
+                            None => html!{<td>{rule.module.clone()}</td>},
+                        }}
+                        <td>{ rule.arguments.iter().map(|argument| {
+                            let title = module_info(&rule.module)
+                                .and_then(|info| info.argument(argument))
+                                .map(|known| known.description.clone());
+                            html!{<><span title={title}>{pam_explainer::quote_argument(argument)}</span>{" "}</>}
+                        }).collect::<Html>() }</td>
+                        <td><select
                             id={rule.rulehash.clone().unwrap_or("foo".to_string())}
-                            checked={checked}
                             onchange={ctx.link().callback(move |event: Event| {
                             if let Some(event) = event.target(){
                                 if let Some(rulehash) = rule.rulehash.as_ref() {
 
-                                    let input = event.dyn_into::<HtmlInputElement>().expect("Failed to cast event target to HtmlInputElement");
-                                    let checked = input.checked();
-                                    debug!("Sending rule update", rule.rulehash.clone(), checked);
-                                    RuleSetMessage::RuleUpdate{rulehash: rulehash.clone(), final_result: checked}
+                                    let input = event.dyn_into::<HtmlSelectElement>().expect("Failed to cast event target to HtmlSelectElement");
+                                    match ReturnCode::from_str(&input.value()) {
+                                        Ok(final_result) => {
+                                            debug!("Sending rule update", rule.rulehash.clone(), final_result.to_string());
+                                            RuleSetMessage::RuleUpdate{rulehash: rulehash.clone(), final_result}
+                                        }
+                                        Err(err) => {
+                                            debug!("Failed to parse selected result", err.to_string());
+                                            RuleSetMessage::Nothing
+                                        }
+                                    }
                                 } else {
                                     debug !("No rulehash found for rule, cannot send update");
                                     RuleSetMessage::Nothing
@@ -93,13 +133,107 @@ impl Component for RuleSet {
                             } else {
                                 RuleSetMessage::Nothing
                             }
-                        })}/></td>
+                        })}>{outcome_options}</select></td>
                         <td>{final_result_string}</td>
+                        <td>{step_string}</td>
                     </tr>
                 }
             })
             .collect::<Html>();
 
+        let truth_table_html = match self.0.facility {
+            Facility::Invalid(_) => html! {<></>},
+            _ => {
+                let table = self.0.truth_table(MAX_PATHS);
+                let headers = table
+                    .columns
+                    .iter()
+                    .map(|index| {
+                        let rule = &table.rules[*index];
+                        html! {
+                            <th title={rule.module.clone()}>{format!("#{}", rule.rule_order.unwrap_or(0))}</th>
+                        }
+                    })
+                    .collect::<Html>();
+                let rows = table
+                    .rows
+                    .iter()
+                    .map(|row| {
+                        let cells = row
+                            .outcomes
+                            .iter()
+                            .map(|code| match code {
+                                Some(code) => html! {<td>{code.to_string()}</td>},
+                                None => html! {<td class="ruleSkipped">{"-"}</td>},
+                            })
+                            .collect::<Html>();
+                        html! {
+                            <tr>{cells}<td>{row.result.to_string()}</td><td>{row.rules_run.to_string()}</td></tr>
+                        }
+                    })
+                    .collect::<Html>();
+                html! {
+                    <details class="facilityDetails">
+                    <summary>{"Truth table"}</summary>
+                    <table id="data">
+                    <thead>{headers}<th>{"Result"}</th><th>{"Rules run"}</th></thead>
+                    <tbody>{rows}</tbody>
+                    </table>
+                    { if table.complete {
+                        html!{<></>}
+                    } else {
+                        html!{<p>{format!("The stack has more than {} ways through it, the table is incomplete.", MAX_PATHS)}</p>}
+                    }}
+                    </details>
+                }
+            }
+        };
+
+        let criticality_html = match self.0.facility {
+            Facility::Invalid(_) => html! {<></>},
+            _ => {
+                let report = self.0.criticality();
+                let describe = |index: &usize| {
+                    let rule = &report.rules[*index];
+                    format!("#{} {}", rule.rule_order.unwrap_or(0), rule.module)
+                };
+                let list = |rules: &[usize]| match rules.is_empty() {
+                    true => "none".to_string(),
+                    false => rules
+                        .iter()
+                        .map(describe)
+                        .collect::<Vec<String>>()
+                        .join(", "),
+                };
+                let sufficient = report
+                    .sufficient
+                    .iter()
+                    .map(|set| match set.is_empty() {
+                        true => {
+                            html! {<li>{"nothing, it succeeds whatever the modules return"}</li>}
+                        }
+                        false => html! {<li>{list(set)}</li>},
+                    })
+                    .collect::<Html>();
+                html! {
+                    <details class="facilityDetails">
+                    <summary>{"Which modules matter"}</summary>
+                    { if report.can_succeed {
+                        html! {
+                            <ul>
+                            <li>{format!("Must succeed: {}", list(&report.must_succeed))}</li>
+                            <li>{format!("Never change the result: {}", list(&report.never_matters))}</li>
+                            <li>{"Enough to succeed on their own:"}<ul>{sufficient}</ul></li>
+                            </ul>
+                        }
+                    } else {
+                        html! {<p>{"Can't succeed, whatever the modules return."}</p>}
+                    }}
+                    </details>
+                }
+            }
+        };
+
         html! {
 
         <div><h2 class="facilityTitle">{self.0.facility.to_string()}</h2>
@@ -114,14 +248,17 @@ impl Component for RuleSet {
         <th>{"Control"}</th>
         <th>{"Module"}</th>
         <th>{"Arguments"}</th>
-        <th>{"Success?"}</th>
+        <th>{"Result"}</th>
         <th>{"Explanation"}</th>
+        <th>{"What happened"}</th>
         </thead>
         <tbody>
         {rules_html}
         </tbody>
         </table>
         {ruleset_final_result}
+        {truth_table_html}
+        {criticality_html}
         </div>}
     }
 
@@ -1,6 +1,6 @@
 use std::collections::HashMap;
 
-use pam_explainer::{Facility, FinalResult, RuleSets};
+use pam_explainer::{Facility, ParseError, ReturnCode, RuleSets};
 #[allow(unused_imports)]
 use wasm_bindgen::prelude::*;
 
@@ -20,6 +20,7 @@ struct PamSplainer {
     config_ref: NodeRef,
     config: AttrValue,
     rulesets: RuleSets,
+    diagnostics: Vec<ParseError>,
 }
 
 enum PamSplainerMessage {
@@ -27,7 +28,7 @@ enum PamSplainerMessage {
     View,
     RuleUpdate {
         rulehash: String,
-        final_result: bool,
+        final_result: ReturnCode,
     },
 }
 
@@ -40,6 +41,7 @@ impl Component for PamSplainer {
             config_ref: NodeRef::default(),
             config: String::new().into(),
             rulesets: HashMap::new(),
+            diagnostics: Vec::new(),
         }
     }
 
@@ -52,7 +54,7 @@ impl Component for PamSplainer {
             <div>
                 <h1>{"PAM-Splainer"}</h1>
                 <p>{"This runs completely in the browser, allowing you to figure out how your PAM config rules get run."}</p>
-                <p>{"Check the boxes next to the rules to specify which ones succeeded/failed, and it'll show the outcome."}</p>
+                <p>{"Pick the result each module returned next to the rules, and it'll show the outcome."}</p>
             </div>
             <div id="inputData" class="bodyDivs configBackground">
             <h2>{"Paste your config here"}</h2>
@@ -71,6 +73,24 @@ impl Component for PamSplainer {
                     }
                 })}
                 />
+            { if self.diagnostics.is_empty() {
+                html!{<></>}
+            } else {
+                html!{
+                    <ul class="diagnostics">
+                    { self.diagnostics.iter().map(|diagnostic| html!{
+                        <li>
+                            <span class="diagnosticLocation">{format!("Line {}, column {}: ", diagnostic.span().line, diagnostic.span().columns.start + 1)}</span>
+                            {diagnostic.message()}
+                            { match diagnostic.suggestion() {
+                                Some(suggestion) => html!{<span class="diagnosticSuggestion">{format!(" ({})", suggestion)}</span>},
+                                None => html!{<></>},
+                            }}
+                        </li>
+                    }).collect::<Html>() }
+                    </ul>
+                }
+            }}
             </div>
             <div class="bodyDivs resultsBackground">
                 {
@@ -100,10 +120,8 @@ impl Component for PamSplainer {
                     // config changed
                     info!("{}", "config - changed".to_string());
                     self.config = value.clone();
-                    self.rulesets = pam_explainer::rulesets_from_string(
-                        value.to_string(),
-                        FinalResult::Success,
-                    );
+                    (self.rulesets, self.diagnostics) =
+                        pam_explainer::rulesets_from_string(value.to_string(), ReturnCode::Success);
                     true
                 } else {
                     info!("{}", "config - no change".to_string());
@@ -118,13 +136,17 @@ impl Component for PamSplainer {
                 rulehash,
                 final_result,
             } => {
-                info!("PamSplainer Update ", rulehash.clone(), final_result);
+                info!(
+                    "PamSplainer Update ",
+                    rulehash.clone(),
+                    final_result.to_string()
+                );
                 let mut rulesets = self.rulesets.clone();
                 for (_facility, ruleset) in rulesets.iter_mut() {
                     for rule in ruleset.rules.iter_mut() {
                         if rule.rulehash.clone() == Some(rulehash.clone()) {
                             info!("PamSplainer Updating rule: ", rulehash.clone());
-                            rule.final_result = Some(final_result.into());
+                            rule.final_result = Some(final_result);
                         }
                     }
                 }